@@ -0,0 +1,146 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use ratatui::style::{Color, Modifier, Style};
+
+use crate::color;
+
+/// Splits `text` into runs of printable text and the style set for them by
+/// SGR escape sequences.
+///
+/// Parsing starts from `style` and leaves in it the style in effect at the
+/// end of the text, so that it carries over to the text that follows. Other
+/// escape sequences, such as cursor movements, are dropped.
+pub fn parse(text: &str, style: &mut Style) -> Vec<(String, Style)> {
+    let mut runs = Vec::new();
+    let mut run = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            run.push(c);
+            continue;
+        }
+        if !run.is_empty() {
+            runs.push((std::mem::take(&mut run), *style));
+        }
+        match chars.next() {
+            // Control sequence: parameters up to a final byte.
+            Some('[') => {
+                let mut params = String::new();
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        if c == 'm' {
+                            apply_sgr(&params, style);
+                        }
+                        break;
+                    }
+                    params.push(c);
+                }
+            }
+            // Operating system command, such as a window title or hyperlink,
+            // ended by BEL or ST.
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if !run.is_empty() {
+        runs.push((run, *style));
+    }
+    runs
+}
+
+/// Removes every escape sequence from `text`.
+pub fn strip(text: &str) -> String {
+    parse(text, &mut Style::default())
+        .into_iter()
+        .map(|(run, _)| run)
+        .collect()
+}
+
+/// Updates `style` with the `;` separated parameters of an SGR sequence.
+fn apply_sgr(params: &str, style: &mut Style) {
+    let mut codes = params
+        .split([';', ':'])
+        .map(|code| code.parse::<u16>().unwrap_or(0));
+    while let Some(code) = codes.next() {
+        match code {
+            0 => *style = Style::default(),
+            1 => set(style, Modifier::BOLD, true),
+            2 => set(style, Modifier::DIM, true),
+            3 => set(style, Modifier::ITALIC, true),
+            4 => set(style, Modifier::UNDERLINED, true),
+            5 => set(style, Modifier::SLOW_BLINK, true),
+            6 => set(style, Modifier::RAPID_BLINK, true),
+            7 => set(style, Modifier::REVERSED, true),
+            8 => set(style, Modifier::HIDDEN, true),
+            9 => set(style, Modifier::CROSSED_OUT, true),
+            22 => set(style, Modifier::BOLD | Modifier::DIM, false),
+            23 => set(style, Modifier::ITALIC, false),
+            24 => set(style, Modifier::UNDERLINED, false),
+            25 => set(style, Modifier::SLOW_BLINK | Modifier::RAPID_BLINK, false),
+            27 => set(style, Modifier::REVERSED, false),
+            28 => set(style, Modifier::HIDDEN, false),
+            29 => set(style, Modifier::CROSSED_OUT, false),
+            30..=37 => style.fg = Some(base_color(code - 30, false)),
+            38 => style.fg = extended_color(&mut codes),
+            39 => style.fg = None,
+            40..=47 => style.bg = Some(base_color(code - 40, false)),
+            48 => style.bg = extended_color(&mut codes),
+            49 => style.bg = None,
+            90..=97 => style.fg = Some(base_color(code - 90, true)),
+            100..=107 => style.bg = Some(base_color(code - 100, true)),
+            _ => {}
+        }
+    }
+}
+
+/// Adds or removes `modifier`. Removed modifiers fall back to the entry style
+/// rather than being forced off.
+fn set(style: &mut Style, modifier: Modifier, on: bool) {
+    if on {
+        style.add_modifier.insert(modifier);
+    } else {
+        style.add_modifier.remove(modifier);
+    }
+}
+
+/// One of the eight standard colors, or its bright variant.
+fn base_color(idx: u16, bright: bool) -> Color {
+    match (idx, bright) {
+        (0, false) => Color::Black,
+        (1, false) => Color::Red,
+        (2, false) => Color::Green,
+        (3, false) => Color::Yellow,
+        (4, false) => Color::Blue,
+        (5, false) => Color::Magenta,
+        (6, false) => Color::Cyan,
+        (7, false) => Color::Gray,
+        (0, true) => Color::DarkGray,
+        (1, true) => Color::LightRed,
+        (2, true) => Color::LightGreen,
+        (3, true) => Color::LightYellow,
+        (4, true) => Color::LightBlue,
+        (5, true) => Color::LightMagenta,
+        (6, true) => Color::LightCyan,
+        _ => Color::White,
+    }
+}
+
+/// Reads a `5;N` indexed or `2;R;G;B` true color following a 38 or 48 code,
+/// as the terminal can show it.
+fn extended_color(codes: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    let mut next = || codes.next().map(|code| code.min(255) as u8);
+    let color = match next()? {
+        5 => Color::Indexed(next()?),
+        2 => Color::Rgb(next()?, next()?, next()?),
+        _ => return None,
+    };
+    Some(color::downgrade(color))
+}
@@ -2,44 +2,268 @@
 //
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::config::{self, CommandType, LoopConfig, RemoteConfig, StyleConfig, SudoConfig};
-use crate::session::CommandSession;
-use anyhow::Result;
-use crossterm::event::{KeyCode, KeyEvent};
+use crate::config::{
+    self, ActionConfig, BorderStyle, CommandConfig, CommandType, OnError, RemoteConfig, Remotes,
+    Reveal, StyleConfig, SudoConfig, TransferConfig, WaitForConfig, When, PASSWORD_PROMPT,
+};
+use crate::{
+    ansi,
+    cast::Cast,
+    clipboard::{self, Target},
+    color::styled,
+    diff::{self, DiffLine},
+    keymap::{KeyAction, Keymap},
+    links, redact,
+    session::{AuthQuestion, CommandSession, Direction, FileTransfer, OutputStream, SessionCache},
+    summary::{CommandRecord, Summary},
+    transcript::Transcript,
+};
+use anyhow::{bail, Result};
+use crossterm::event::{KeyCode, KeyEvent, MouseEvent, MouseEventKind};
 use ratatui::{
+    layout::Alignment,
     style::{Color, Style, Styled},
     text::{Line, Span},
+    widgets::ListState,
 };
 use std::{
+    cell::Cell,
+    collections::HashMap,
     error,
-    sync::{Arc, Mutex},
-    thread,
-    time::Duration,
+    io::{self, Write},
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
+use tracing::{debug, info, warn};
+
+/// Lines scrolled by a notch of the mouse wheel.
+const WHEEL_LINES: u16 = 3;
+
+/// Shortest time a frame of the spinner is shown, so that it does not whirl
+/// on short ticks.
+const SPINNER_STEP: Duration = Duration::from_millis(40);
+
+/// Frames of the spinner shown while an action is running, one per tick and
+/// [`SPINNER_STEP`] at most.
+const SPINNER: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// Written in place of the output of what a dry run does not execute.
+const DRY_RUN_MARKER: &str = "[dry-run, not executed]\n";
+
+/// How long quitting waits for the background work of the running action to
+/// stop.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a toast replaces the status in the instructions bar.
+const TOAST_DURATION: Duration = Duration::from_secs(2);
 
 /// Application result type.
 pub type AppResult<T> = Result<T, Box<dyn error::Error>>;
 
+/// A run of text sharing the same style within a [`BufferedOutput`].
 #[derive(Clone, Debug)]
-pub struct BufferedOutput {
+struct Segment {
     text: String,
+    /// Style patched over the entry style, if any.
+    style: Option<StyleConfig>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BufferedOutput {
+    segments: Vec<Segment>,
     style: StyleConfig,
+    alignment: Alignment,
+    /// Stage and action indices of the command that produced this entry.
+    origin: Option<(usize, usize)>,
+    /// Whether SGR escape sequences in the text style it, rather than
+    /// being stripped.
+    ansi: bool,
+    /// Lines kept after the first one, the oldest being dropped beyond.
+    max_lines: Option<usize>,
+    /// Lines dropped so far to stay within `max_lines`.
+    trimmed: usize,
+    /// Whether the URLs of the text are highlighted, and can be opened.
+    links: bool,
+    /// Rendered lines, until the text changes.
+    lines: Option<Vec<Line<'static>>>,
 }
 
-impl<'a> BufferedOutput {
-    pub fn into_lines(self) -> Vec<Line<'a>> {
-        self.text
-            .clone()
-            .lines()
-            .map(|l| Line::from(l.to_owned()).set_style(Into::<Style>::into(self.style.clone())))
+impl BufferedOutput {
+    fn new(text: String, style: StyleConfig, alignment: Alignment) -> Self {
+        Self {
+            segments: vec![Segment { text, style: None }],
+            style,
+            alignment,
+            origin: None,
+            ansi: true,
+            max_lines: None,
+            trimmed: 0,
+            links: false,
+            lines: None,
+        }
+    }
+
+    /// Appends text in the entry style.
+    fn push_str(&mut self, text: &str) {
+        match self.segments.last_mut() {
+            Some(segment) if segment.style.is_none() => segment.text.push_str(text),
+            _ => self.segments.push(Segment {
+                text: text.to_owned(),
+                style: None,
+            }),
+        }
+        self.trim();
+        self.lines = None;
+    }
+
+    /// Drops the oldest lines after the first one, the command prompt, once
+    /// there are more than `max_lines` of them.
+    fn trim(&mut self) {
+        let Some(max_lines) = self.max_lines else {
+            return;
+        };
+        let lines: usize = self
+            .segments
+            .iter()
+            .map(|segment| segment.text.matches('\n').count())
+            .sum();
+        let mut excess = lines.saturating_sub(max_lines.saturating_add(1));
+        if excess == 0 {
+            return;
+        }
+        self.trimmed += excess;
+        let mut past_first_line = false;
+        for segment in &mut self.segments {
+            let start = if past_first_line {
+                0
+            } else {
+                match segment.text.find('\n') {
+                    Some(idx) => idx + 1,
+                    None => continue,
+                }
+            };
+            past_first_line = true;
+            let mut end = segment.text.len();
+            for (idx, _) in segment.text[start..].match_indices('\n') {
+                excess -= 1;
+                if excess == 0 {
+                    end = start + idx + 1;
+                    break;
+                }
+            }
+            segment.text.replace_range(start..end, "");
+            if excess == 0 {
+                break;
+            }
+        }
+        self.segments.retain(|segment| !segment.text.is_empty());
+    }
+
+    /// Whether the text ends with a complete line.
+    fn ends_line(&self) -> bool {
+        self.segments
+            .iter()
+            .rev()
+            .find(|segment| !segment.text.is_empty())
+            .is_none_or(|segment| segment.text.ends_with('\n'))
+    }
+
+    /// Appends text with its own style patched over the entry style.
+    fn push_styled(&mut self, text: &str, style: StyleConfig) {
+        self.segments.push(Segment {
+            text: text.to_owned(),
+            style: Some(style),
+        });
+        self.trim();
+        self.lines = None;
+    }
+
+    /// The text of the entry, without styling.
+    fn plain_text(&self) -> String {
+        let mut text: String = self.segments.iter().map(|s| s.text.as_str()).collect();
+        if self.trimmed > 0 {
+            let at = text.find('\n').map_or(text.len(), |idx| idx + 1);
+            text.insert_str(at, &format!("… {} lines trimmed …\n", self.trimmed));
+        }
+        if self.ansi {
+            text
+        } else {
+            ansi::strip(&text)
+        }
+    }
+
+    pub fn has_links(&self) -> bool {
+        self.links
+    }
+
+    /// The rendered lines of the entry, only computed again once its text
+    /// changed.
+    pub fn lines(&mut self) -> &[Line<'static>] {
+        if self.lines.is_none() {
+            self.lines = Some(self.render_lines());
+        }
+        self.lines.as_deref().unwrap_or_default()
+    }
+
+    fn render_lines(&self) -> Vec<Line<'static>> {
+        let mut lines = vec![Line::default()];
+        // Styles set by escape sequences carry over segments and lines.
+        let mut escaped = Style::default();
+        for segment in &self.segments {
+            let style = segment.style.clone().map(Style::from).unwrap_or_default();
+            for (run, run_style) in ansi::parse(&segment.text, &mut escaped) {
+                let style = if self.ansi {
+                    style.patch(styled(run_style))
+                } else {
+                    style
+                };
+                for (idx, piece) in run.split('\n').enumerate() {
+                    if idx > 0 {
+                        lines.push(Line::default());
+                    }
+                    let piece = piece.strip_suffix('\r').unwrap_or(piece);
+                    if !piece.is_empty() {
+                        lines
+                            .last_mut()
+                            .unwrap()
+                            .push_span(Span::styled(piece.to_owned(), style));
+                    }
+                }
+            }
+        }
+        // Like `str::lines`, a trailing newline does not start a new line.
+        if lines.last().is_some_and(|line| line.spans.is_empty()) {
+            lines.pop();
+        }
+        if self.trimmed > 0 {
+            let marker = format!("… {} lines trimmed …", self.trimmed);
+            lines.insert(
+                1.min(lines.len()),
+                Line::from(Span::styled(marker, Style::from(StyleConfig::muted()))),
+            );
+        }
+        lines
+            .into_iter()
+            .map(|line| {
+                line.set_style(Into::<Style>::into(self.style.clone()))
+                    .alignment(self.alignment)
+            })
             .collect()
     }
 }
 
+/// What the current action is doing.
 #[derive(Clone, Debug, Default, PartialEq)]
-enum ActionStatus {
+pub enum ActionStatus {
     Running,
+    /// Being stopped by the presenter.
     Forced,
+    /// Held by a pause until the presenter moves on.
+    Waiting,
     #[default]
     Stopped,
 }
@@ -48,6 +272,131 @@ impl ActionStatus {
     pub fn force_stop(&self) -> bool {
         *self == ActionStatus::Forced
     }
+
+    /// Whether no action is in progress.
+    fn idle(&self) -> bool {
+        matches!(self, ActionStatus::Stopped | ActionStatus::Waiting)
+    }
+}
+
+/// A failed command holding the presentation, as requested by its `on_error`.
+#[derive(Clone, Copy, Debug)]
+pub struct Failure {
+    pub on_error: OnError,
+    pub exit_code: Option<i32>,
+}
+
+/// A command run on one host by a worker, returning its exit code, along
+/// with what its failure does.
+struct Job {
+    on_error: OnError,
+    run: Box<dyn FnOnce() -> Option<i32> + Send>,
+}
+
+/// A password being entered, for the remote or sudo user in `label`, or the
+/// answer to a question of a server authenticating that user.
+#[derive(Clone, Debug)]
+pub struct PasswordPrompt {
+    pub label: String,
+    pub input: String,
+    pub question: Option<AuthQuestion>,
+}
+
+/// A command waiting to be confirmed before it runs.
+#[derive(Clone, Debug)]
+pub struct Confirmation {
+    /// The question configured for the command, if any.
+    pub prompt: Option<String>,
+    pub command: String,
+}
+
+/// Where the check of a remote host stands.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PreflightOutcome {
+    Checking,
+    Passed,
+    /// The host could not be logged into, for this reason.
+    Failed(String),
+}
+
+/// A remote host used by the current stage, checked as it starts.
+#[derive(Clone, Debug)]
+pub struct PreflightCheck {
+    /// The user and host, and the sudo user if any.
+    pub label: String,
+    pub outcome: PreflightOutcome,
+}
+
+/// A search through the output, whose matches are highlighted.
+#[derive(Clone, Debug, Default)]
+pub struct Search {
+    pub query: String,
+    /// Whether letters only match in the same case.
+    pub case_sensitive: bool,
+    /// Index of the match moved to, clamped to the last one when rendered.
+    pub current: usize,
+    /// Matches found in the output when last rendered.
+    pub matches: usize,
+    /// Whether the view still has to be scrolled to the current match.
+    pub jump: bool,
+}
+
+/// Position of the presentation, as shown in the instructions bar.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    /// Shown stage, counting from one.
+    pub stage: usize,
+    pub stages: usize,
+    /// Actions run in the shown stage.
+    pub action: usize,
+    pub actions: usize,
+    /// Actions run across all stages, up to the shown one.
+    pub done: usize,
+    pub total: usize,
+}
+
+/// How to take back the output of an action when going back over it.
+enum Undo {
+    /// Drop the buffer entries from this index on.
+    Truncate(usize),
+    /// Put back the entries cleared after the stage title.
+    Restore(Vec<BufferedOutput>),
+}
+
+/// An action run, as remembered to go back over it.
+struct Step {
+    undo: Undo,
+    /// Whether the last command run before the action succeeded, as seen
+    /// by `when`.
+    last_success: Option<bool>,
+}
+
+/// A session opened ahead of time for the action at `position`.
+struct Prefetched {
+    position: (usize, usize),
+    session: CommandSession,
+}
+
+/// Which view is currently shown on top of the output pane.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Mode {
+    /// Overview of the presentation, shown until the first action.
+    Splash,
+    /// Asks whether to proceed after a failed command.
+    Prompt,
+    /// Asks whether to run a command that needs confirmation.
+    Confirm,
+    /// Reads a password left to be entered in the configuration.
+    Password,
+    /// Idle animation, shown until a key restarts the presentation.
+    Attract,
+    /// Lists the stages to jump to one of them.
+    Contents,
+    /// Reads the text to search the output for.
+    Search,
+    #[default]
+    Normal,
+    Help,
 }
 
 pub struct App {
@@ -58,12 +407,134 @@ pub struct App {
     stage_idx: usize,
     action_idx: usize,
     action_status: Arc<Mutex<ActionStatus>>,
+    /// Lines the output pane is scrolled up by from its bottom, where it
+    /// follows new output. Bounded to the top of the output when rendered.
     pub scroll: u16,
+    pub show_line_numbers: bool,
+    pub show_instructions: bool,
+    pub wrap: bool,
+    /// Whether the outline pane is shown beside the output.
+    pub show_outline: bool,
+    /// Whether command and presentation times are shown.
+    pub show_timing: bool,
+    pub mode: Mode,
+    /// Presentation title shown on the splash and attract screens.
+    pub title: String,
+    /// Title shown on top of the border, if any.
+    pub border_title: Option<String>,
+    pub border: BorderStyle,
+    /// Buffer index of the command entry selected for re-running.
+    pub selected: Option<usize>,
+    /// Search being entered, or whose matches are highlighted.
+    pub search: Option<Search>,
+    /// The keys bound to every action.
+    pub keymap: Keymap,
+    /// Message confirming a key press, and when it was shown.
+    toast: Option<(String, Instant)>,
+    /// Whether to restart once the running action is interrupted.
+    restart_pending: bool,
     finished: bool,
+    records: Arc<Mutex<Vec<CommandRecord>>>,
+    actions_run: usize,
+    force_stops: usize,
+    prefetched: Arc<Mutex<Option<Prefetched>>>,
+    /// Threads working in the background, waited for on exit.
+    workers: Vec<JoinHandle<()>>,
+    /// Failed command that must be acknowledged before going on.
+    failure: Arc<Mutex<Option<Failure>>>,
+    /// Whether the last command run succeeded, on every host it ran on, if
+    /// a command ran yet.
+    last_success: Arc<Mutex<Option<bool>>>,
+    /// SSH sessions reused by the remote actions of the current stage.
+    sessions: SessionCache,
+    /// Questions of the servers authenticating interactively, to be shown.
+    questions: Receiver<AuthQuestion>,
+    /// Whether the next action waits for the sessions opened ahead for it.
+    connecting: bool,
+    /// Whether every remote host is checked as a stage using it starts,
+    /// rather than only those set to.
+    pub preflight: bool,
+    /// Checks of the remote hosts of the current stage.
+    preflight_checks: Arc<Mutex<Vec<PreflightCheck>>>,
+    /// Stage whose remote hosts were checked, while it is entered.
+    preflight_stage: Option<usize>,
+    /// Last stdout of every command, keyed by command line.
+    captures: Arc<Mutex<HashMap<String, String>>>,
+    /// Output of the commands that capture it, keyed by variable name.
+    variables: Arc<Mutex<HashMap<String, String>>>,
+    /// Stage whose setup hook ran last, and whose teardown is pending.
+    entered_stage: Option<usize>,
+    /// When the running command started, if a command is running.
+    command_started: Option<Instant>,
+    /// Time the running command has been executing, refreshed every tick.
+    elapsed: Duration,
+    /// When the first action of the presentation ran, if one did.
+    started: Option<Instant>,
+    /// Time since the first action ran, refreshed every tick until the
+    /// presentation is finished.
+    pub total: Option<Duration>,
+    /// When the running wait action ends, if one is running.
+    wait_deadline: Option<Instant>,
+    /// Time left until the running wait action ends, refreshed every tick.
+    remaining: Duration,
+    /// Frames of the spinner shown so far.
+    frame: usize,
+    /// When the spinner last moved to its next frame.
+    frame_shown: Instant,
+    /// Time between two ticks, redrawing the interface.
+    pub tick_rate: Duration,
+    /// Idle time after which the attract screen is shown, if enabled.
+    attract_after: Option<Duration>,
+    /// When the last key was pressed, or the attract screen was entered.
+    last_input: Instant,
+    /// How long autoplay waits once an action is done before running the
+    /// next one, if the presentation plays by itself.
+    pub autoplay: Option<Duration>,
+    /// Whether autoplay starts over once the presentation is finished.
+    pub autoplay_loop: bool,
+    /// Time without a key press after which autoplay takes over again.
+    pub autoplay_idle: Duration,
+    /// Whether autoplay waits for the presenter to leave the keys alone.
+    autoplay_paused: bool,
+    /// When autoplay started waiting to move on.
+    autoplay_since: Option<Instant>,
+    /// How to take back each action run in the current stage.
+    history: Vec<Step>,
+    /// Password being entered before running the current action.
+    pub password_prompt: Option<PasswordPrompt>,
+    /// Passwords entered so far, keyed by the label they were asked with.
+    secrets: HashMap<String, String>,
+    /// Command asked about before running the current action.
+    pub confirmation: Option<Confirmation>,
+    /// Position of the action confirmed to run, until it starts.
+    confirmed: Option<(usize, usize)>,
+    /// Stage highlighted in the table of contents.
+    pub contents: ListState,
+    /// Width of the terminal, as last resized to.
+    width: Option<u16>,
+    /// Whether the presentation runs without a terminal, skipping delays.
+    headless: bool,
+    /// Whether commands, transfers and hooks are shown without being run.
+    dry_run: bool,
+    /// Factor applied to the typing speed of every message.
+    pub speed_multiplier: f64,
+    /// Log the output is appended to, if one was requested.
+    pub transcript: Option<Transcript>,
+    /// Recording of the output as a cast, if one was requested.
+    pub cast: Option<Cast>,
+    /// Stage and first buffer entry of the output of the last action run,
+    /// until it is done and recorded.
+    unrecorded: Option<(usize, usize)>,
 }
 
 impl App {
     pub fn new(config: config::Config) -> Self {
+        let ui = config.ui.clone().unwrap_or_default();
+        // Checked when the configuration was loaded.
+        let keymap = Keymap::new(config.keybindings.as_ref()).unwrap_or_default();
+        let sessions = SessionCache::default();
+        let (sender, questions) = mpsc::channel();
+        sessions.set_questions(Some(sender));
         let mut app = Self {
             running: true,
             config,
@@ -72,241 +543,2756 @@ impl App {
             action_idx: 0,
             action_status: Arc::new(Mutex::new(ActionStatus::default())),
             scroll: 0,
+            show_line_numbers: ui.show_line_numbers.unwrap_or(false),
+            show_instructions: ui.show_instructions.unwrap_or(true),
+            wrap: ui.wrap.unwrap_or(false),
+            show_outline: ui.show_outline.unwrap_or(false),
+            show_timing: ui.show_timing.unwrap_or(true),
+            mode: Mode::default(),
+            title: String::new(),
+            border_title: match ui.title.clone() {
+                Some(title) if title.is_empty() => None,
+                Some(title) => Some(title),
+                None => Some(String::from("AutoPilot")),
+            },
+            border: ui.border.unwrap_or_default(),
+            selected: None,
+            search: None,
+            keymap,
+            toast: None,
+            restart_pending: false,
             finished: false,
+            records: Arc::new(Mutex::new(Vec::new())),
+            actions_run: 0,
+            force_stops: 0,
+            prefetched: Arc::new(Mutex::new(None)),
+            workers: Vec::new(),
+            failure: Arc::new(Mutex::new(None)),
+            last_success: Arc::new(Mutex::new(None)),
+            sessions,
+            questions,
+            connecting: false,
+            preflight: false,
+            preflight_checks: Arc::new(Mutex::new(Vec::new())),
+            preflight_stage: None,
+            captures: Arc::new(Mutex::new(HashMap::new())),
+            variables: Arc::new(Mutex::new(HashMap::new())),
+            entered_stage: None,
+            command_started: None,
+            elapsed: Duration::ZERO,
+            started: None,
+            total: None,
+            wait_deadline: None,
+            remaining: Duration::ZERO,
+            frame: 0,
+            frame_shown: Instant::now(),
+            tick_rate: Duration::from_millis(
+                ui.tick_ms
+                    .unwrap_or(config::DEFAULT_TICK_MS)
+                    .max(config::MIN_TICK_MS),
+            ),
+            attract_after: ui.attract_after.map(Duration::from_secs),
+            last_input: Instant::now(),
+            autoplay: None,
+            autoplay_loop: false,
+            autoplay_idle: Duration::ZERO,
+            autoplay_paused: false,
+            autoplay_since: None,
+            history: Vec::new(),
+            password_prompt: None,
+            confirmation: None,
+            confirmed: None,
+            secrets: HashMap::new(),
+            contents: ListState::default(),
+            width: None,
+            headless: false,
+            dry_run: false,
+            speed_multiplier: 1.0,
+            transcript: None,
+            cast: None,
+            unrecorded: None,
         };
         app.write_title();
         app
     }
 
+    /// What the current action is doing.
+    pub fn action_status(&self) -> ActionStatus {
+        self.action_status.lock().unwrap().clone()
+    }
+
+    /// The text of every output entry, without styling, the stage title
+    /// first.
+    pub fn output(&self) -> Vec<String> {
+        self.buffer
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| ansi::strip(&entry.plain_text()))
+            .collect()
+    }
+
+    /// The checks of the remote hosts of the current stage, until they
+    /// are dismissed.
+    pub fn preflight_checks(&self) -> Vec<PreflightCheck> {
+        self.preflight_checks.lock().unwrap().clone()
+    }
+
+    /// The failed command holding the presentation, if any.
+    pub fn failure(&self) -> Option<Failure> {
+        *self.failure.lock().unwrap()
+    }
+
     pub fn status(&self) -> Span<'static> {
-        match *self.action_status.lock().unwrap() {
-            ActionStatus::Forced | ActionStatus::Stopped if self.finished => {
-                Span::styled(" [ Finished ] ", Style::default().fg(Color::LightYellow))
-            }
-            ActionStatus::Running => {
-                Span::styled(" ◄ Running... ▶ ", Style::default().fg(Color::LightGreen))
-            }
-            ActionStatus::Forced => {
-                Span::styled(" ■ Stopping... ■ ", Style::default().fg(Color::Red))
-            }
-            ActionStatus::Stopped => {
-                Span::styled(" ■ Stopped ■ ", Style::default().fg(Color::LightRed))
+        if let Some((toast, shown)) = &self.toast {
+            if shown.elapsed() < TOAST_DURATION {
+                return Span::styled(
+                    format!(" ✔ {} ✔ ", toast),
+                    styled(Style::default().fg(Color::LightGreen)),
+                );
             }
         }
+        if let Some(failure) = self.failure() {
+            let code = failure
+                .exit_code
+                .map_or_else(|| String::from("none"), |c| c.to_string());
+            return Span::styled(
+                format!(" ✖ Exit {} ✖ ", code),
+                styled(Style::default().fg(Color::Red)),
+            );
+        }
+        let spinner = SPINNER[self.frame % SPINNER.len()];
+        match *self.action_status.lock().unwrap() {
+            ActionStatus::Waiting => Span::styled(
+                " ⏸ Waiting ⏸ ",
+                styled(Style::default().fg(Color::LightYellow)),
+            ),
+            ActionStatus::Forced | ActionStatus::Stopped if self.finished => Span::styled(
+                " [ Finished ] ",
+                styled(Style::default().fg(Color::LightYellow)),
+            ),
+            ActionStatus::Running if self.wait_deadline.is_some() => Span::styled(
+                format!(
+                    " {} Waiting {:.1}s {} ",
+                    spinner,
+                    self.remaining.as_secs_f64(),
+                    spinner
+                ),
+                styled(Style::default().fg(Color::LightGreen)),
+            ),
+            ActionStatus::Running if self.command_started.is_some() => Span::styled(
+                format!(
+                    " {} Running... {:.1}s {} ",
+                    spinner,
+                    self.elapsed.as_secs_f64(),
+                    spinner
+                ),
+                styled(Style::default().fg(Color::LightGreen)),
+            ),
+            ActionStatus::Running => Span::styled(
+                format!(" {} Running... {} ", spinner, spinner),
+                styled(Style::default().fg(Color::LightGreen)),
+            ),
+            ActionStatus::Forced => Span::styled(
+                format!(" {} Stopping... {} ", spinner, spinner),
+                styled(Style::default().fg(Color::Red)),
+            ),
+            ActionStatus::Stopped => Span::styled(
+                " ■ Stopped ■ ",
+                styled(Style::default().fg(Color::LightRed)),
+            ),
+        }
     }
 
-    fn write_title(&mut self) {
-        self.buffer.lock().unwrap().clear();
-        self.buffer.lock().unwrap().push(BufferedOutput {
-            text: format!(
-                "### {} ###",
-                self.config.stages[self.stage_idx].name
-            )
-            .into(),
-            style: StyleConfig::title(),
-        });
+    /// Time spent on the attract screen, driving its animation.
+    pub fn attract_elapsed(&self) -> Duration {
+        self.last_input.elapsed()
     }
 
-    /// updates the application's state based on user input
-    pub fn handle_events(&mut self, key_event: KeyEvent) -> Result<()> {
-        Ok(match key_event.code {
-            KeyCode::Char('q') | KeyCode::Char('Q') => self.exit(),
-            KeyCode::Left => self.prev_action(),
-            KeyCode::Right => self.next_action()?,
-            KeyCode::Up => self.scroll_up(1),
-            KeyCode::PageUp => self.scroll_up(10),
-            KeyCode::Down => self.scroll_down(1),
-            KeyCode::PageDown => self.scroll_down(10),
-            _ => {}
-        })
+    /// Stage names of the presentation, in order.
+    pub fn stage_names(&self) -> Vec<&str> {
+        self.config.stages.iter().map(|s| s.name.as_str()).collect()
     }
 
-    fn scroll_up(&mut self, value: u16) {
-        self.scroll = self.scroll.saturating_add(value);
+    /// The name of every stage along with the summary of its actions.
+    pub fn outline(&self) -> Vec<(&str, Vec<String>)> {
+        self.config
+            .stages
+            .iter()
+            .map(|stage| {
+                let actions = stage
+                    .actions
+                    .iter()
+                    .map(|a| a.label.clone().unwrap_or_else(|| a.action.summary()))
+                    .collect();
+                (stage.name.as_str(), actions)
+            })
+            .collect()
     }
 
-    fn scroll_down(&mut self, value: u16) {
-        self.scroll = self.scroll.saturating_sub(value);
+    /// Number of stages and actions, and the remote hosts to be contacted.
+    pub fn overview(&self) -> (usize, usize, Vec<String>) {
+        (
+            self.config.stages.len(),
+            self.config.stages.iter().map(|s| s.actions.len()).sum(),
+            self.config.remote_hosts(),
+        )
     }
 
-    /// Handles the tick event of the terminal.
-    pub fn tick(&self) {}
+    /// Stage and action reached, for the progress indicator.
+    pub fn progress(&self) -> Progress {
+        let stages = &self.config.stages;
+        // Once a stage is over, it stays shown until the next one starts.
+        let (stage, action) =
+            if self.action_idx == 0 && self.stage_idx > 0 && !self.history.is_empty() {
+                (self.stage_idx - 1, stages[self.stage_idx - 1].actions.len())
+            } else {
+                (self.stage_idx, self.action_idx)
+            };
+        Progress {
+            stage: stage + 1,
+            stages: stages.len(),
+            action,
+            actions: stages[stage].actions.len(),
+            done: stages[..stage]
+                .iter()
+                .map(|s| s.actions.len())
+                .sum::<usize>()
+                + action,
+            total: stages.iter().map(|s| s.actions.len()).sum(),
+        }
+    }
 
-    fn next_action_idx(&mut self) {
-        if self.finished {
-            return;
+    /// Summarizes the progress of the presentation so far.
+    pub fn summary(&self) -> Summary {
+        let commands = self.records.lock().unwrap().clone();
+        let success = self.finished && commands.iter().all(|r| r.exit_code == Some(0));
+        Summary {
+            total_stages: self.config.stages.len(),
+            total_actions: self.config.stages.iter().map(|s| s.actions.len()).sum(),
+            actions_run: self.actions_run,
+            commands,
+            force_stops: self.force_stops,
+            finished: self.finished,
+            success,
         }
-        self.action_idx += 1;
+    }
 
-        let stage = &self.config.stages[self.stage_idx];
-        if stage.actions.len() == self.action_idx {
-            if self.config.stages.len() == self.stage_idx + 1 {
-                self.finished = true;
-            } else {
-                self.stage_idx += 1;
-                self.action_idx = 0;
-            }
+    /// The position of the next action to run, as the index of its stage
+    /// and its index within it, or `None` once the presentation is finished.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        (!self.finished).then_some((self.stage_idx, self.action_idx))
+    }
+
+    /// Moves to the action at `position`, below the title of its stage. The
+    /// actions before it are skipped rather than run, and going back over
+    /// them leaves the title alone. Returns whether the position exists.
+    pub fn resume_at(&mut self, (stage_idx, action_idx): (usize, usize)) -> bool {
+        let Some(stage) = self.config.stages.get(stage_idx) else {
+            return false;
+        };
+        if action_idx >= stage.actions.len() {
+            return false;
         }
+        info!(stage = %stage.name, action = action_idx, "Resuming the presentation");
+        self.stage_idx = stage_idx;
+        self.action_idx = action_idx;
+        self.write_title();
+        let last_success = *self.last_success.lock().unwrap();
+        self.history = (0..action_idx)
+            .map(|_| Step {
+                undo: Undo::Truncate(1),
+                last_success,
+            })
+            .collect();
+        true
     }
 
-    fn prev_action(&mut self) {
-        if *self.action_status.lock().unwrap() != ActionStatus::Stopped {
-            return;
+    fn write_title(&mut self) {
+        let stage = &self.config.stages[self.stage_idx];
+        self.history.clear();
+        self.buffer.lock().unwrap().clear();
+        self.buffer.lock().unwrap().push(BufferedOutput::new(
+            format!("### {} ###", stage.name),
+            StyleConfig::title(),
+            stage.align.unwrap_or_default().into(),
+        ));
+    }
+
+    /// updates the application's state based on user input
+    pub fn handle_events(&mut self, key_event: KeyEvent) -> Result<()> {
+        self.last_input = Instant::now();
+        if self.autoplay.is_some() && !self.autoplay_paused {
+            info!("Pausing autoplay");
+            self.autoplay_paused = true;
+            self.toast = Some((String::from("Autoplay paused"), Instant::now()));
         }
-        if self.finished {
-            self.finished = false;
+        if self.mode == Mode::Attract {
+            self.restart();
+            return Ok(());
         }
-        if self.action_idx == 0 {
-            if self.stage_idx > 0 {
-                self.stage_idx -= 1;
-                self.write_title();
+        if self.mode == Mode::Password {
+            return self.handle_password_input(key_event);
+        }
+        if self.mode == Mode::Search {
+            self.handle_search_input(key_event);
+            return Ok(());
+        }
+        // Text being entered aside, the quit key works in every view.
+        let action = self.keymap.action(&key_event);
+        if self.mode == Mode::Prompt {
+            match key_event.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') | KeyCode::Enter => {
+                    self.acknowledge_failure();
+                    self.next_action()?;
+                }
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                    self.acknowledge_failure()
+                }
+                _ if action == Some(KeyAction::Quit) => self.exit(),
+                _ => {}
             }
-            return;
+            return Ok(());
         }
-
-        self.action_idx -= 1;
-        self.buffer.lock().unwrap().pop();
-    }
-
-    fn next_action(&mut self) -> Result<()> {
-        if *self.action_status.lock().unwrap() == ActionStatus::Running {
-            *self.action_status.lock().unwrap() = ActionStatus::Forced;
+        if self.mode == Mode::Confirm {
+            // Only an explicit yes runs the command.
+            match key_event.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => {
+                    self.confirmation = None;
+                    self.confirmed = Some((self.stage_idx, self.action_idx));
+                    self.mode = Mode::Normal;
+                    self.next_action()?;
+                }
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                    self.confirmation = None;
+                    self.mode = Mode::Normal;
+                    self.write_skipped(String::from("[skipped: not confirmed]"));
+                }
+                _ if action == Some(KeyAction::Quit) => self.exit(),
+                _ => {}
+            }
             return Ok(());
         }
-        if self.finished {
+        if self.mode == Mode::Splash {
+            match (key_event.code, action) {
+                (_, Some(KeyAction::Quit)) => self.exit(),
+                (KeyCode::Enter, _) | (_, Some(KeyAction::Next)) => self.mode = Mode::Normal,
+                _ => {}
+            }
             return Ok(());
         }
-        if self.action_idx == 0 && self.stage_idx > 0 {
-            self.write_title();
+        if self.mode == Mode::Contents {
+            match (key_event.code, action) {
+                (_, Some(KeyAction::Quit)) => self.exit(),
+                (KeyCode::Up, _) => self.contents.select_previous(),
+                (KeyCode::Down, _) => self.contents.select_next(),
+                (KeyCode::Home, _) => self.contents.select_first(),
+                (KeyCode::End, _) => self.contents.select_last(),
+                (KeyCode::Enter, _) => {
+                    self.mode = Mode::Normal;
+                    if let Some(stage_idx) = self.contents.selected() {
+                        self.jump_to_stage(stage_idx.min(self.config.stages.len() - 1));
+                    }
+                }
+                (KeyCode::Esc, _) | (_, Some(KeyAction::Contents)) => self.mode = Mode::Normal,
+                _ => {}
+            }
+            return Ok(());
         }
-        match self.config.stages[self.stage_idx].actions[self.action_idx].clone() {
-            config::Action::Message { text, style, speed } => {
-                self.write_message(text, style, speed.unwrap());
+        if self.mode == Mode::Help {
+            match (key_event.code, action) {
+                (_, Some(KeyAction::Quit)) => self.exit(),
+                (KeyCode::Esc, _) | (_, Some(KeyAction::Help)) => self.mode = Mode::Normal,
+                _ => {}
             }
-            config::Action::Command {
-                command,
-                sudo,
-                hide_stdout,
-                hide_stderr,
-                style,
-                remote,
-                r#loop,
-            } => {
-                self.run_command(
-                    command,
-                    remote,
-                    sudo,
-                    hide_stdout.unwrap(),
-                    hide_stderr.unwrap(),
-                    style,
-                    r#loop.unwrap(),
-                )?;
+            return Ok(());
+        }
+        let Some(action) = action else {
+            // Digits jump to a stage, unless bound to an action.
+            if let KeyCode::Char(digit @ '1'..='9') = key_event.code {
+                let stage_idx = digit as usize - '1' as usize;
+                if stage_idx < self.config.stages.len() {
+                    self.jump_to_stage(stage_idx);
+                }
             }
+            return Ok(());
         };
-        self.next_action_idx();
-
+        match action {
+            KeyAction::Quit => self.exit(),
+            KeyAction::Prev => self.prev_action(),
+            KeyAction::Next => self.next_action()?,
+            KeyAction::Skip => self.skip_action(),
+            KeyAction::ScrollUp => self.scroll_up(1),
+            KeyAction::PageUp => self.scroll_up(10),
+            KeyAction::ScrollDown => self.scroll_down(1),
+            KeyAction::PageDown => self.scroll_down(10),
+            KeyAction::Top => self.scroll = u16::MAX,
+            KeyAction::Bottom => self.scroll = 0,
+            KeyAction::LineNumbers => self.toggle_line_numbers(),
+            KeyAction::Wrap => self.toggle_wrap(),
+            KeyAction::Outline => self.toggle_outline(),
+            KeyAction::Instructions => self.toggle_instructions(),
+            KeyAction::Help => self.mode = Mode::Help,
+            KeyAction::Contents => self.open_contents(),
+            KeyAction::Restart => self.restart(),
+            KeyAction::SelectPrev => self.select_command(false),
+            KeyAction::SelectNext => self.select_command(true),
+            KeyAction::Rerun => self.rerun_selected()?,
+            KeyAction::Search => self.open_search(),
+            KeyAction::Copy => self.copy_output(false),
+            KeyAction::CopyAll => self.copy_output(true),
+            KeyAction::OpenLink => self.open_link(),
+            KeyAction::NextMatch => self.next_match(true),
+            KeyAction::PrevMatch => self.next_match(false),
+            KeyAction::Clear => {
+                self.selected = None;
+                self.search = None;
+                self.dismiss_preflight();
+            }
+        }
         Ok(())
     }
 
-    fn write_message(&mut self, text: String, style: Option<StyleConfig>, speed: u64) {
-        let exec_status = self.action_status.clone();
-        *exec_status.lock().unwrap() = ActionStatus::Running;
+    /// Starts entering a new search, keeping the case sensitivity of the
+    /// previous one.
+    fn open_search(&mut self) {
+        let case_sensitive = self
+            .search
+            .as_ref()
+            .is_some_and(|search| search.case_sensitive);
+        self.search = Some(Search {
+            case_sensitive,
+            ..Search::default()
+        });
+        self.mode = Mode::Search;
+    }
 
-        self.write_buf(String::from("> "), style);
-        let buffer = self.buffer.clone();
-        thread::spawn(move || {
-            for (idx, c) in text.chars().enumerate() {
-                if exec_status.lock().unwrap().force_stop() {
-                    // Print the rest of the string all at once.
-                    Self::add_to_buf(buffer, &text[idx..text.len()], false);
-                    break;
+    /// Edits the search being entered. On `<Enter>` the view moves to the
+    /// latest match, `<Tab>` toggles case sensitivity and `<Esc>` drops the
+    /// search.
+    fn handle_search_input(&mut self, key_event: KeyEvent) {
+        let Some(search) = self.search.as_mut() else {
+            self.mode = Mode::Normal;
+            return;
+        };
+        match key_event.code {
+            KeyCode::Char(c) => search.query.push(c),
+            KeyCode::Backspace => {
+                search.query.pop();
+            }
+            KeyCode::Tab => search.case_sensitive = !search.case_sensitive,
+            KeyCode::Enter => {
+                if search.query.is_empty() {
+                    self.search = None;
+                } else {
+                    search.current = usize::MAX;
+                    search.jump = true;
                 }
-                buffer.lock().unwrap().last_mut().unwrap().text.push(c);
-                thread::sleep(Duration::from_millis(speed));
+                self.mode = Mode::Normal;
             }
-            *exec_status.lock().unwrap() = ActionStatus::Stopped;
-        });
+            KeyCode::Esc => {
+                self.search = None;
+                self.mode = Mode::Normal;
+            }
+            _ => {}
+        }
     }
 
-    fn run_command(
-        &mut self,
-        command: CommandType,
-        remote: Option<RemoteConfig>,
-        sudo: Option<SudoConfig>,
-        hide_stdout: bool,
-        hide_stderr: bool,
-        style: Option<StyleConfig>,
-        loop_config: LoopConfig,
-    ) -> Result<()> {
-        let exec_status = self.action_status.clone();
-        *exec_status.lock().unwrap() = ActionStatus::Running;
-
-        let mut command_session = match CommandSession::new(&command, remote, sudo) {
-            Ok(command_session) => command_session,
-            Err(e) => {
-                self.write_buf(
-                    format!(
-                        "Failed to initialize a new session.\n\tCommand: {}\n\tError:   {}",
-                        command.get_command(),
-                        e,
-                    ),
-                    Some(StyleConfig::error()),
-                );
-                *exec_status.lock().unwrap() = ActionStatus::Stopped;
-                return Ok(());
-            }
+    /// Moves to the next or previous match of the search, wrapping around
+    /// the output.
+    fn next_match(&mut self, forward: bool) {
+        let Some(search) = self.search.as_mut() else {
+            return;
+        };
+        if search.matches == 0 {
+            return;
+        }
+        let current = search.current.min(search.matches - 1);
+        search.current = if forward {
+            (current + 1) % search.matches
+        } else {
+            current.checked_sub(1).unwrap_or(search.matches - 1)
         };
+        search.jump = true;
+    }
 
-        self.write_buf(command_session.get_prompt()?, style);
+    /// Scrolls the output pane with the mouse wheel.
+    pub fn handle_mouse(&mut self, mouse_event: MouseEvent) {
+        if self.mode != Mode::Normal {
+            return;
+        }
+        match mouse_event.kind {
+            MouseEventKind::ScrollUp => self.scroll_up(WHEEL_LINES),
+            MouseEventKind::ScrollDown => self.scroll_down(WHEEL_LINES),
+            _ => return,
+        }
+        self.last_input = Instant::now();
+    }
 
-        let buffer = self.buffer.clone();
-        thread::spawn(move || {
-            let times = loop_config.times;
-            let delay = loop_config.delay.unwrap();
-            for repetition in 0..times {
-                if exec_status.lock().unwrap().force_stop() {
-                    Self::add_to_buf(buffer, "Command interrupted!\n", hide_stdout);
-                    break;
+    /// Edits the password being entered. On `<Enter>` it is kept and the
+    /// action waiting for it run, while `<Esc>` leaves the action pending.
+    fn handle_password_input(&mut self, key_event: KeyEvent) -> Result<()> {
+        let Some(prompt) = self.password_prompt.as_mut() else {
+            self.mode = Mode::Normal;
+            return Ok(());
+        };
+        match key_event.code {
+            KeyCode::Char(c) => prompt.input.push(c),
+            KeyCode::Backspace => {
+                prompt.input.pop();
+            }
+            KeyCode::Enter => {
+                let prompt = self.password_prompt.take().unwrap();
+                self.mode = Mode::Normal;
+                match prompt.question {
+                    // The authentication waiting for the answer goes on.
+                    Some(question) => question.answer(Some(prompt.input)),
+                    None => {
+                        self.secrets.insert(prompt.label, prompt.input);
+                        // Further passwords of the action are asked for in turn.
+                        self.next_action()?;
+                    }
+                }
+            }
+            KeyCode::Esc => {
+                if let Some(question) = self.password_prompt.take().and_then(|p| p.question) {
+                    question.answer(None);
                 }
+                self.mode = Mode::Normal;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
 
-                command_session.run_command().unwrap();
-                Self::add_to_buf(buffer.clone(),
-                                 &command_session.get_stdout(),
-                                 hide_stdout);
-                Self::add_to_buf(buffer.clone(),
-                                 &command_session.get_stderr(),
-                                 hide_stderr);
+    /// The passwords of `remote`, its jump hosts and `sudo`, in the order
+    /// they are needed, each with the label it is asked for with.
+    fn password_fields<'a>(
+        remote: &'a mut Option<RemoteConfig>,
+        sudo: &'a mut Option<SudoConfig>,
+    ) -> Vec<(String, &'a mut Option<String>)> {
+        let host = remote
+            .as_ref()
+            .map_or_else(|| String::from("localhost"), |remote| remote.host.clone());
+        let mut fields = Vec::new();
+        let mut next = remote.as_mut();
+        while let Some(RemoteConfig {
+            host,
+            user,
+            password,
+            jump,
+            ..
+        }) = next
+        {
+            let label = match user {
+                Some(user) => format!("{}@{}", user, host),
+                None => host.clone(),
+            };
+            fields.push((label, password));
+            next = jump.as_deref_mut();
+        }
+        // Jump hosts are connected to first.
+        fields.reverse();
+        if let Some(SudoConfig { user, password, .. }) = sudo {
+            let user = user.as_deref().unwrap_or("root");
+            fields.push((format!("sudo {} on {}", user, host), password));
+        }
+        fields
+    }
 
-                if delay > 0 && repetition != times - 1 {
-                    thread::sleep(Duration::from_millis(delay));
-                }
+    /// The hosts `action` runs on, `None` standing for the local machine, and
+    /// the sudo user it runs as.
+    fn action_remotes(action: &config::Action) -> (Vec<Option<RemoteConfig>>, &Option<SudoConfig>) {
+        match action {
+            config::Action::Command(command) => (
+                match &command.remote {
+                    Some(remotes) => remotes.hosts().iter().cloned().map(Some).collect(),
+                    None => vec![None],
+                },
+                &command.sudo,
+            ),
+            config::Action::WaitFor(wait_for) => (vec![wait_for.remote.clone()], &wait_for.sudo),
+            config::Action::Upload(transfer) | config::Action::Download(transfer) => {
+                (vec![transfer.remote.clone()], &None)
             }
-            *exec_status.lock().unwrap() = ActionStatus::Stopped;
-        });
+            // The commands of a group have hosts of their own.
+            config::Action::Message { .. }
+            | config::Action::Pause { .. }
+            | config::Action::Wait { .. }
+            | config::Action::Clear
+            | config::Action::Parallel { .. } => (Vec::new(), &None),
+        }
+    }
 
-        Ok(())
+    /// What to ask before running `action`, if it is a command needing
+    /// confirmation that is not confirmed yet. A dry run has nothing to
+    /// confirm.
+    fn needed_confirmation(&self, action: &config::Action) -> Option<Confirmation> {
+        let config::Action::Command(command_config) = action else {
+            return None;
+        };
+        let needed =
+            command_config.confirm.unwrap_or(false) || command_config.confirm_prompt.is_some();
+        if !needed || self.dry_run || self.confirmed == Some((self.stage_idx, self.action_idx)) {
+            return None;
+        }
+        Some(Confirmation {
+            prompt: command_config.confirm_prompt.clone(),
+            command: command_config.command.get_command(),
+        })
     }
 
-    fn write_buf(&mut self, text: String, style: Option<StyleConfig>) {
-        self.buffer.lock().unwrap().push(BufferedOutput {
-            text,
-            style: style.unwrap_or_else(|| StyleConfig::default()),
-        });
+    /// Label of the first password of `action` left to be entered.
+    fn missing_password(&self, action: &config::Action) -> Option<String> {
+        if let config::Action::Parallel { actions } = action {
+            return actions
+                .iter()
+                .find_map(|child| self.missing_password(&child.action));
+        }
+        let (remotes, sudo) = Self::action_remotes(action);
+        // Every host of a fan-out has passwords of its own.
+        remotes.into_iter().find_map(|mut remote| {
+            Self::password_fields(&mut remote, &mut sudo.clone())
+                .into_iter()
+                .find(|(label, password)| {
+                    password.as_deref() == Some(PASSWORD_PROMPT)
+                        && !self.secrets.contains_key(label)
+                })
+                .map(|(label, _)| label)
+        })
     }
 
-    fn add_to_buf(buffer: Arc<Mutex<Vec<BufferedOutput>>>, output: &str, hide_output: bool) {
-        if !hide_output && !output.is_empty() {
-            buffer
-                .lock()
-                .unwrap()
-                .last_mut()
-                .unwrap()
-                .text
-                .push_str(&output);
+    /// Replaces the passwords left to be entered with those entered so far.
+    /// Returns whether none is missing.
+    fn fill_passwords(
+        &self,
+        remote: &mut Option<RemoteConfig>,
+        sudo: &mut Option<SudoConfig>,
+    ) -> bool {
+        let mut complete = true;
+        for (label, password) in Self::password_fields(remote, sudo) {
+            if password.as_deref() == Some(PASSWORD_PROMPT) {
+                match self.secrets.get(&label) {
+                    Some(secret) => *password = Some(secret.clone()),
+                    None => complete = false,
+                }
+            }
         }
+        complete
     }
 
-    fn exit(&mut self) {
+    fn toggle_line_numbers(&mut self) {
+        self.show_line_numbers = !self.show_line_numbers;
+    }
+
+    /// Switches between soft-wrapping and clipping long lines. As the rows
+    /// scrolled by change, a view scrolled up goes back to the bottom, like
+    /// on a resize.
+    fn toggle_wrap(&mut self) {
+        self.wrap = !self.wrap;
+        self.scroll = 0;
+    }
+
+    /// Shows or hides the outline pane. Wrapped lines get another width, so
+    /// the view goes back to the bottom as for `toggle_wrap`.
+    fn toggle_outline(&mut self) {
+        self.show_outline = !self.show_outline;
+        if self.wrap {
+            self.scroll = 0;
+        }
+    }
+
+    /// Moves the selection to the previous or next command entry in the buffer.
+    fn select_command(&mut self, forward: bool) {
+        let buffer = self.buffer.lock().unwrap();
+        let commands: Vec<usize> = buffer
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.origin.is_some())
+            .map(|(idx, _)| idx)
+            .collect();
+        self.selected = match (self.selected, forward) {
+            (None, _) => commands.last().copied(),
+            (Some(current), true) => commands
+                .iter()
+                .find(|&&idx| idx > current)
+                .copied()
+                .or(Some(current)),
+            (Some(current), false) => commands
+                .iter()
+                .rev()
+                .find(|&&idx| idx < current)
+                .copied()
+                .or(Some(current)),
+        };
+    }
+
+    /// Copies the output of the selected command, or else of the last action
+    /// run, to the clipboard. With `all`, the whole output is copied instead.
+    fn copy_output(&mut self, all: bool) {
+        let text = {
+            let buffer = self.buffer.lock().unwrap();
+            let undo = self.history.last().map(|step| &step.undo);
+            let entries = match (all, self.selected, undo) {
+                (true, _, _) | (false, None, None) => &buffer[..],
+                (false, Some(selected), _) => buffer.get(selected..=selected).unwrap_or_default(),
+                (false, None, Some(Undo::Truncate(start))) => &buffer[(*start).min(buffer.len())..],
+                (false, None, Some(Undo::Restore(_))) => &buffer[1.min(buffer.len())..],
+            };
+            let mut text = String::new();
+            for entry in entries {
+                if !text.is_empty() {
+                    text.push('\n');
+                }
+                text.push_str(&ansi::strip(&entry.plain_text()));
+                if !text.ends_with('\n') {
+                    text.push('\n');
+                }
+            }
+            text
+        };
+        let message = if text.is_empty() {
+            String::from("Nothing to copy")
+        } else {
+            let lines = text.lines().count();
+            match clipboard::copy(&text) {
+                Ok(Target::System) => format!("Copied {} lines", lines),
+                Ok(Target::Terminal) => format!("Copied {} lines through the terminal", lines),
+                Err(err) => format!("Failed to copy: {}", err),
+            }
+        };
+        self.toast = Some((message, Instant::now()));
+    }
+
+    /// Opens the latest URL shown in a message.
+    fn open_link(&mut self) {
+        let url = self
+            .buffer
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|entry| entry.links)
+            .find_map(|entry| {
+                let text = ansi::strip(&entry.plain_text());
+                let url = links::find_urls(&text).pop()?;
+                Some(text[url].to_owned())
+            });
+        let message = match url {
+            None => String::from("No link to open"),
+            Some(url) => match links::open(&url) {
+                Ok(()) => format!("Opened {}", url),
+                Err(err) => format!("Failed to open {}: {}", url, err),
+            },
+        };
+        self.toast = Some((message, Instant::now()));
+    }
+
+    /// Runs the selected command again, appending its output to the buffer.
+    fn rerun_selected(&mut self) -> Result<()> {
+        if !self.action_status.lock().unwrap().idle() {
+            return Ok(());
+        }
+        let Some(selected) = self.selected.take() else {
+            return Ok(());
+        };
+        let Some(origin) = self
+            .buffer
+            .lock()
+            .unwrap()
+            .get(selected)
+            .and_then(|e| e.origin)
+        else {
+            return Ok(());
+        };
+        self.scroll = 0;
+        self.take_output();
+        self.unrecorded = Some((origin.0, self.buffer.lock().unwrap().len()));
+        let action_config = match self.expand_captured(origin) {
+            Ok(action_config) => action_config,
+            Err(e) => {
+                self.write_start_error(e);
+                return Ok(());
+            }
+        };
+        match action_config.action {
+            config::Action::Command(command_config) => self.run_command(*command_config, origin),
+            config::Action::Parallel { actions } => self.run_parallel(actions, origin),
+            config::Action::WaitFor(wait_config) => self.wait_for(wait_config, origin),
+            config::Action::Upload(transfer_config) => {
+                self.transfer(Direction::Upload, transfer_config, origin)
+            }
+            config::Action::Download(transfer_config) => {
+                self.transfer(Direction::Download, transfer_config, origin)
+            }
+            config::Action::Message { .. }
+            | config::Action::Pause { .. }
+            | config::Action::Wait { .. }
+            | config::Action::Clear => Ok(()),
+        }
+    }
+
+    fn toggle_instructions(&mut self) {
+        self.show_instructions = !self.show_instructions;
+    }
+
+    /// Scrolls towards the start of the output.
+    fn scroll_up(&mut self, value: u16) {
+        self.scroll = self.scroll.saturating_add(value);
+    }
+
+    /// Scrolls towards the latest output.
+    fn scroll_down(&mut self, value: u16) {
+        self.scroll = self.scroll.saturating_sub(value);
+    }
+
+    /// Handles a resize of the terminal, which is redrawn right after.
+    ///
+    /// The output stays pinned to the bottom when it was, as the scroll
+    /// offset counts rows from there. Wrapped lines take other rows at another
+    /// width though, so a view scrolled up then goes back to the bottom rather
+    /// than showing unrelated lines.
+    pub fn resize(&mut self, width: u16) {
+        if self.wrap && self.width.is_some_and(|w| w != width) {
+            self.scroll = 0;
+        }
+        self.width = Some(width);
+    }
+
+    /// Handles the tick event of the terminal.
+    pub fn tick(&mut self) -> Result<()> {
+        if self.frame_shown.elapsed() >= SPINNER_STEP {
+            self.frame = self.frame.wrapping_add(1);
+            self.frame_shown = Instant::now();
+        }
+        self.take_output();
+        if self.restart_pending && self.action_status.lock().unwrap().idle() {
+            self.restart_pending = false;
+            self.reset();
+        }
+        if self.connecting && self.action_status.lock().unwrap().idle() {
+            self.connecting = false;
+            // The checks of a stage may leave sessions to open for its first
+            // action.
+            if !self.connect_ahead() {
+                self.start_action()?;
+            }
+        }
+        if self.password_prompt.is_none() {
+            if let Ok(question) = self.questions.try_recv() {
+                self.password_prompt = Some(PasswordPrompt {
+                    label: question.label.clone(),
+                    input: String::new(),
+                    question: Some(question),
+                });
+                self.mode = Mode::Password;
+            }
+        }
+        self.update_cast();
+        if *self.action_status.lock().unwrap() == ActionStatus::Stopped {
+            self.command_started = None;
+            self.wait_deadline = None;
+        }
+        if let Some(start) = self.command_started {
+            self.elapsed = start.elapsed();
+        }
+        if let Some(deadline) = self.wait_deadline {
+            self.remaining = deadline.saturating_duration_since(Instant::now());
+        }
+        if let Some(started) = self.started.filter(|_| !self.finished) {
+            self.total = Some(started.elapsed());
+        }
+        if self.mode == Mode::Normal
+            && self
+                .failure()
+                .is_some_and(|failure| failure.on_error == OnError::Prompt)
+        {
+            self.mode = Mode::Prompt;
+        }
+        self.autoplay()?;
+        if let Some(attract_after) = self.attract_after {
+            // A password being entered is not given up on, and a presentation
+            // playing by itself, or still typing a message, is not idle.
+            if !matches!(self.mode, Mode::Attract | Mode::Password | Mode::Confirm)
+                && self.autoplay.is_none()
+                && self.command_started.is_none()
+                && self.action_status.lock().unwrap().idle()
+                && self.last_input.elapsed() >= attract_after
+            {
+                self.mode = Mode::Attract;
+                self.last_input = Instant::now();
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves on once the current action is done and the autoplay delay has
+    /// passed, leaving the splash screen first and starting over once
+    /// finished if looping.
+    ///
+    /// Pauses, failed commands and questions to the presenter hold autoplay
+    /// as they would the presenter, and so does any key press for a while.
+    fn autoplay(&mut self) -> Result<()> {
+        let Some(delay) = self.autoplay else {
+            return Ok(());
+        };
+        if self.autoplay_paused {
+            if self.last_input.elapsed() < self.autoplay_idle {
+                self.autoplay_since = None;
+                return Ok(());
+            }
+            info!("Resuming autoplay");
+            self.autoplay_paused = false;
+        }
+        let ready = matches!(self.mode, Mode::Normal | Mode::Splash)
+            && *self.action_status.lock().unwrap() == ActionStatus::Stopped
+            && !self.connecting
+            && self.failure().is_none()
+            && (!self.finished || self.autoplay_loop);
+        if !ready {
+            self.autoplay_since = None;
+            return Ok(());
+        }
+        if self
+            .autoplay_since
+            .get_or_insert_with(Instant::now)
+            .elapsed()
+            < delay
+        {
+            return Ok(());
+        }
+        self.autoplay_since = None;
+        if self.mode == Mode::Splash {
+            self.mode = Mode::Normal;
+        } else if self.finished {
+            self.reset();
+        } else {
+            self.next_action()?;
+        }
+        Ok(())
+    }
+
+    /// Releases the presentation held by a failed command.
+    fn acknowledge_failure(&mut self) {
+        self.failure.lock().unwrap().take();
+        self.mode = Mode::Normal;
+    }
+
+    /// Starts the presentation over, once the running action, if any, is
+    /// interrupted. The SSH sessions are kept.
+    fn restart(&mut self) {
+        let mut action_status = self.action_status.lock().unwrap();
+        match *action_status {
+            ActionStatus::Running => {
+                *action_status = ActionStatus::Forced;
+                self.force_stops += 1;
+                self.restart_pending = true;
+            }
+            ActionStatus::Forced => self.restart_pending = true,
+            ActionStatus::Waiting | ActionStatus::Stopped => {
+                *action_status = ActionStatus::Stopped;
+                drop(action_status);
+                self.reset();
+            }
+        }
+    }
+
+    /// Goes back to the title of the first stage, as if freshly started.
+    fn reset(&mut self) {
+        info!("Restarting the presentation");
+        // Output left to record belongs to the run being left.
+        self.take_output();
+        self.unrecorded = None;
+        self.failure.lock().unwrap().take();
+        self.last_success.lock().unwrap().take();
+        self.started = None;
+        self.total = None;
+        self.leave_stage();
+        self.prefetched.lock().unwrap().take();
+        self.captures.lock().unwrap().clear();
+        self.variables.lock().unwrap().clear();
+        self.stage_idx = 0;
+        self.action_idx = 0;
+        self.finished = false;
+        self.selected = None;
+        self.search = None;
+        self.password_prompt = None;
+        self.confirmation = None;
+        self.confirmed = None;
+        self.connecting = false;
+        self.scroll = 0;
+        self.mode = Mode::Normal;
+        // Also clears the history, as the title is the only output left.
+        self.write_title();
+    }
+
+    fn next_action_idx(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.action_idx += 1;
+
+        let stage = &self.config.stages[self.stage_idx];
+        if stage.actions.len() == self.action_idx {
+            if self.config.stages.len() == self.stage_idx + 1 {
+                self.finished = true;
+            } else {
+                self.stage_idx += 1;
+                self.action_idx = 0;
+            }
+        }
+    }
+
+    fn prev_action(&mut self) {
+        let mut action_status = self.action_status.lock().unwrap();
+        if !action_status.idle() {
+            return;
+        }
+        *action_status = ActionStatus::Stopped;
+        drop(action_status);
+        if self.finished {
+            self.finished = false;
+        }
+        self.failure.lock().unwrap().take();
+        // Drop any session opened ahead for an action we are moving away from,
+        // and reconnect afresh when going forward again.
+        self.prefetched.lock().unwrap().take();
+        self.sessions.clear();
+        if self.action_idx == 0 {
+            if self.stage_idx == 0 {
+                return;
+            }
+            self.stage_idx -= 1;
+            if self.history.is_empty() {
+                self.write_title();
+                self.enter_stage(self.stage_idx);
+                return;
+            }
+            // The previous stage is still shown, as the next one did not
+            // start yet: take back its last action.
+            self.action_idx = self.config.stages[self.stage_idx].actions.len();
+        }
+
+        self.action_idx -= 1;
+        let step = self.history.pop();
+        if let Some(step) = &step {
+            // Conditions see the same status as when the action first ran.
+            *self.last_success.lock().unwrap() = step.last_success;
+        }
+        let mut buffer = self.buffer.lock().unwrap();
+        match step.map(|step| step.undo) {
+            Some(Undo::Truncate(len)) => buffer.truncate(len),
+            Some(Undo::Restore(entries)) => {
+                buffer.truncate(1);
+                buffer.extend(entries);
+            }
+            None => {
+                buffer.pop();
+            }
+        }
+    }
+
+    /// Shows the table of contents, highlighting the current stage.
+    fn open_contents(&mut self) {
+        if !self.action_status.lock().unwrap().idle() {
+            return;
+        }
+        self.contents.select(Some(self.stage_idx));
+        self.mode = Mode::Contents;
+    }
+
+    /// Moves to the title of `stage_idx`, ready to run its first action.
+    ///
+    /// The actions in between are skipped rather than run, whichever way
+    /// the jump goes, the same as going back to the start of a stage.
+    fn jump_to_stage(&mut self, stage_idx: usize) {
+        let mut action_status = self.action_status.lock().unwrap();
+        if !action_status.idle() {
+            return;
+        }
+        *action_status = ActionStatus::Stopped;
+        drop(action_status);
+        self.failure.lock().unwrap().take();
+        self.prefetched.lock().unwrap().take();
+        self.sessions.clear();
+        self.finished = false;
+        self.selected = None;
+        self.scroll = 0;
+        self.stage_idx = stage_idx;
+        self.action_idx = 0;
+        self.write_title();
+        self.enter_stage(stage_idx);
+    }
+
+    /// Runs the teardown hook of the stage being left, if any, and the setup
+    /// hook of `stage_idx` when it is not the stage already entered.
+    fn enter_stage(&mut self, stage_idx: usize) {
+        if self.entered_stage == Some(stage_idx) {
+            return;
+        }
+        self.leave_stage();
+        info!(stage = %self.config.stages[stage_idx].name, "Entering stage");
+        if let Some(setup) = self.config.stages[stage_idx].setup.clone() {
+            self.run_hook("setup", &setup);
+        }
+        self.entered_stage = Some(stage_idx);
+    }
+
+    /// Runs the teardown hook of the entered stage, if any, and closes the
+    /// SSH sessions of its actions.
+    fn leave_stage(&mut self) {
+        self.sessions.clear();
+        self.dismiss_preflight();
+        self.preflight_stage = None;
+        let Some(stage_idx) = self.entered_stage.take() else {
+            return;
+        };
+        if let Some(teardown) = self.config.stages[stage_idx].teardown.clone() {
+            self.run_hook("teardown", &teardown);
+        }
+    }
+
+    /// Runs a stage hook locally and silently, only reporting failures.
+    fn run_hook(&mut self, hook: &str, command: &CommandType) {
+        if self.dry_run {
+            self.write_buf(
+                format!(
+                    "Stage {}: {}\n{}",
+                    hook,
+                    command.get_command(),
+                    DRY_RUN_MARKER
+                ),
+                Some(StyleConfig::muted()),
+                Alignment::Left,
+            );
+            return;
+        }
+        let result =
+            CommandSession::new(command, None, None, &self.sessions).and_then(|mut session| {
+                self.apply_session_defaults(&mut session);
+                session.run_command()?;
+                match session.get_exit_code() {
+                    Some(0) => Ok(()),
+                    code => {
+                        let code = code.map_or_else(|| String::from("none"), |c| c.to_string());
+                        let stderr = redact::apply(&self.config.redactions, &session.get_stderr());
+                        match stderr.trim() {
+                            "" => anyhow::bail!("exit code {}", code),
+                            stderr => anyhow::bail!("exit code {}: {}", code, stderr),
+                        }
+                    }
+                }
+            });
+        if let Err(e) = result {
+            warn!(hook, error = %e, "Stage hook failed");
+            self.write_buf(
+                format!(
+                    "Stage {} failed.\n    Command: {}\n    Error:   {}",
+                    hook,
+                    command.get_command(),
+                    e
+                ),
+                Some(StyleConfig::error()),
+                Alignment::Left,
+            );
+        }
+    }
+
+    /// Position of the action that follows the current one, if any.
+    fn upcoming_position(&self) -> Option<(usize, usize)> {
+        if self.action_idx + 1 < self.config.stages[self.stage_idx].actions.len() {
+            Some((self.stage_idx, self.action_idx + 1))
+        } else if self.stage_idx + 1 < self.config.stages.len() {
+            Some((self.stage_idx + 1, 0))
+        } else {
+            None
+        }
+    }
+
+    /// Opens the session of the upcoming action in the background when it
+    /// is a remote command, so that it runs instantly once reached.
+    fn prefetch_upcoming(&mut self) {
+        let Some(position) = self.upcoming_position() else {
+            return;
+        };
+        let config::Action::Command(command_config) = self.config.stages[position.0].actions
+            [position.1]
+            .action
+            .clone()
+        else {
+            return;
+        };
+        let CommandConfig {
+            command,
+            mut sudo,
+            remote: Some(Remotes::Single(remote)),
+            ..
+        } = *command_config
+        else {
+            return;
+        };
+        let mut remote = Some(remote);
+        // Passwords are not asked for ahead of time.
+        if !self.fill_passwords(&mut remote, &mut sudo) {
+            return;
+        }
+
+        let prefetched = self.prefetched.clone();
+        let sessions = self.sessions.clone();
+        self.spawn_worker(move || {
+            if let Ok(session) = CommandSession::new(&command, remote, sudo, &sessions) {
+                *prefetched.lock().unwrap() = Some(Prefetched { position, session });
+            }
+        });
+    }
+
+    /// Takes the prefetched session if it was opened for the current action.
+    fn take_prefetched(&self, position: (usize, usize)) -> Option<CommandSession> {
+        let mut prefetched = self.prefetched.lock().unwrap();
+        match prefetched.take() {
+            Some(p) if p.position == position => Some(p.session),
+            _ => None,
+        }
+    }
+
+    fn next_action(&mut self) -> Result<()> {
+        // Connecting cannot be interrupted, but its questions can be
+        // cancelled.
+        if self.connecting {
+            return Ok(());
+        }
+        if *self.action_status.lock().unwrap() == ActionStatus::Running {
+            *self.action_status.lock().unwrap() = ActionStatus::Forced;
+            self.force_stops += 1;
+            return Ok(());
+        }
+        if *self.action_status.lock().unwrap() == ActionStatus::Waiting {
+            // Moving on ends the pause.
+            *self.action_status.lock().unwrap() = ActionStatus::Stopped;
+        }
+        if self.failure().is_some() {
+            // The first key press only acknowledges the failure.
+            self.acknowledge_failure();
+            return Ok(());
+        }
+        if self.finished {
+            return Ok(());
+        }
+        let action_config = &self.config.stages[self.stage_idx].actions[self.action_idx];
+        if let Some(reason) = self.unmet_condition(action_config.when.unwrap_or_default()) {
+            self.write_skipped(format!("[skipped: {}]", reason));
+            return Ok(());
+        }
+        if let Some(confirmation) = self.needed_confirmation(&action_config.action) {
+            // Nobody is there to confirm a headless run.
+            if self.headless {
+                self.write_skipped(String::from("[skipped: not confirmed]"));
+            } else {
+                self.confirmation = Some(confirmation);
+                self.mode = Mode::Confirm;
+            }
+            return Ok(());
+        }
+        let action_config = &self.config.stages[self.stage_idx].actions[self.action_idx];
+        if let Some(label) = self
+            .missing_password(&action_config.action)
+            .filter(|_| !self.dry_run)
+        {
+            self.password_prompt = Some(PasswordPrompt {
+                label,
+                input: String::new(),
+                question: None,
+            });
+            self.mode = Mode::Password;
+            return Ok(());
+        }
+        self.actions_run += 1;
+        self.started.get_or_insert_with(Instant::now);
+        self.take_output();
+        if self.action_idx == 0 && self.stage_idx > 0 {
+            self.write_title();
+        }
+        self.enter_stage(self.stage_idx);
+        if self.start_preflight() {
+            return Ok(());
+        }
+        if self.connect_ahead() {
+            return Ok(());
+        }
+        self.start_action()
+    }
+
+    /// Checks in the background that the remote hosts of the current stage
+    /// set to be checked can be logged into, running `true` on each, as the
+    /// user of its sudo if any. The sessions opened are kept for the
+    /// actions. Returns whether the action waits for the checks, to be
+    /// started by a later tick.
+    ///
+    /// The hosts are checked once per stage entered. Those whose passwords
+    /// are yet to be entered are left to the actions.
+    fn start_preflight(&mut self) -> bool {
+        if self.headless || self.dry_run || self.preflight_stage == Some(self.stage_idx) {
+            return false;
+        }
+        self.preflight_stage = Some(self.stage_idx);
+        let mut hosts = Vec::new();
+        let mut checks = Vec::new();
+        for action_config in &self.config.stages[self.stage_idx].actions {
+            let actions = match &action_config.action {
+                config::Action::Parallel { actions } => {
+                    actions.iter().map(|child| &child.action).collect()
+                }
+                action => vec![action],
+            };
+            for action in actions {
+                let (remotes, sudo) = Self::action_remotes(action);
+                for remote in remotes.into_iter().flatten() {
+                    if !self.preflight && !remote.preflight.unwrap_or(false) {
+                        continue;
+                    }
+                    let mut label = match &remote.user {
+                        Some(user) => format!("{}@{}", user, remote.host),
+                        None => remote.host.clone(),
+                    };
+                    if let Some(sudo) = sudo {
+                        label.push_str(&format!(
+                            " (sudo {})",
+                            sudo.user.as_deref().unwrap_or("root")
+                        ));
+                    }
+                    let mut remote = Some(remote);
+                    let mut sudo = sudo.clone();
+                    if checks
+                        .iter()
+                        .any(|check: &PreflightCheck| check.label == label)
+                        || !self.fill_passwords(&mut remote, &mut sudo)
+                    {
+                        continue;
+                    }
+                    hosts.push((remote.unwrap(), sudo));
+                    checks.push(PreflightCheck {
+                        label,
+                        outcome: PreflightOutcome::Checking,
+                    });
+                }
+            }
+        }
+        if hosts.is_empty() {
+            return false;
+        }
+        info!(hosts = hosts.len(), "Checking the hosts of the stage");
+        *self.preflight_checks.lock().unwrap() = checks;
+        let exec_status = self.action_status.clone();
+        *exec_status.lock().unwrap() = ActionStatus::Running;
+        self.command_started = Some(Instant::now());
+        self.elapsed = Duration::ZERO;
+        self.connecting = true;
+        let sessions = self.sessions.clone();
+        let preflight_checks = self.preflight_checks.clone();
+        self.spawn_worker(move || {
+            thread::scope(|scope| {
+                for (idx, (remote, sudo)) in hosts.into_iter().enumerate() {
+                    let sessions = &sessions;
+                    let preflight_checks = &preflight_checks;
+                    scope.spawn(move || {
+                        let outcome = match Self::check_host(remote, sudo, sessions) {
+                            Ok(()) => PreflightOutcome::Passed,
+                            Err(e) => {
+                                warn!(error = format!("{:#}", e), "Host check failed");
+                                PreflightOutcome::Failed(format!("{:#}", e))
+                            }
+                        };
+                        // Checks dismissed meanwhile stay so.
+                        if let Some(check) = preflight_checks.lock().unwrap().get_mut(idx) {
+                            check.outcome = outcome;
+                        }
+                    });
+                }
+            });
+            // Nothing is left to show once every host passed.
+            let mut preflight_checks = preflight_checks.lock().unwrap();
+            if preflight_checks
+                .iter()
+                .all(|check| check.outcome == PreflightOutcome::Passed)
+            {
+                preflight_checks.clear();
+            }
+            drop(preflight_checks);
+            *exec_status.lock().unwrap() = ActionStatus::Stopped;
+        });
+        true
+    }
+
+    /// Logs into `remote`, as the user of `sudo` if set, and runs `true`.
+    fn check_host(
+        remote: RemoteConfig,
+        sudo: Option<SudoConfig>,
+        sessions: &SessionCache,
+    ) -> Result<()> {
+        let command = CommandType::Single(String::from("true"));
+        let mut session = CommandSession::new(&command, Some(remote), sudo, sessions)?;
+        session.run_command()?;
+        match session.get_exit_code() {
+            Some(0) => Ok(()),
+            code => {
+                let stderr = session.get_stderr();
+                let reason = stderr.lines().find(|line| !line.trim().is_empty());
+                let code = code.map_or_else(|| String::from("none"), |c| c.to_string());
+                match reason {
+                    Some(reason) => bail!("'true' exited with code {}: {}", code, reason.trim()),
+                    None => bail!("'true' exited with code {}", code),
+                }
+            }
+        }
+    }
+
+    /// Hides the checks of the remote hosts.
+    fn dismiss_preflight(&mut self) {
+        self.preflight_checks.lock().unwrap().clear();
+    }
+
+    /// Opens in the background the sessions of the next action whose servers
+    /// ask questions, so that the presenter can answer them meanwhile.
+    /// Returns whether the action waits for them, to be started by a later
+    /// tick.
+    fn connect_ahead(&mut self) -> bool {
+        if self.headless || self.dry_run {
+            return false;
+        }
+        let action = &self.config.stages[self.stage_idx].actions[self.action_idx].action;
+        let remotes: Vec<RemoteConfig> = Self::action_remotes(action)
+            .0
+            .into_iter()
+            .filter_map(|mut remote| {
+                self.fill_passwords(&mut remote, &mut None);
+                remote
+            })
+            .filter(|remote| remote.interactive() && self.sessions.needs_connecting(remote))
+            .collect();
+        if remotes.is_empty() {
+            return false;
+        }
+        info!(hosts = remotes.len(), "Connecting ahead of the action");
+        let exec_status = self.action_status.clone();
+        *exec_status.lock().unwrap() = ActionStatus::Running;
+        self.command_started = Some(Instant::now());
+        self.elapsed = Duration::ZERO;
+        self.connecting = true;
+        let sessions = self.sessions.clone();
+        self.spawn_worker(move || {
+            for remote in remotes {
+                sessions.open_ahead(remote);
+            }
+            *exec_status.lock().unwrap() = ActionStatus::Stopped;
+        });
+        true
+    }
+
+    /// The action at `position`, with the variables captured so far filled
+    /// in.
+    fn expand_captured(&self, position: (usize, usize)) -> Result<ActionConfig> {
+        let action_config = &self.config.stages[position.0].actions[position.1];
+        if self.config.captured.is_empty() {
+            return Ok(action_config.clone());
+        }
+        action_config.expand_captured(&self.config.captured, &self.variables.lock().unwrap())
+    }
+
+    /// Writes the error that kept an action from starting, which counts as
+    /// a failure.
+    fn write_start_error(&mut self, error: anyhow::Error) {
+        warn!(error = format!("{:#}", error), "Failed to start action");
+        self.write_buf(
+            format!("Failed to start action: {:#}", error),
+            Some(StyleConfig::error()),
+            Alignment::Left,
+        );
+        *self.last_success.lock().unwrap() = Some(false);
+        *self.action_status.lock().unwrap() = ActionStatus::Stopped;
+    }
+
+    /// Runs the next action, once its stage is entered.
+    fn start_action(&mut self) -> Result<()> {
+        self.confirmed = None;
+        let position = (self.stage_idx, self.action_idx);
+        // An action that cannot be expanded still takes its turn, to show
+        // why it did not run.
+        let (action_config, error) = match self.expand_captured(position) {
+            Ok(action_config) => (action_config, None),
+            Err(e) => (
+                self.config.stages[position.0].actions[position.1].clone(),
+                Some(e),
+            ),
+        };
+        let label = action_config.label;
+        let action = action_config.action;
+        let kind = match &action {
+            config::Action::Message { .. } => "message",
+            config::Action::Command(_) => "command",
+            config::Action::WaitFor(_) => "wait_for",
+            config::Action::Upload(_) => "upload",
+            config::Action::Download(_) => "download",
+            config::Action::Pause { .. } => "pause",
+            config::Action::Wait { .. } => "wait",
+            config::Action::Clear => "clear",
+            config::Action::Parallel { .. } => "parallel",
+        };
+        debug!(
+            stage = self.stage_idx,
+            action = self.action_idx,
+            kind,
+            "Starting action"
+        );
+        let len = self.buffer.lock().unwrap().len();
+        let undo = match action {
+            // The stage title is kept.
+            config::Action::Clear => {
+                Undo::Restore(self.buffer.lock().unwrap().split_off(len.min(1)))
+            }
+            _ => Undo::Truncate(len),
+        };
+        self.push_step(undo);
+        // The output of the first action of a stage includes its title.
+        let start = if self.action_idx == 0 { 0 } else { len };
+        self.unrecorded = Some((self.stage_idx, start));
+        if let Some(label) = label {
+            self.write_buf(
+                format!("== {} ==", label),
+                Some(StyleConfig::label()),
+                Alignment::Left,
+            );
+        }
+        if let Some(e) = error {
+            self.write_start_error(e);
+            self.next_action_idx();
+            return Ok(());
+        }
+        match action {
+            config::Action::Message {
+                text,
+                style,
+                speed,
+                reveal,
+                align,
+                prefix,
+            } => {
+                let stage = &self.config.stages[self.stage_idx];
+                let prefix = prefix
+                    .or_else(|| stage.prefix.clone())
+                    .or_else(|| self.config.prefix.clone())
+                    .unwrap_or_else(|| String::from("> "));
+                let style = self.effective_style(self.stage_idx, style);
+                self.write_message(
+                    text,
+                    Some(style),
+                    if self.headless {
+                        0
+                    } else {
+                        (speed.unwrap() as f64 * self.speed_multiplier).round() as u64
+                    },
+                    reveal.unwrap_or_default(),
+                    align.unwrap_or_default(),
+                    prefix,
+                );
+            }
+            config::Action::Command(command_config) => {
+                self.run_command(*command_config, (self.stage_idx, self.action_idx))?;
+            }
+            config::Action::Parallel { actions } => {
+                self.run_parallel(actions, (self.stage_idx, self.action_idx))?;
+            }
+            config::Action::WaitFor(wait_config) => {
+                self.wait_for(wait_config, (self.stage_idx, self.action_idx))?;
+            }
+            config::Action::Upload(transfer_config) => {
+                let position = (self.stage_idx, self.action_idx);
+                self.transfer(Direction::Upload, transfer_config, position)?;
+            }
+            config::Action::Download(transfer_config) => {
+                let position = (self.stage_idx, self.action_idx);
+                self.transfer(Direction::Download, transfer_config, position)?;
+            }
+            config::Action::Pause { .. } | config::Action::Wait { .. } if self.headless => {}
+            config::Action::Pause { prompt } => {
+                let prompt = prompt.unwrap_or_else(|| {
+                    format!(
+                        "Press <{}> to continue…",
+                        self.keymap.label(KeyAction::Next)
+                    )
+                });
+                self.write_buf(prompt, Some(StyleConfig::muted()), Alignment::Left);
+                *self.action_status.lock().unwrap() = ActionStatus::Waiting;
+            }
+            config::Action::Wait { millis } => self.wait(Duration::from_millis(millis)),
+            // Cleared above.
+            config::Action::Clear => {}
+        };
+        self.next_action_idx();
+
+        Ok(())
+    }
+
+    /// Moves past the next action without running it, leaving a marker in
+    /// its place. Going back takes the marker back like any output.
+    fn skip_action(&mut self) {
+        let mut action_status = self.action_status.lock().unwrap();
+        if !action_status.idle() {
+            return;
+        }
+        *action_status = ActionStatus::Stopped;
+        drop(action_status);
+        if self.failure().is_some() {
+            self.acknowledge_failure();
+            return;
+        }
+        if self.finished {
+            return;
+        }
+        self.write_skipped(String::from("[skipped]"));
+    }
+
+    /// Remembers how to take back the action about to run.
+    fn push_step(&mut self, undo: Undo) {
+        let last_success = *self.last_success.lock().unwrap();
+        self.history.push(Step { undo, last_success });
+    }
+
+    /// Moves past the next action, writing `marker` in place of its output.
+    fn write_skipped(&mut self, marker: String) {
+        self.take_output();
+        if self.action_idx == 0 && self.stage_idx > 0 {
+            self.write_title();
+        }
+        self.enter_stage(self.stage_idx);
+        let len = self.buffer.lock().unwrap().len();
+        self.push_step(Undo::Truncate(len));
+        let start = if self.action_idx == 0 { 0 } else { len };
+        self.unrecorded = Some((self.stage_idx, start));
+        self.write_buf(marker, Some(StyleConfig::muted()), Alignment::Left);
+        self.next_action_idx();
+    }
+
+    /// Why an action to run `when` is skipped, given the exit status of the
+    /// last command run, or `None` if it runs.
+    fn unmet_condition(&self, when: When) -> Option<&'static str> {
+        let last_success = *self.last_success.lock().unwrap();
+        match (when, last_success) {
+            (When::OnSuccess, Some(false)) => Some("the last command failed"),
+            (When::OnFailure, Some(true)) => Some("the last command succeeded"),
+            (When::OnFailure, None) => Some("no command ran yet"),
+            _ => None,
+        }
+    }
+
+    fn write_message(
+        &mut self,
+        text: String,
+        style: Option<StyleConfig>,
+        speed: u64,
+        reveal: Reveal,
+        align: config::Align,
+        prefix: String,
+    ) {
+        let exec_status = self.action_status.clone();
+        *exec_status.lock().unwrap() = ActionStatus::Running;
+
+        self.write_buf(prefix, style, align.into());
+        if self.config.prefetch.unwrap_or(false) {
+            self.prefetch_upcoming();
+        }
+        let buffer = self.buffer.clone();
+        let entry = buffer.lock().unwrap().len() - 1;
+        buffer.lock().unwrap()[entry].links = true;
+        self.spawn_worker(move || {
+            let mut starts: Vec<usize> = match reveal {
+                Reveal::Char => text.char_indices().map(|(idx, _)| idx).collect(),
+                // A word starts where a non-space follows a space.
+                Reveal::Word => text
+                    .char_indices()
+                    .scan(true, |space, (idx, c)| {
+                        let start = *space && !c.is_whitespace();
+                        *space = c.is_whitespace();
+                        Some((idx, start))
+                    })
+                    .filter(|&(idx, start)| start || idx == 0)
+                    .map(|(idx, _)| idx)
+                    .collect(),
+                Reveal::Instant => vec![0],
+            };
+            starts.push(text.len());
+            for chunk in starts.windows(2) {
+                if exec_status.lock().unwrap().force_stop() {
+                    // Print the rest of the string all at once.
+                    Self::add_to_buf(buffer, entry, &text[chunk[0]..text.len()], false);
+                    break;
+                }
+                buffer.lock().unwrap()[entry].push_str(&text[chunk[0]..chunk[1]]);
+                if reveal != Reveal::Instant {
+                    thread::sleep(Duration::from_millis(speed));
+                }
+            }
+            *exec_status.lock().unwrap() = ActionStatus::Stopped;
+        });
+    }
+
+    fn run_command(
+        &mut self,
+        command_config: CommandConfig,
+        position: (usize, usize),
+    ) -> Result<()> {
+        let parallel = command_config.parallel.unwrap_or(false);
+        self.start_commands();
+        if let Some(jobs) = self.command_jobs(command_config, position) {
+            self.run_jobs(jobs, parallel, Arc::default());
+        }
+        Ok(())
+    }
+
+    /// Runs the commands of a parallel group all at once, as a single step.
+    ///
+    /// A command failing with `on_error: stop` interrupts the others.
+    fn run_parallel(&mut self, actions: Vec<ActionConfig>, position: (usize, usize)) -> Result<()> {
+        self.start_commands();
+        let stopped_by: Arc<Mutex<Option<Failure>>> = Arc::default();
+        let mut jobs = Vec::new();
+        for child in actions {
+            let config::Action::Command(command_config) = child.action else {
+                continue;
+            };
+            if let Some(label) = child.label {
+                self.write_buf(
+                    format!("== {} ==", label),
+                    Some(StyleConfig::label()),
+                    Alignment::Left,
+                );
+            }
+            let on_error = command_config.on_error.unwrap_or_default();
+            // A command that cannot start fails without keeping the others
+            // from running.
+            let child_jobs = self
+                .command_jobs(*command_config, position)
+                .unwrap_or_else(|| {
+                    vec![Job {
+                        on_error,
+                        run: Box::new(|| None),
+                    }]
+                });
+            for Job { on_error, run } in child_jobs {
+                let status = self.action_status.clone();
+                let stopped_by = stopped_by.clone();
+                let run = move || {
+                    let exit_code = run();
+                    if on_error == OnError::Stop && exit_code != Some(0) {
+                        let mut status = status.lock().unwrap();
+                        let mut stopped_by = stopped_by.lock().unwrap();
+                        if !status.force_stop() && stopped_by.is_none() {
+                            *stopped_by = Some(Failure {
+                                on_error,
+                                exit_code,
+                            });
+                            *status = ActionStatus::Forced;
+                        }
+                    }
+                    exit_code
+                };
+                jobs.push(Job {
+                    on_error,
+                    run: Box::new(run),
+                });
+            }
+        }
+        self.run_jobs(jobs, true, stopped_by);
+        Ok(())
+    }
+
+    /// Marks the action as running commands, none having succeeded yet.
+    fn start_commands(&mut self) {
+        *self.action_status.lock().unwrap() = ActionStatus::Running;
+        self.command_started = Some(Instant::now());
+        self.elapsed = Duration::ZERO;
+        // Failing to run the command at all counts as a failure.
+        *self.last_success.lock().unwrap() = Some(false);
+    }
+
+    /// Opens the sessions of a command, one per host, and writes their
+    /// prompts. Returns the jobs running it, or `None` when it cannot run,
+    /// the error written and the action stopped.
+    fn command_jobs(
+        &mut self,
+        command_config: CommandConfig,
+        position: (usize, usize),
+    ) -> Option<Vec<Job>> {
+        let CommandConfig {
+            command,
+            sudo,
+            hide_stdout,
+            hide_stderr,
+            style,
+            remote,
+            parallel: _,
+            r#loop,
+            retry,
+            output_prefix,
+            diff,
+            merge_streams,
+            strip_ansi,
+            max_output_bytes,
+            on_error,
+            shell,
+            shell_args,
+            cwd,
+            stdin,
+            timeout_ms,
+            confirm: _,
+            confirm_prompt: _,
+            capture: variable,
+        } = command_config;
+        let style = Some(self.effective_style(position.0, style));
+        let on_error = on_error.unwrap_or_default();
+        let diff = diff.unwrap_or(false);
+        let merge_streams = merge_streams.unwrap_or(false);
+        let hide_stdout = hide_stdout.unwrap();
+        let hide_stderr = hide_stderr.unwrap();
+        let show_timing = self.show_timing;
+        let loop_config = r#loop.unwrap();
+        let timeout = timeout_ms.map(Duration::from_millis);
+        let output_prefix = output_prefix
+            .or_else(|| self.config.output_prefix.clone())
+            .unwrap_or_default();
+
+        // A list of hosts fans the command out, each host writing to an entry
+        // of its own.
+        let (hosts, fan_out) = match remote {
+            Some(Remotes::Multiple(remotes)) => (remotes.into_iter().map(Some).collect(), true),
+            Some(Remotes::Single(remote)) => (vec![Some(remote)], false),
+            None => (vec![None], false),
+        };
+        let mut jobs = Vec::new();
+        for remote in hosts {
+            let host = remote.as_ref().map(|remote| remote.host.clone());
+            let mut command_session =
+                match self.new_session(&command, remote, sudo.clone(), position) {
+                    Ok(session) => session,
+                    // A host that cannot be reached does not keep the others from
+                    // running.
+                    Err(e) if fan_out => {
+                        self.write_error(
+                            &format!("[{}] Failed to initialize a new session.", host.unwrap()),
+                            &command,
+                            e,
+                        );
+                        continue;
+                    }
+                    Err(e) => {
+                        self.fail_action("Failed to initialize a new session.", &command, e);
+                        return None;
+                    }
+                };
+            if let Some(limit) = max_output_bytes {
+                command_session.set_max_output_bytes(limit);
+            }
+            if let Some(shell) = &shell {
+                command_session.set_shell(shell.clone(), shell_args.clone());
+            }
+            if let Some(cwd) = &cwd {
+                if let Err(e) = command_session.set_cwd(cwd.clone()) {
+                    self.fail_action("Invalid working directory.", &command, e);
+                    return None;
+                }
+            }
+            if let Some(stdin) = &stdin {
+                command_session.set_stdin(stdin.clone());
+            }
+
+            let prompt = match command_session.get_prompt() {
+                Ok(prompt) => prompt,
+                Err(e) => {
+                    self.fail_action("Failed to run command.", &command, e);
+                    return None;
+                }
+            };
+            // The output of each host is told apart by its name, and so is
+            // the output it is diffed against.
+            let (prefix, capture) = match command_session.get_remote_host() {
+                Some(host) if fan_out => (
+                    format!("[{}] {}", host, output_prefix),
+                    format!("[{}] {}", host, command.get_command()),
+                ),
+                _ => (output_prefix.clone(), command.get_command()),
+            };
+            self.write_buf(prompt, style.clone(), Alignment::Left);
+            let mut buffer = self.buffer.lock().unwrap();
+            let entry = buffer.len() - 1;
+            buffer[entry].origin = Some(position);
+            buffer[entry].ansi = !strip_ansi.unwrap_or(false);
+            buffer[entry].max_lines = self.config.max_lines;
+            if self.dry_run {
+                buffer[entry].push_styled(DRY_RUN_MARKER, StyleConfig::muted());
+                continue;
+            }
+            jobs.push((command_session, entry, prefix, capture));
+        }
+
+        let buffer = self.buffer.clone();
+        let records = self.records.clone();
+        let captures = self.captures.clone();
+        let variables = self.variables.clone();
+        let redactions = self.config.redactions.clone();
+        let stage = self.config.stages[position.0].name.clone();
+        let status = self.action_status.clone();
+        // Runs the command on the session of one host, writing to its entry,
+        // and returns the exit code.
+        let run = move |mut command_session: CommandSession,
+                        entry: usize,
+                        output_prefix: String,
+                        capture: String| {
+            let exec_status = status;
+            let start = Instant::now();
+            let times = loop_config.max_runs();
+            let delay = loop_config.delay.unwrap();
+            let until_success = loop_config.until_success.unwrap_or(false);
+            let loop_deadline = loop_config
+                .duration_ms
+                .map(|duration| start + Duration::from_millis(duration));
+            let attempts = retry.as_ref().map_or(1, |retry| retry.attempts.max(1));
+            let mut attempt = 1;
+            let mut retry_delay = 0.0;
+            let mut repetition = 0;
+            while repetition < times {
+                if repetition > 0
+                    && loop_deadline.is_some_and(|deadline| Instant::now() >= deadline)
+                {
+                    break;
+                }
+                if exec_status.lock().unwrap().force_stop() {
+                    Self::add_to_buf(buffer.clone(), entry, "Command interrupted!\n", hide_stdout);
+                    break;
+                }
+                command_session.set_iteration(repetition);
+
+                // A diff needs the whole output, so it is shown once the
+                // command finishes instead of streamed.
+                let previous = captures.lock().unwrap().get(&capture).cloned();
+                let previous = previous.filter(|_| diff && !hide_stdout);
+                let stream_stdout = !hide_stdout && previous.is_none();
+                // Merged streams share the line they are written to.
+                let mut at_line_start = true;
+                let mut stdout_lines = redact::Lines::default();
+                let mut stderr_lines = redact::Lines::default();
+                // A run that outlasts the timeout is stopped like a forced
+                // one, but told apart from it.
+                let deadline = timeout.map(|timeout| Instant::now() + timeout);
+                let timed_out = Cell::new(false);
+                let result = command_session.run_command_streaming(
+                    |stream, text| match stream {
+                        OutputStream::Stdout if stream_stdout => {
+                            let text = stdout_lines.push(&redactions, text);
+                            let text =
+                                Self::prefix_chunk(&text, &output_prefix, &mut at_line_start);
+                            Self::add_to_buf(buffer.clone(), entry, &text, false);
+                        }
+                        OutputStream::Stderr if merge_streams && !hide_stderr => {
+                            let text = stderr_lines.push(&redactions, text);
+                            let text =
+                                Self::prefix_chunk(&text, &output_prefix, &mut at_line_start);
+                            if !text.is_empty() {
+                                buffer.lock().unwrap()[entry]
+                                    .push_styled(&text, StyleConfig::stderr());
+                            }
+                        }
+                        _ => {}
+                    },
+                    || {
+                        if exec_status.lock().unwrap().force_stop() {
+                            return true;
+                        }
+                        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                            timed_out.set(true);
+                        }
+                        timed_out.get()
+                    },
+                );
+                // The last line may not end with a newline.
+                let text = stdout_lines.finish(&redactions);
+                let text = Self::prefix_chunk(&text, &output_prefix, &mut at_line_start);
+                Self::add_to_buf(buffer.clone(), entry, &text, false);
+                let text = stderr_lines.finish(&redactions);
+                let text = Self::prefix_chunk(&text, &output_prefix, &mut at_line_start);
+                if !text.is_empty() {
+                    buffer.lock().unwrap()[entry].push_styled(&text, StyleConfig::stderr());
+                }
+                if let Err(e) = result {
+                    let mut buffer = buffer.lock().unwrap();
+                    let entry = &mut buffer[entry];
+                    if !entry.ends_line() {
+                        entry.push_str("\n");
+                    }
+                    entry.push_styled(
+                        &format!("Failed to run command: {:#}\n", e),
+                        StyleConfig::error(),
+                    );
+                    break;
+                }
+                let stdout = redact::apply(
+                    &redactions,
+                    &Self::prefix_lines(&command_session.get_stdout(), &output_prefix),
+                );
+                captures
+                    .lock()
+                    .unwrap()
+                    .insert(capture.clone(), stdout.clone());
+                if let Some(previous) = previous {
+                    Self::add_diff_to_buf(buffer.clone(), entry, &previous, &stdout);
+                }
+                let stderr = redact::apply(
+                    &redactions,
+                    &Self::prefix_lines(&command_session.get_stderr(), &output_prefix),
+                );
+                if !hide_stderr && !merge_streams && !stderr.is_empty() {
+                    let mut buffer = buffer.lock().unwrap();
+                    let entry = &mut buffer[entry];
+                    // Output cut mid-line would otherwise run into the
+                    // error output.
+                    if !entry.ends_line() {
+                        entry.push_str("\n");
+                    }
+                    entry.push_styled(&stderr, StyleConfig::stderr());
+                }
+
+                if exec_status.lock().unwrap().force_stop() {
+                    // The output stopped mid-line.
+                    if !buffer.lock().unwrap()[entry].ends_line() {
+                        Self::add_to_buf(buffer.clone(), entry, "\n", hide_stdout);
+                    }
+                    Self::add_to_buf(buffer.clone(), entry, "Command interrupted!\n", hide_stdout);
+                    break;
+                }
+                if timed_out.get() {
+                    let mut buffer = buffer.lock().unwrap();
+                    let entry = &mut buffer[entry];
+                    if !entry.ends_line() {
+                        entry.push_str("\n");
+                    }
+                    entry.push_styled(
+                        &format!("[timed out after {:.1}s]\n", timeout.unwrap().as_secs_f64()),
+                        StyleConfig::error(),
+                    );
+                    if attempt == attempts {
+                        break;
+                    }
+                }
+                if command_session.get_exit_code() != Some(0) && attempt < attempts {
+                    let retry = retry.as_ref().unwrap();
+                    retry_delay = if attempt == 1 {
+                        retry.delay_ms.unwrap_or(0) as f64
+                    } else {
+                        retry_delay * retry.backoff_factor.unwrap_or(1.0)
+                    };
+                    let delay = Duration::from_secs_f64(retry_delay / 1000.0);
+                    if !delay.is_zero() && !Self::sleep_unless_forced(&exec_status, delay) {
+                        Self::add_to_buf(
+                            buffer.clone(),
+                            entry,
+                            "Command interrupted!\n",
+                            hide_stdout,
+                        );
+                        break;
+                    }
+                    attempt += 1;
+                    let mut buffer = buffer.lock().unwrap();
+                    let entry = &mut buffer[entry];
+                    if !entry.ends_line() {
+                        entry.push_str("\n");
+                    }
+                    entry.push_styled(
+                        &format!("[retry {}/{}]\n", attempt, attempts),
+                        StyleConfig::muted(),
+                    );
+                    continue;
+                }
+                attempt = 1;
+                if repetition == times - 1
+                    || (until_success && command_session.get_exit_code() == Some(0))
+                {
+                    break;
+                }
+                // Waiting past the deadline would only delay the end.
+                let delay = match loop_deadline {
+                    Some(deadline) => Duration::from_millis(delay)
+                        .min(deadline.saturating_duration_since(Instant::now())),
+                    None => Duration::from_millis(delay),
+                };
+                if !delay.is_zero() && !Self::sleep_unless_forced(&exec_status, delay) {
+                    Self::add_to_buf(buffer.clone(), entry, "Command interrupted!\n", hide_stdout);
+                    break;
+                }
+                repetition += 1;
+            }
+            let took = start.elapsed();
+            let exit_code = command_session.get_exit_code();
+            // A run ending in success sets the variable to the output of
+            // its last iteration, the last host to finish winning, masked
+            // as it was shown.
+            if let (Some(variable), Some(0)) = (&variable, exit_code) {
+                let value = redact::apply(&redactions, command_session.get_stdout().trim());
+                variables.lock().unwrap().insert(variable.clone(), value);
+            }
+            records.lock().unwrap().push(CommandRecord {
+                stage,
+                command: command.get_command(),
+                exit_code,
+                duration_ms: took.as_millis(),
+            });
+            let exec_status = exec_status.lock().unwrap();
+            let mut buffer = buffer.lock().unwrap();
+            let entry = &mut buffer[entry];
+            if on_error != OnError::Continue && exit_code != Some(0) && !exec_status.force_stop() {
+                let code = exit_code.map_or_else(|| String::from("none"), |c| c.to_string());
+                entry.push_styled(
+                    &format!("Command failed with exit code {}.\n", code),
+                    StyleConfig::error(),
+                );
+            }
+            if show_timing {
+                if !entry.ends_line() {
+                    entry.push_str("\n");
+                }
+                entry.push_styled(
+                    &format!("[took {:.1}s]\n", took.as_secs_f64()),
+                    StyleConfig::muted(),
+                );
+            }
+            exit_code
+        };
+        let jobs = jobs
+            .into_iter()
+            .map(|(session, entry, prefix, capture)| {
+                let run = run.clone();
+                Job {
+                    on_error,
+                    run: Box::new(move || run(session, entry, prefix, capture)),
+                }
+            })
+            .collect();
+        Some(jobs)
+    }
+
+    /// Runs `jobs` in the background, one after the other or all at once,
+    /// and stops the action once they are all done. The first job failing
+    /// with an `on_error` other than `continue` holds the presentation,
+    /// unless one failing earlier is recorded in `stopped_by`.
+    fn run_jobs(
+        &mut self,
+        jobs: Vec<Job>,
+        parallel: bool,
+        stopped_by: Arc<Mutex<Option<Failure>>>,
+    ) {
+        if self.dry_run {
+            // Conditional actions play as if every command succeeded.
+            *self.last_success.lock().unwrap() = Some(true);
+        }
+        let exec_status = self.action_status.clone();
+        if jobs.is_empty() {
+            *exec_status.lock().unwrap() = ActionStatus::Stopped;
+            return;
+        }
+        // A command that could not start may have stopped the action.
+        *exec_status.lock().unwrap() = ActionStatus::Running;
+
+        self.failure.lock().unwrap().take();
+        let failure = self.failure.clone();
+        let last_success = self.last_success.clone();
+        self.spawn_worker(move || {
+            let on_errors: Vec<OnError> = jobs.iter().map(|job| job.on_error).collect();
+            let exit_codes: Vec<Option<i32>> = if parallel {
+                let workers: Vec<_> = jobs.into_iter().map(|job| thread::spawn(job.run)).collect();
+                workers
+                    .into_iter()
+                    .map(|worker| worker.join().unwrap_or(None))
+                    .collect()
+            } else {
+                jobs.into_iter().map(|job| (job.run)()).collect()
+            };
+            *last_success.lock().unwrap() = Some(exit_codes.iter().all(|&code| code == Some(0)));
+            let mut exec_status = exec_status.lock().unwrap();
+            let first_failure = stopped_by.lock().unwrap().take().or_else(|| {
+                if exec_status.force_stop() {
+                    return None;
+                }
+                on_errors
+                    .into_iter()
+                    .zip(exit_codes)
+                    .find(|&(on_error, exit_code)| {
+                        on_error != OnError::Continue && exit_code != Some(0)
+                    })
+                    .map(|(on_error, exit_code)| Failure {
+                        on_error,
+                        exit_code,
+                    })
+            });
+            if first_failure.is_some() {
+                *failure.lock().unwrap() = first_failure;
+            }
+            *exec_status = ActionStatus::Stopped;
+        });
+    }
+
+    /// Applies the configuration-wide settings of command sessions.
+    fn apply_session_defaults(&self, session: &mut CommandSession) {
+        if let Some(limit) = self.config.max_output_bytes {
+            session.set_max_output_bytes(limit);
+        }
+        if let Some(shell) = &self.config.shell {
+            session.set_shell(shell.clone(), self.config.shell_args.clone());
+        }
+    }
+
+    /// Opens the session for the command at `position`.
+    fn new_session(
+        &mut self,
+        command: &CommandType,
+        mut remote: Option<RemoteConfig>,
+        mut sudo: Option<SudoConfig>,
+        position: (usize, usize),
+    ) -> Result<CommandSession> {
+        self.fill_passwords(&mut remote, &mut sudo);
+        let mut command_session = match self.take_prefetched(position) {
+            Some(session) => session,
+            None => CommandSession::new(command, remote, sudo, &self.sessions)?,
+        };
+        self.apply_session_defaults(&mut command_session);
+        Ok(command_session)
+    }
+
+    /// Opens the session for the command at `position`.
+    ///
+    /// On failure the error is written to the buffer and the action stopped.
+    fn open_session(
+        &mut self,
+        command: &CommandType,
+        remote: Option<RemoteConfig>,
+        sudo: Option<SudoConfig>,
+        position: (usize, usize),
+    ) -> Option<CommandSession> {
+        match self.new_session(command, remote, sudo, position) {
+            Ok(command_session) => Some(command_session),
+            Err(e) => {
+                self.fail_action("Failed to initialize a new session.", command, e);
+                None
+            }
+        }
+    }
+
+    /// Writes `message` and the error that prevented running `command`, and
+    /// stops the action.
+    fn fail_action(&mut self, message: &str, command: &CommandType, error: anyhow::Error) {
+        warn!(error = format!("{:#}", error), "{}", message);
+        self.write_error(message, command, error);
+        *self.action_status.lock().unwrap() = ActionStatus::Stopped;
+    }
+
+    /// Writes `message` and the error that prevented running `command`.
+    fn write_error(&mut self, message: &str, command: &CommandType, error: anyhow::Error) {
+        self.write_buf(
+            format!(
+                "{}\n    Command: {}\n    Error:   {:#}",
+                message,
+                command.get_command(),
+                error,
+            ),
+            Some(StyleConfig::error()),
+            Alignment::Left,
+        );
+    }
+
+    /// Polls `command` until it exits successfully or `timeout` elapses.
+    fn wait_for(&mut self, wait_config: WaitForConfig, position: (usize, usize)) -> Result<()> {
+        let WaitForConfig {
+            command,
+            remote,
+            sudo,
+            interval_ms,
+            timeout_ms,
+        } = wait_config;
+        let interval = Duration::from_millis(interval_ms.unwrap());
+        let timeout = Duration::from_millis(timeout_ms.unwrap());
+
+        let exec_status = self.action_status.clone();
+        // The wait succeeds once the command does, like a command for `when`.
+        self.start_commands();
+
+        let Some(mut command_session) = self.open_session(&command, remote, sudo, position) else {
+            return Ok(());
+        };
+
+        let prompt = match command_session.get_prompt() {
+            Ok(prompt) => prompt,
+            Err(e) => {
+                self.fail_action("Failed to run command.", &command, e);
+                return Ok(());
+            }
+        };
+        self.write_buf(
+            format!("{}Waiting", prompt),
+            Some(StyleConfig::muted()),
+            Alignment::Left,
+        );
+        let entry = self.buffer.lock().unwrap().len() - 1;
+        self.buffer.lock().unwrap()[entry].origin = Some(position);
+        if self.dry_run {
+            let marker = format!(" {}", DRY_RUN_MARKER);
+            self.buffer.lock().unwrap()[entry].push_styled(&marker, StyleConfig::muted());
+            *self.last_success.lock().unwrap() = Some(true);
+            *exec_status.lock().unwrap() = ActionStatus::Stopped;
+            return Ok(());
+        }
+
+        let buffer = self.buffer.clone();
+        let records = self.records.clone();
+        let last_success = self.last_success.clone();
+        let stage = self.config.stages[position.0].name.clone();
+        self.spawn_worker(move || {
+            let start = Instant::now();
+            loop {
+                let stopped = || exec_status.lock().unwrap().force_stop();
+                if command_session
+                    .run_command_streaming(|_, _| {}, stopped)
+                    .is_ok()
+                    && command_session.get_exit_code() == Some(0)
+                {
+                    Self::add_to_buf(
+                        buffer.clone(),
+                        entry,
+                        &format!(" ready after {:.1}s\n", start.elapsed().as_secs_f64()),
+                        false,
+                    );
+                    *last_success.lock().unwrap() = Some(true);
+                    break;
+                }
+                Self::add_to_buf(buffer.clone(), entry, ".", false);
+                if start.elapsed() + interval > timeout {
+                    Self::push_to_buf(
+                        buffer.clone(),
+                        format!("Timed out after {:.1}s", timeout.as_secs_f64()),
+                        StyleConfig::error(),
+                    );
+                    break;
+                }
+                if !Self::sleep_unless_forced(&exec_status, interval) {
+                    Self::add_to_buf(buffer.clone(), entry, " interrupted!\n", false);
+                    break;
+                }
+            }
+            records.lock().unwrap().push(CommandRecord {
+                stage,
+                command: command.get_command(),
+                exit_code: command_session.get_exit_code(),
+                duration_ms: start.elapsed().as_millis(),
+            });
+            *exec_status.lock().unwrap() = ActionStatus::Stopped;
+        });
+
+        Ok(())
+    }
+
+    /// Copies a file to or from the remote host over SFTP, reporting the
+    /// progress every tenth of the file.
+    fn transfer(
+        &mut self,
+        direction: Direction,
+        mut transfer_config: TransferConfig,
+        position: (usize, usize),
+    ) -> Result<()> {
+        let exec_status = self.action_status.clone();
+        *exec_status.lock().unwrap() = ActionStatus::Running;
+        self.command_started = Some(Instant::now());
+        self.elapsed = Duration::ZERO;
+
+        self.fill_passwords(&mut transfer_config.remote, &mut None);
+        let local = transfer_config.local.clone();
+        let transfer = match FileTransfer::new(direction, transfer_config, &self.sessions) {
+            Ok(transfer) => transfer,
+            Err(e) => {
+                let message = "Failed to initialize a new session.";
+                self.write_buf(
+                    format!("{}\n    File:    {}\n    Error:   {:#}", message, local, e),
+                    Some(StyleConfig::error()),
+                    Alignment::Left,
+                );
+                *exec_status.lock().unwrap() = ActionStatus::Stopped;
+                return Ok(());
+            }
+        };
+        let style = self.effective_style(position.0, None);
+        self.write_buf(transfer.get_prompt(), Some(style), Alignment::Left);
+        let entry = self.buffer.lock().unwrap().len() - 1;
+        self.buffer.lock().unwrap()[entry].origin = Some(position);
+        if self.dry_run {
+            self.buffer.lock().unwrap()[entry].push_styled(DRY_RUN_MARKER, StyleConfig::muted());
+            *exec_status.lock().unwrap() = ActionStatus::Stopped;
+            return Ok(());
+        }
+
+        let buffer = self.buffer.clone();
+        let records = self.records.clone();
+        let stage = self.config.stages[position.0].name.clone();
+        self.spawn_worker(move || {
+            let start = Instant::now();
+            let mut tenths = 0;
+            let result = transfer.run(
+                |copied, size| {
+                    while tenths < (copied * 10).checked_div(size).unwrap_or(10) {
+                        let separator = if tenths == 0 { "" } else { " " };
+                        tenths += 1;
+                        let text = format!("{}{}%", separator, tenths * 10);
+                        Self::add_to_buf(buffer.clone(), entry, &text, false);
+                    }
+                },
+                || exec_status.lock().unwrap().force_stop(),
+            );
+            let stopped = exec_status.lock().unwrap().force_stop();
+            let mut output = buffer.lock().unwrap();
+            let output = &mut output[entry];
+            if !output.ends_line() {
+                output.push_str("\n");
+            }
+            let (interrupted, done) = match direction {
+                Direction::Upload => ("Upload interrupted!", "Uploaded"),
+                Direction::Download => ("Download interrupted!", "Downloaded"),
+            };
+            match &result {
+                Ok(_) if stopped => output.push_str(&format!("{}\n", interrupted)),
+                Ok(copied) => output.push_str(&format!(
+                    "{} {} bytes in {:.1}s\n",
+                    done,
+                    copied,
+                    start.elapsed().as_secs_f64()
+                )),
+                Err(e) => output.push_styled(
+                    &format!("Failed to {} file: {:#}\n", direction.name(), e),
+                    StyleConfig::error(),
+                ),
+            }
+            records.lock().unwrap().push(CommandRecord {
+                stage,
+                command: transfer.describe(),
+                exit_code: result.is_ok().then_some(0),
+                duration_ms: start.elapsed().as_millis(),
+            });
+            *exec_status.lock().unwrap() = ActionStatus::Stopped;
+        });
+
+        Ok(())
+    }
+
+    /// Waits for `duration` in the background, or until skipped.
+    fn wait(&mut self, duration: Duration) {
+        let exec_status = self.action_status.clone();
+        *exec_status.lock().unwrap() = ActionStatus::Running;
+        self.wait_deadline = Some(Instant::now() + duration);
+        self.remaining = duration;
+        self.spawn_worker(move || {
+            Self::sleep_unless_forced(&exec_status, duration);
+            *exec_status.lock().unwrap() = ActionStatus::Stopped;
+        });
+    }
+
+    /// Runs `work` in a background thread, waited for on exit.
+    fn spawn_worker(&mut self, work: impl FnOnce() + Send + 'static) {
+        self.workers.retain(|worker| !worker.is_finished());
+        self.workers.push(thread::spawn(work));
+    }
+
+    /// Interrupts the running action and waits for the background threads
+    /// to finish, so that no command outlives the presentation nor writes
+    /// to it once the terminal is restored. Threads stuck, such as on a
+    /// connection, are given up on after a while.
+    fn stop_workers(&mut self) {
+        let mut status = self.action_status.lock().unwrap();
+        if *status != ActionStatus::Stopped {
+            *status = ActionStatus::Forced;
+        }
+        drop(status);
+        // Questions of servers go unanswered, failing their connection.
+        self.password_prompt = None;
+        while self.questions.try_recv().is_ok() {}
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while self.workers.iter().any(|worker| !worker.is_finished()) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        for worker in self.workers.drain(..).filter(JoinHandle::is_finished) {
+            let _ = worker.join();
+        }
+    }
+
+    /// Sleeps for `duration`, returning early (and `false`) on a force-stop.
+    fn sleep_unless_forced(exec_status: &Arc<Mutex<ActionStatus>>, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        loop {
+            if exec_status.lock().unwrap().force_stop() {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            thread::sleep((deadline - now).min(Duration::from_millis(50)));
+        }
+    }
+
+    /// The style of an action of `stage_idx`, merged over the stage style and
+    /// then the default one.
+    fn effective_style(&self, stage_idx: usize, style: Option<StyleConfig>) -> StyleConfig {
+        let stage = self.config.stages[stage_idx].style.clone();
+        [stage, self.config.default_style.clone()]
+            .into_iter()
+            .flatten()
+            .fold(style.unwrap_or_default(), |style, base| {
+                style.merged_over(&base)
+            })
+    }
+
+    fn write_buf(&mut self, text: String, style: Option<StyleConfig>, alignment: Alignment) {
+        self.buffer.lock().unwrap().push(BufferedOutput::new(
+            text,
+            style.unwrap_or_default(),
+            alignment,
+        ));
+    }
+
+    /// Pushes a new left-aligned entry from a worker thread.
+    fn push_to_buf(buffer: Arc<Mutex<Vec<BufferedOutput>>>, text: String, style: StyleConfig) {
+        buffer
+            .lock()
+            .unwrap()
+            .push(BufferedOutput::new(text, style, Alignment::Left));
+    }
+
+    /// Prepends `prefix` to every line of `output`, keeping line endings.
+    fn prefix_lines(output: &str, prefix: &str) -> String {
+        Self::prefix_chunk(output, prefix, &mut true)
+    }
+
+    /// Prepends `prefix` to every line of a chunk of streamed output.
+    ///
+    /// `at_line_start` tells whether the chunk starts a new line, and is
+    /// updated for the next chunk of the same stream.
+    fn prefix_chunk(output: &str, prefix: &str, at_line_start: &mut bool) -> String {
+        if prefix.is_empty() {
+            return output.to_owned();
+        }
+        let mut prefixed = String::with_capacity(output.len());
+        for line in output.split_inclusive('\n') {
+            if *at_line_start {
+                prefixed.push_str(prefix);
+            }
+            prefixed.push_str(line);
+            *at_line_start = line.ends_with('\n');
+        }
+        prefixed
+    }
+
+    /// Appends `output` to the buffer entry at index `entry`.
+    fn add_to_buf(
+        buffer: Arc<Mutex<Vec<BufferedOutput>>>,
+        entry: usize,
+        output: &str,
+        hide_output: bool,
+    ) {
+        if !hide_output && !output.is_empty() {
+            buffer.lock().unwrap()[entry].push_str(output);
+        }
+    }
+
+    /// Appends the changes from `previous` to `output`: added lines in green
+    /// and removed lines in red.
+    fn add_diff_to_buf(
+        buffer: Arc<Mutex<Vec<BufferedOutput>>>,
+        entry: usize,
+        previous: &str,
+        output: &str,
+    ) {
+        let Some(diff) = diff::diff_lines(previous, output) else {
+            Self::add_to_buf(buffer, entry, output, false);
+            return;
+        };
+        let added = StyleConfig {
+            color: Some(String::from("green")),
+            ..Default::default()
+        };
+        let removed = StyleConfig {
+            color: Some(String::from("red")),
+            ..Default::default()
+        };
+        let mut buffer = buffer.lock().unwrap();
+        let entry = &mut buffer[entry];
+        for line in diff {
+            match line {
+                DiffLine::Unchanged(line) => entry.push_str(&format!("  {}\n", line)),
+                DiffLine::Added(line) => entry.push_styled(&format!("+ {}\n", line), added.clone()),
+                DiffLine::Removed(line) => {
+                    entry.push_styled(&format!("- {}\n", line), removed.clone())
+                }
+            }
+        }
+    }
+
+    /// Shows commands, transfers and stage hooks without running them, nor
+    /// connecting to their hosts.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+        self.sessions.set_dry_run(dry_run);
+    }
+
+    /// Runs every action in turn without a terminal, printing the output of
+    /// each to stdout once it is done. Messages are written at once and
+    /// waits and pauses skipped.
+    ///
+    /// A failed command whose `on_error` is not `continue` ends the run.
+    /// Returns whether the presentation finished with every command
+    /// succeeding.
+    pub fn run_headless(&mut self) -> Result<bool> {
+        self.headless = true;
+        // Nobody is there to answer the questions of the servers.
+        self.sessions.set_questions(None);
+        let mut stdout = io::stdout();
+        while !self.finished {
+            self.next_action()?;
+            if self.mode == Mode::Password {
+                let label = self.password_prompt.take().unwrap().label;
+                self.exit();
+                bail!("Password for {} must be set to run headless", label);
+            }
+            while !self.action_status.lock().unwrap().idle() {
+                self.update_cast();
+                thread::sleep(Duration::from_millis(20));
+            }
+            self.update_cast();
+            for text in self.take_output() {
+                let newline = if text.ends_with('\n') { "" } else { "\n" };
+                writeln!(stdout, "{}{}", text, newline)?;
+            }
+            stdout.flush()?;
+            if self.failure().is_some() {
+                break;
+            }
+        }
+        self.exit();
+        Ok(self.summary().success)
+    }
+
+    /// Takes the text of the entries written by the last action once it is
+    /// done, appending them to the transcript.
+    fn take_output(&mut self) -> Vec<String> {
+        if !self.action_status.lock().unwrap().idle() {
+            return Vec::new();
+        }
+        let Some((stage_idx, start)) = self.unrecorded.take() else {
+            return Vec::new();
+        };
+        let texts: Vec<String> = {
+            let buffer = self.buffer.lock().unwrap();
+            // A clear may have dropped the entries from `start` on.
+            buffer[start.min(buffer.len())..]
+                .iter()
+                .map(BufferedOutput::plain_text)
+                .collect()
+        };
+        if let Some(transcript) = self.transcript.as_mut() {
+            let stage = &self.config.stages[stage_idx].name;
+            let result = texts
+                .iter()
+                .try_for_each(|text| transcript.record(stage, &ansi::strip(text)));
+            if let Err(e) = result {
+                // Give up on the transcript rather than failing every action.
+                self.transcript = None;
+                self.write_buf(
+                    format!("Transcript stopped.\n    Error:   {:#}", e),
+                    Some(StyleConfig::error()),
+                    Alignment::Left,
+                );
+            }
+        }
+        texts
+    }
+
+    /// Writes the output shown since the last tick to the cast.
+    fn update_cast(&mut self) {
+        let Some(cast) = self.cast.as_mut() else {
+            return;
+        };
+        let entries: Vec<String> = self
+            .buffer
+            .lock()
+            .unwrap()
+            .iter()
+            .map(BufferedOutput::plain_text)
+            .collect();
+        if let Err(e) = cast.update(&entries) {
+            self.cast = None;
+            self.write_buf(
+                format!("Cast recording stopped.\n    Error:   {:#}", e),
+                Some(StyleConfig::error()),
+                Alignment::Left,
+            );
+        }
+    }
+
+    fn exit(&mut self) {
+        self.stop_workers();
+        self.take_output();
+        self.update_cast();
+        self.leave_stage();
         self.running = false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An entry keeping `max_lines` lines of output after its prompt.
+    fn entry(max_lines: usize) -> BufferedOutput {
+        let mut entry = BufferedOutput::new(
+            String::from("$ seq 5\n"),
+            StyleConfig::default(),
+            Alignment::Left,
+        );
+        entry.max_lines = Some(max_lines);
+        entry
+    }
+
+    #[test]
+    fn entries_keep_their_last_lines() {
+        let mut entry = entry(3);
+        entry.push_str("1\n2\n3\n");
+        assert_eq!(entry.plain_text(), "$ seq 5\n1\n2\n3\n");
+
+        entry.push_str("4\n");
+        entry.push_styled("5\n", StyleConfig::stderr());
+        assert_eq!(
+            entry.plain_text(),
+            "$ seq 5\n… 2 lines trimmed …\n3\n4\n5\n"
+        );
+        let lines = entry.lines();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[1].to_string(), "… 2 lines trimmed …");
+    }
+
+    #[test]
+    fn appending_only_renders_the_last_entry_again() {
+        let mut entries: Vec<BufferedOutput> = (0..3).map(|_| entry(10)).collect();
+        let rendered: Vec<*const Line> = entries
+            .iter_mut()
+            .map(|entry| entry.lines().as_ptr())
+            .collect();
+
+        entries[2].push_str("1\n");
+        assert!(entries[..2].iter().all(|entry| entry.lines.is_some()));
+        assert!(entries[2].lines.is_none());
+        for (entry, lines) in entries[..2].iter_mut().zip(&rendered) {
+            assert_eq!(entry.lines().as_ptr(), *lines);
+        }
+        assert_eq!(entries[2].lines().len(), 2);
+    }
+}
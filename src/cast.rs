@@ -0,0 +1,115 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::{
+    fs::File,
+    io::Write,
+    path::Path,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Escape sequence clearing the screen and moving the cursor home.
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// Recording of the presentation output as an asciinema v2 cast.
+///
+/// The output entries are replayed as they grow, separated by a blank line
+/// like on screen. When entries are taken back or cleared, the screen is
+/// cleared and the remaining ones written again.
+pub struct Cast {
+    file: File,
+    start: Instant,
+    /// Text of the first entry, the stage title, when last written.
+    title: String,
+    /// Length in bytes of every entry written so far.
+    written: Vec<usize>,
+}
+
+impl Cast {
+    /// Creates the cast file at `path`, writing its header.
+    pub fn create(path: &Path, width: u16, height: u16) -> Result<Self> {
+        let mut file = File::create(path)
+            .with_context(|| format!("Failed to create cast '{}'", path.display()))?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let header = json!({
+            "version": 2,
+            "width": width,
+            "height": height,
+            "timestamp": timestamp.as_secs(),
+        });
+        writeln!(file, "{}", header).context("Failed to write to the cast")?;
+        Ok(Self {
+            file,
+            start: Instant::now(),
+            title: String::new(),
+            written: Vec::new(),
+        })
+    }
+
+    /// Writes what changed in the output since the last update, given the
+    /// text of every entry.
+    pub fn update(&mut self, entries: &[String]) -> Result<()> {
+        let data = match self.appended(entries) {
+            Some(data) => data,
+            None => {
+                let mut data = String::from(CLEAR_SCREEN);
+                for (idx, entry) in entries.iter().enumerate() {
+                    if idx > 0 {
+                        data.push_str(Self::separator(&entries[idx - 1]));
+                    }
+                    data.push_str(entry);
+                }
+                data
+            }
+        };
+        self.title = entries.first().cloned().unwrap_or_default();
+        self.written = entries.iter().map(String::len).collect();
+        if data.is_empty() {
+            return Ok(());
+        }
+        let event = json!([
+            self.start.elapsed().as_secs_f64(),
+            "o",
+            data.replace('\n', "\r\n")
+        ]);
+        writeln!(self.file, "{}", event).context("Failed to write to the cast")
+    }
+
+    /// The text added to `entries` since the last update, or `None` if
+    /// written text was changed or removed.
+    fn appended(&self, entries: &[String]) -> Option<String> {
+        if entries.len() < self.written.len()
+            || entries.first().is_some_and(|title| *title != self.title)
+        {
+            return None;
+        }
+        let mut data = String::new();
+        for (idx, entry) in entries.iter().enumerate() {
+            match self.written.get(idx) {
+                Some(&len) if idx + 1 < self.written.len() && len != entry.len() => return None,
+                Some(&len) => data.push_str(entry.get(len..)?),
+                None => {
+                    if idx > 0 {
+                        data.push_str(Self::separator(&entries[idx - 1]));
+                    }
+                    data.push_str(entry);
+                }
+            }
+        }
+        Some(data)
+    }
+
+    /// What ends the line of `entry` and leaves a blank line after it.
+    fn separator(entry: &str) -> &'static str {
+        if entry.ends_with('\n') {
+            "\n"
+        } else {
+            "\n\n"
+        }
+    }
+}
@@ -0,0 +1,73 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{
+    env,
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+/// Where copied text was sent to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    /// The system clipboard, through one of its command line tools.
+    System,
+    /// The clipboard of the terminal, which may be on another host.
+    Terminal,
+}
+
+/// Copies `text` to the system clipboard.
+///
+/// Without a clipboard tool that can reach a display, as over SSH, the text
+/// is sent to the terminal as an OSC 52 sequence instead, which terminals
+/// supporting it put in their clipboard.
+pub fn copy(text: &str) -> Result<Target> {
+    if tools().iter().any(|tool| pipe_to(tool, text)) {
+        return Ok(Target::System);
+    }
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", STANDARD.encode(text))
+        .and_then(|_| stdout.flush())
+        .context("Failed to write to the terminal")?;
+    Ok(Target::Terminal)
+}
+
+/// Commands setting the clipboard from their input, that could work here.
+fn tools() -> Vec<&'static [&'static str]> {
+    let mut tools: Vec<&[&str]> = Vec::new();
+    if cfg!(target_os = "macos") {
+        tools.push(&["pbcopy"]);
+    }
+    if cfg!(windows) {
+        tools.push(&["clip.exe"]);
+    }
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        tools.push(&["wl-copy"]);
+    }
+    if env::var_os("DISPLAY").is_some() {
+        tools.push(&["xclip", "-selection", "clipboard"]);
+        tools.push(&["xsel", "--clipboard", "--input"]);
+    }
+    tools
+}
+
+/// Runs `command` with `text` as its input, returning whether it succeeded.
+fn pipe_to(command: &[&str], text: &str) -> bool {
+    let Ok(mut child) = Command::new(command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    else {
+        return false;
+    };
+    let written = child
+        .stdin
+        .take()
+        .is_some_and(|mut stdin| stdin.write_all(text.as_bytes()).is_ok());
+    child.wait().is_ok_and(|status| status.success()) && written
+}
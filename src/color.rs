@@ -0,0 +1,236 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{bail, Result};
+use ratatui::style::{Color, Style};
+use std::{env, sync::OnceLock};
+
+static COLORS_ENABLED: OnceLock<bool> = OnceLock::new();
+
+static COLOR_DEPTH: OnceLock<ColorDepth> = OnceLock::new();
+
+/// How many colors the terminal can show.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColorDepth {
+    /// The 16 colors of the ANSI palette.
+    Ansi,
+    /// The 256 colors of the xterm palette.
+    Indexed,
+    /// Any RGB color.
+    TrueColor,
+}
+
+/// The levels of each channel in the color cube of the xterm palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// The colors of the ANSI palette, in palette order, as xterm shows them.
+const ANSI_COLORS: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// Whether output may be styled.
+///
+/// Styling is disabled when `NO_COLOR` is set to a non-empty value
+/// (see <https://no-color.org>) or when the terminal is `TERM=dumb`.
+pub fn colors_enabled() -> bool {
+    *COLORS_ENABLED.get_or_init(|| {
+        let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        let dumb = env::var("TERM").is_ok_and(|term| term == "dumb");
+        !no_color && !dumb
+    })
+}
+
+/// Disables styling, whatever the environment. Takes effect only before
+/// anything is styled.
+pub fn disable() {
+    let _ = COLORS_ENABLED.set(false);
+}
+
+/// The color depth of the terminal.
+///
+/// `COLORTERM=truecolor` (or `24bit`) tells of RGB support, and a `TERM`
+/// ending in `256color` of the xterm palette. Other terminals are assumed to
+/// show the 16 ANSI colors only.
+pub fn color_depth() -> ColorDepth {
+    *COLOR_DEPTH.get_or_init(|| {
+        let colorterm = env::var("COLORTERM").unwrap_or_default().to_lowercase();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if env::var("TERM").is_ok_and(|term| term.ends_with("256color")) {
+            ColorDepth::Indexed
+        } else {
+            ColorDepth::Ansi
+        }
+    })
+}
+
+/// Sets the color depth, whatever the terminal. Takes effect only before
+/// anything is styled.
+pub fn set_color_depth(depth: ColorDepth) {
+    let _ = COLOR_DEPTH.set(depth);
+}
+
+/// `color` as the terminal can show it, the nearest color of its palette
+/// standing in for those it lacks.
+pub fn downgrade(color: Color) -> Color {
+    match (color_depth(), color) {
+        (ColorDepth::Indexed, Color::Rgb(r, g, b)) => Color::Indexed(nearest_indexed((r, g, b))),
+        (ColorDepth::Ansi, Color::Rgb(r, g, b)) => nearest_ansi((r, g, b)),
+        (ColorDepth::Ansi, Color::Indexed(index)) => nearest_ansi(indexed_rgb(index)),
+        _ => color,
+    }
+}
+
+/// How far apart two colors look, roughly.
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    channel(a.0, b.0) + channel(a.1, b.1) + channel(a.2, b.2)
+}
+
+/// The RGB value of an xterm palette index.
+fn indexed_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => ANSI_COLORS[index as usize].1,
+        16..=231 => {
+            let cube = index - 16;
+            (
+                CUBE_LEVELS[(cube / 36) as usize],
+                CUBE_LEVELS[(cube / 6 % 6) as usize],
+                CUBE_LEVELS[(cube % 6) as usize],
+            )
+        }
+        _ => {
+            let level = 8 + 10 * (index - 232);
+            (level, level, level)
+        }
+    }
+}
+
+/// The index of the color of the cube or of the gray ramp nearest to `rgb`.
+/// The first 16 colors are left out, as terminals often change them.
+fn nearest_indexed(rgb: (u8, u8, u8)) -> u8 {
+    let level = |value: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&idx| CUBE_LEVELS[idx].abs_diff(value))
+            .unwrap_or(0) as u8
+    };
+    let cube = 16 + 36 * level(rgb.0) + 6 * level(rgb.1) + level(rgb.2);
+    let mean = (rgb.0 as u16 + rgb.1 as u16 + rgb.2 as u16) / 3;
+    let gray = 232 + (mean.saturating_sub(3) / 10).min(23) as u8;
+    if distance(indexed_rgb(gray), rgb) < distance(indexed_rgb(cube), rgb) {
+        gray
+    } else {
+        cube
+    }
+}
+
+/// The color of the ANSI palette nearest to `rgb`.
+fn nearest_ansi(rgb: (u8, u8, u8)) -> Color {
+    ANSI_COLORS
+        .iter()
+        .min_by_key(|(_, palette)| distance(*palette, rgb))
+        .map_or(Color::Reset, |&(color, _)| color)
+}
+
+/// Returns `style` when colors are enabled, or a plain style otherwise.
+pub fn styled(style: Style) -> Style {
+    if colors_enabled() {
+        style
+    } else {
+        Style::default()
+    }
+}
+
+/// Parses a color, ignoring case and surrounding whitespace.
+///
+/// Besides names, a color may be given as `#rrggbb`, or as an xterm palette
+/// index from 0 to 255, either bare (`208`) or prefixed (`color208`).
+pub fn parse_color(name: &str) -> Result<Color> {
+    let lower = name.trim().to_lowercase();
+    if let Some(hex) = lower.strip_prefix('#') {
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("Invalid hex color: '{}', expected '#rrggbb'", name);
+        }
+        let channel = |idx: usize| u8::from_str_radix(&hex[idx..idx + 2], 16).unwrap_or(0);
+        return Ok(Color::Rgb(channel(0), channel(2), channel(4)));
+    }
+    let index = lower.strip_prefix("color").unwrap_or(&lower);
+    if !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()) {
+        return match index.parse::<u8>() {
+            Ok(index) => Ok(Color::Indexed(index)),
+            Err(_) => bail!("Invalid color index: '{}', expected 0 to 255", name),
+        };
+    }
+    Ok(match lower.as_str() {
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "cyan" => Color::Cyan,
+        "red" => Color::Red,
+        "magenta" => Color::Magenta,
+        "white" => Color::White,
+        "darkgray" => Color::DarkGray,
+        _ => bail!("Unknown color: '{}'", name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_ignore_case() {
+        assert_eq!(parse_color("red").unwrap(), Color::Red);
+        assert_eq!(parse_color("Green").unwrap(), Color::Green);
+        assert_eq!(parse_color(" DarkGray ").unwrap(), Color::DarkGray);
+    }
+
+    #[test]
+    fn hex_colors_are_rgb() {
+        assert_eq!(parse_color("#ff8000").unwrap(), Color::Rgb(255, 128, 0));
+        assert_eq!(parse_color("#FF8000").unwrap(), Color::Rgb(255, 128, 0));
+        for invalid in ["#fff", "#ff80001", "#gg8000"] {
+            let error = parse_color(invalid).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("Invalid hex color: '{}', expected '#rrggbb'", invalid)
+            );
+        }
+    }
+
+    #[test]
+    fn indices_are_palette_colors() {
+        assert_eq!(parse_color("208").unwrap(), Color::Indexed(208));
+        assert_eq!(parse_color("Color0").unwrap(), Color::Indexed(0));
+        assert_eq!(parse_color("color255").unwrap(), Color::Indexed(255));
+        let error = parse_color("256").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid color index: '256', expected 0 to 255"
+        );
+        assert!(parse_color("color").is_err());
+    }
+
+    #[test]
+    fn unknown_names_are_errors() {
+        let error = parse_color("Purple").unwrap_err();
+        assert_eq!(error.to_string(), "Unknown color: 'Purple'");
+        assert!(parse_color("").is_err());
+    }
+}
@@ -2,15 +2,28 @@
 //
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::{
+    color,
+    keymap::{KeyAction, Keymap},
+    positions::Positions,
+    redact,
+};
+use crate::logging;
 use anyhow::{Context, Result};
-use jsonschema;
-use ratatui::style::{Color, Modifier, Style};
+use ratatui::{
+    layout::Alignment,
+    style::{Modifier, Style},
+};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Number, Value};
 use std::{
-    fs,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    env, fs,
     path::{Path, PathBuf},
 };
+use yaml_rust2::{Yaml, YamlLoader};
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct StyleConfig {
@@ -20,56 +33,182 @@ pub struct StyleConfig {
     pub bold: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub italic: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub underline: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strikethrough: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dim: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
 }
 
 impl StyleConfig {
     pub fn title() -> Self {
-        let mut title = StyleConfig::default();
-        title.bold = Some(true);
-        title.color = Some(String::from("white"));
-        title
+        StyleConfig {
+            color: Some(String::from("white")),
+            bold: Some(true),
+            ..Default::default()
+        }
+    }
+
+    pub fn label() -> Self {
+        StyleConfig {
+            color: Some(String::from("cyan")),
+            bold: Some(true),
+            ..Default::default()
+        }
     }
 
     pub fn error() -> Self {
-        let mut title = StyleConfig::default();
-        title.bold = Some(true);
-        title.color = Some(String::from("red"));
-        title
+        StyleConfig {
+            color: Some(String::from("red")),
+            bold: Some(true),
+            ..Default::default()
+        }
+    }
+
+    /// Fills the fields left unset with those of `base`.
+    pub fn merged_over(self, base: &StyleConfig) -> Self {
+        StyleConfig {
+            color: self.color.or_else(|| base.color.clone()),
+            bold: self.bold.or(base.bold),
+            italic: self.italic.or(base.italic),
+            underline: self.underline.or(base.underline),
+            strikethrough: self.strikethrough.or(base.strikethrough),
+            dim: self.dim.or(base.dim),
+            background: self.background.or_else(|| base.background.clone()),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(color) = &self.color {
+            color::parse_color(color)?;
+        }
+        if let Some(background) = &self.background {
+            color::parse_color(background)?;
+        }
+        Ok(())
+    }
+
+    pub fn muted() -> Self {
+        StyleConfig {
+            color: Some(String::from("darkgray")),
+            ..Default::default()
+        }
+    }
+
+    pub fn stderr() -> Self {
+        StyleConfig {
+            color: Some(String::from("red")),
+            ..Default::default()
+        }
     }
 }
 
-impl Into<Style> for StyleConfig {
-    fn into(self) -> Style {
+impl From<StyleConfig> for Style {
+    fn from(config: StyleConfig) -> Self {
         let mut style = Style::default();
+        if !color::colors_enabled() {
+            return style;
+        }
 
-        if let Some(color) = &self.color {
-            style = style.fg(match color.as_str() {
-                "green" => Color::Green,
-                "yellow" => Color::Yellow,
-                "blue" => Color::Blue,
-                "cyan" => Color::Cyan,
-                "red" => Color::Red,
-                "magenta" => Color::Magenta,
-                _ => Color::White,
-            });
-        }
-
-        if self.bold.unwrap_or(false) {
+        if let Some(color) = &config.color {
+            // Colors are validated when loading the configuration.
+            if let Ok(color) = color::parse_color(color) {
+                style = style.fg(color::downgrade(color));
+            }
+        }
+
+        if config.bold.unwrap_or(false) {
             style = style.add_modifier(Modifier::BOLD);
         }
 
-        if self.italic.unwrap_or(false) {
+        if let Some(background) = &config.background {
+            if let Ok(background) = color::parse_color(background) {
+                style = style.bg(color::downgrade(background));
+            }
+        }
+
+        if config.italic.unwrap_or(false) {
             style = style.add_modifier(Modifier::ITALIC);
         }
 
+        if config.underline.unwrap_or(false) {
+            style = style.add_modifier(Modifier::UNDERLINED);
+        }
+
+        if config.strikethrough.unwrap_or(false) {
+            style = style.add_modifier(Modifier::CROSSED_OUT);
+        }
+
+        if config.dim.unwrap_or(false) {
+            style = style.add_modifier(Modifier::DIM);
+        }
+
         style
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// How much of a message is typed at a time.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reveal {
+    /// One character, waiting `speed` milliseconds after each.
+    #[default]
+    Char,
+    /// One word along with the spaces following it.
+    Word,
+    /// The whole message at once.
+    Instant,
+}
+
+/// What to do when a command exits with a nonzero code.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+    /// Carry on as if the command succeeded.
+    #[default]
+    Continue,
+    /// Hold the presentation until the failure is acknowledged.
+    Stop,
+    /// Ask whether to proceed to the next action.
+    Prompt,
+}
+
+impl From<Align> for Alignment {
+    fn from(align: Align) -> Self {
+        match align {
+            Align::Left => Alignment::Left,
+            Align::Center => Alignment::Center,
+            Align::Right => Alignment::Right,
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct Stage {
     pub name: String,
-    pub actions: Vec<Action>,
+    pub actions: Vec<ActionConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub align: Option<Align>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<StyleConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub setup: Option<CommandType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub teardown: Option<CommandType>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -77,9 +216,52 @@ pub struct Stage {
 pub enum CommandType {
     Single(String),
     Multiple(Vec<String>),
+    /// Commands joined with an explicit shell operator.
+    Joined {
+        commands: Vec<String>,
+        #[serde(default)]
+        join: JoinOperator,
+    },
+    /// Program and arguments executed as-is, without a shell.
+    ExecArgv { argv: Vec<String> },
+}
+
+/// Shell operator placed between the commands of a list.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub enum JoinOperator {
+    /// Run each command only if the previous one succeeded.
+    #[default]
+    #[serde(rename = "&&")]
+    And,
+    /// Run each command only if the previous one failed.
+    #[serde(rename = "||")]
+    Or,
+    /// Run every command regardless of the previous one.
+    #[serde(rename = ";")]
+    Sequence,
+    /// Run the commands as the lines of a script read by the shell from its
+    /// standard input, so that loops and here-documents can span them.
+    #[serde(rename = "script")]
+    Script,
+}
+
+impl JoinOperator {
+    fn separator(self) -> &'static str {
+        match self {
+            Self::And => " && ",
+            Self::Or => " || ",
+            Self::Sequence => "; ",
+            Self::Script => "\n",
+        }
+    }
 }
 
 impl CommandType {
+    /// Whether the commands are run as a script fed to the shell.
+    pub fn is_script(&self) -> bool {
+        matches!(self, Self::Joined { join: JoinOperator::Script, .. })
+    }
+
     pub fn get_command(&self) -> String {
         match self {
             Self::Single(ref cmd) => {
@@ -88,7 +270,422 @@ impl CommandType {
             Self::Multiple(ref cmds) => {
                 cmds.join(" && ")
             }
+            Self::Joined { ref commands, join } => {
+                commands.join(join.separator())
+            }
+            Self::ExecArgv { ref argv } => {
+                argv.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" ")
+            }
+        }
+    }
+
+    /// Expands the environment variables of the commands, leaving `${NAME}`
+    /// to the shell unless they are run without one, and the `$file:`
+    /// references for the command to read when it runs.
+    fn expand_env(&mut self, expansion: Expansion<'_>) -> Result<()> {
+        match self {
+            Self::Single(cmd) => *cmd = expand_command_env(cmd, expansion, true)?,
+            Self::Multiple(cmds) | Self::Joined { commands: cmds, .. } => {
+                for cmd in cmds {
+                    *cmd = expand_command_env(cmd, expansion, true)?;
+                }
+            }
+            Self::ExecArgv { argv } => {
+                for arg in argv {
+                    *arg = expand_command_env(arg, expansion, false)?;
+                }
+            }
         }
+        Ok(())
+    }
+}
+
+/// Environment variable set to the index of the current iteration of a loop,
+/// from 0. Its references are only expanded when the command runs.
+pub const ITER_VAR: &str = "AUTOPILOT_ITER";
+
+/// What a reference to an environment variable that is not set expands to.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingEnv {
+    /// Nothing: loading the configuration fails.
+    #[default]
+    Error,
+    /// An empty string.
+    Empty,
+}
+
+/// How the references to environment variables are expanded on load.
+#[derive(Clone, Copy)]
+pub struct Expansion<'a> {
+    /// What a variable that is not set expands to.
+    pub missing: MissingEnv,
+    /// Variables captured from the output of commands, whose references are
+    /// only expanded when the actions run.
+    pub captured: &'a HashSet<String>,
+    /// The secrets of the `$file:` references of the commands, which are
+    /// left in place for the commands to read when they run, so that the
+    /// secrets are never shown.
+    pub file_secrets: &'a RefCell<Vec<String>>,
+}
+
+/// Replaces the `$env:NAME` and `${NAME}` references of `text` with the
+/// value of the environment variable, and the `$file:PATH` ones with the
+/// contents of the file unless kept for later, leaving the rest of the text
+/// as is. A path runs up to the next whitespace or quote.
+///
+/// In `shell` commands only the `$env:` form is replaced, `${NAME}` being
+/// left for the shell along with its own variables. Elsewhere, `$${` stands
+/// for a literal `${`.
+pub fn expand_env(text: &str, expansion: Expansion<'_>, shell: bool) -> Result<String> {
+    expand_references(text, expansion, shell, false)
+}
+
+/// Expands `text` as [`expand_env`] does, leaving the `$file:` references
+/// of a command for it to read when it runs.
+fn expand_command_env(text: &str, expansion: Expansion<'_>, shell: bool) -> Result<String> {
+    expand_references(text, expansion, shell, true)
+}
+
+fn expand_references(
+    text: &str,
+    expansion: Expansion<'_>,
+    shell: bool,
+    command: bool,
+) -> Result<String> {
+    let name_len = |text: &str| {
+        text.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(text.len())
+    };
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some((path, len)) = file_reference(rest) {
+            let secret = read_secret_file(path)?;
+            if command {
+                expanded.push_str(&rest[..len]);
+                expansion.file_secrets.borrow_mut().push(secret);
+            } else {
+                expanded.push_str(&secret);
+            }
+            rest = &rest[len..];
+            continue;
+        }
+        let (name, len) = if let Some(after) = rest.strip_prefix("$env:") {
+            let len = name_len(after);
+            (&after[..len], "$env:".len() + len)
+        } else if rest.starts_with("$${") && !shell {
+            expanded.push_str("${");
+            rest = &rest["$${".len()..];
+            continue;
+        } else if let Some(after) = rest.strip_prefix("${").filter(|_| !shell) {
+            let len = name_len(after);
+            if after[len..].starts_with('}') {
+                (&after[..len], "${}".len() + len)
+            } else {
+                ("", 0)
+            }
+        } else {
+            ("", 0)
+        };
+        if name.is_empty() {
+            expanded.push('$');
+            rest = &rest[1..];
+            continue;
+        }
+        // Left for every iteration, or the command capturing it, to fill in.
+        if name == ITER_VAR || expansion.captured.contains(name) {
+            expanded.push_str(&rest[..len]);
+            rest = &rest[len..];
+            continue;
+        }
+        match (env::var(name), expansion.missing) {
+            (Ok(value), _) => expanded.push_str(&value),
+            (Err(_), MissingEnv::Empty) => {}
+            (Err(_), MissingEnv::Error) => {
+                anyhow::bail!("Missing environment variable: '{}'", name)
+            }
+        }
+        rest = &rest[len..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// The path of the `$file:` reference `text` starts with, if any, and the
+/// length of the reference.
+fn file_reference(text: &str) -> Option<(&str, usize)> {
+    let after = text.strip_prefix("$file:")?;
+    let len = after
+        .find(|c: char| c.is_whitespace() || matches!(c, '\'' | '"'))
+        .unwrap_or(after.len());
+    (len > 0).then(|| (&after[..len], "$file:".len() + len))
+}
+
+/// Reads the secret of a `$file:` reference, the contents of the file at
+/// `path` less surrounding whitespace, and keeps it out of the logs. The
+/// bytes read are zeroed once copied.
+fn read_secret_file(path: &str) -> Result<String> {
+    let mut bytes =
+        fs::read(path).with_context(|| format!("Failed to read secret file '{}'", path))?;
+    let secret = std::str::from_utf8(&bytes)
+        .map(|text| text.trim().to_owned())
+        .with_context(|| format!("Secret file '{}' is not valid UTF-8", path));
+    bytes.fill(0);
+    if let Ok(secret) = &secret {
+        logging::add_secret(secret);
+    }
+    secret
+}
+
+/// Reads the secrets of the `$file:` references of `texts`, by path.
+pub fn read_file_secrets<'a>(
+    texts: impl IntoIterator<Item = &'a str>,
+) -> Result<HashMap<String, String>> {
+    let mut secrets = HashMap::new();
+    for text in texts {
+        for (start, _) in text.match_indices("$file:") {
+            if let Some((path, _)) = file_reference(&text[start..]) {
+                if !secrets.contains_key(path) {
+                    secrets.insert(path.to_owned(), read_secret_file(path)?);
+                }
+            }
+        }
+    }
+    Ok(secrets)
+}
+
+/// Replaces the `$file:` references of `text` with their `secrets`, as read
+/// by [`read_file_secrets`].
+pub fn expand_file_secrets(text: &str, secrets: &HashMap<String, String>) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("$file:") {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match file_reference(rest).and_then(|(path, len)| Some((secrets.get(path)?, len))) {
+            Some((secret, len)) => {
+                expanded.push_str(secret);
+                rest = &rest[len..];
+            }
+            None => {
+                expanded.push('$');
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Replaces the references to [`ITER_VAR`] of `text`, as `$env:NAME`,
+/// `{{ NAME }}` or, outside of `shell` commands, `${NAME}`, with `iteration`.
+pub fn expand_iteration(text: &str, iteration: u32, shell: bool) -> String {
+    let value = iteration.to_string();
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['$', '{']) {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let len = if let Some(after) = rest.strip_prefix("$env:") {
+            after
+                .strip_prefix(ITER_VAR)
+                .filter(|after| !after.starts_with(is_name))
+                .map(|after| rest.len() - after.len())
+        } else if let Some(after) = rest.strip_prefix("${").filter(|_| !shell) {
+            after
+                .strip_prefix(ITER_VAR)
+                .filter(|after| after.starts_with('}'))
+                .map(|after| rest.len() - after.len() + 1)
+        } else if rest.starts_with("{{") {
+            rest.find("}}")
+                .filter(|&end| rest[2..end].trim() == ITER_VAR)
+                .map(|end| end + 2)
+        } else {
+            None
+        };
+        match len {
+            Some(len) => {
+                expanded.push_str(&value);
+                rest = &rest[len..];
+            }
+            None => {
+                expanded.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Replaces the references of `text` to the `captured` variables, as
+/// `$env:NAME` or `{{ NAME }}`, with their `values`. A reference to a
+/// variable whose command has not run yet is an error.
+///
+/// In `shell` commands every value is quoted so that the shell reads it
+/// back as is, without splitting or running it: as a single word, as
+/// [`shell_quote`] does, or escaped within the quotes the reference is in.
+pub fn expand_captured(
+    text: &str,
+    captured: &HashSet<String>,
+    values: &HashMap<String, String>,
+    shell: bool,
+) -> Result<String> {
+    let name_len = |text: &str| {
+        text.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(text.len())
+    };
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['$', '{']) {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let (name, len) = if let Some(after) = rest.strip_prefix("$env:") {
+            let len = name_len(after);
+            (&after[..len], "$env:".len() + len)
+        } else if rest.starts_with("{{") {
+            match rest.find("}}") {
+                Some(end) => (rest[2..end].trim(), end + 2),
+                None => ("", 0),
+            }
+        } else {
+            ("", 0)
+        };
+        if !captured.contains(name) {
+            expanded.push_str(&rest[..1]);
+            rest = &rest[1..];
+            continue;
+        }
+        let value = values
+            .get(name)
+            .with_context(|| format!("Variable '{}' has not been captured yet", name))?;
+        if shell {
+            let quoted = match open_quote(&expanded) {
+                Some('\'') => value.replace('\'', "'\\''"),
+                Some(_) => value.chars().fold(String::new(), |mut quoted, c| {
+                    if matches!(c, '\\' | '"' | '$' | '`') {
+                        quoted.push('\\');
+                    }
+                    quoted.push(c);
+                    quoted
+                }),
+                None => shell_quote(value),
+            };
+            expanded.push_str(&quoted);
+        } else {
+            expanded.push_str(value);
+        }
+        rest = &rest[len..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Expands the environment variables of `value`, if set.
+fn expand_env_opt(value: &mut Option<String>, expansion: Expansion<'_>, shell: bool) -> Result<()> {
+    if let Some(text) = value {
+        *text = expand_env(text, expansion, shell)?;
+    }
+    Ok(())
+}
+
+/// The quote left open at the end of the POSIX shell command `text`, if any.
+fn open_quote(text: &str) -> Option<char> {
+    let mut quote = None;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (None, '\'' | '"') => quote = Some(c),
+            (None | Some('"'), '\\') => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    quote
+}
+
+/// Quotes `arg` so that a POSIX shell reads it back as a single word.
+pub fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Exit status of the last command an action runs on.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum When {
+    #[default]
+    Always,
+    /// Unless the last command failed.
+    OnSuccess,
+    /// Only once a command failed, until another one succeeds.
+    OnFailure,
+}
+
+/// An action of a stage, along with when it runs.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ActionConfig {
+    #[serde(flatten)]
+    pub action: Action,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub when: Option<When>,
+    /// Header written above the output of the action, also naming it in
+    /// the outline.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+impl ActionConfig {
+    /// The action with its references to the `captured` variables replaced
+    /// by their `values`, as [`expand_captured`] does.
+    pub fn expand_captured(
+        &self,
+        captured: &HashSet<String>,
+        values: &HashMap<String, String>,
+    ) -> Result<Self> {
+        fn expand(
+            value: &mut Value,
+            captured: &HashSet<String>,
+            values: &HashMap<String, String>,
+            shell: bool,
+        ) -> Result<()> {
+            match value {
+                Value::String(text) => *text = expand_captured(text, captured, values, shell)?,
+                Value::Array(array) => {
+                    for item in array {
+                        expand(item, captured, values, shell)?;
+                    }
+                }
+                Value::Object(map) => {
+                    // The commands of an action are read by the shell,
+                    // unless they are run as argv.
+                    let runs = matches!(
+                        map.get("type").and_then(Value::as_str),
+                        Some("command" | "wait_for")
+                    );
+                    for (key, item) in map.iter_mut() {
+                        let shell =
+                            shell || (runs && key == "command" && item.get("argv").is_none());
+                        expand(item, captured, values, shell)?;
+                    }
+                }
+                _ => {}
+            }
+            Ok(())
+        }
+        let mut value = serde_json::to_value(self).context("Failed to serialize action")?;
+        expand(&mut value, captured, values, false)?;
+        serde_json::from_value(value).context("Failed to parse action")
     }
 }
 
@@ -101,21 +698,29 @@ pub enum Action {
         style: Option<StyleConfig>,
         #[serde(skip_serializing_if = "Option::is_none", default = "Action::speed_default")]
         speed: Option<u64>,
-    },
-    Command {
-        command: CommandType,
         #[serde(skip_serializing_if = "Option::is_none")]
-        sudo: Option<SudoConfig>,
-        #[serde(skip_serializing_if = "Option::is_none", default = "Action::stdout_default")]
-        hide_stdout: Option<bool>,
-        #[serde(skip_serializing_if = "Option::is_none", default = "Action::stderr_default")]
-        hide_stderr: Option<bool>,
+        reveal: Option<Reveal>,
         #[serde(skip_serializing_if = "Option::is_none")]
-        style: Option<StyleConfig>,
+        align: Option<Align>,
         #[serde(skip_serializing_if = "Option::is_none")]
-        remote: Option<RemoteConfig>,
-        #[serde(skip_serializing_if = "Option::is_none", default = "Action::loop_config_default")]
-        r#loop: Option<LoopConfig>,
+        prefix: Option<String>,
+    },
+    Command(Box<CommandConfig>),
+    #[serde(rename = "wait_for")]
+    WaitFor(WaitForConfig),
+    Upload(TransferConfig),
+    Download(TransferConfig),
+    Pause {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        prompt: Option<String>,
+    },
+    Wait {
+        millis: u64,
+    },
+    Clear,
+    /// Commands started together, the group being done once they all are.
+    Parallel {
+        actions: Vec<ActionConfig>,
     },
 }
 
@@ -124,6 +729,97 @@ impl Action {
         Some(50)
     }
 
+    /// The commands the action runs: its own, or those of a parallel group.
+    pub fn commands(&self) -> Vec<&CommandConfig> {
+        match self {
+            Self::Command(command) => vec![command],
+            Self::Parallel { actions } => actions
+                .iter()
+                .flat_map(|child| child.action.commands())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// A one-line description of the action, as listed in the outline.
+    pub fn summary(&self) -> String {
+        let summary = match self {
+            Self::Message { text, .. } => text.clone(),
+            Self::Command(command) => format!("$ {}", command.command.get_command()),
+            Self::WaitFor(wait) => format!("wait for {}", wait.command.get_command()),
+            Self::Upload(transfer) => format!("upload {}", transfer.local),
+            Self::Download(transfer) => format!("download {}", transfer.remote_path),
+            Self::Pause { .. } => String::from("pause"),
+            Self::Wait { millis } => format!("wait {:.1}s", *millis as f64 / 1000.0),
+            Self::Clear => String::from("clear"),
+            Self::Parallel { actions } => format!("{} parallel commands", actions.len()),
+        };
+        let mut lines = summary.lines();
+        let first = lines.next().unwrap_or_default();
+        if lines.next().is_some() {
+            format!("{} …", first)
+        } else {
+            first.to_owned()
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct CommandConfig {
+    pub command: CommandType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sudo: Option<SudoConfig>,
+    #[serde(skip_serializing_if = "Option::is_none", default = "CommandConfig::stdout_default")]
+    pub hide_stdout: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none", default = "CommandConfig::stderr_default")]
+    pub hide_stderr: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<StyleConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote: Option<Remotes>,
+    /// Run on every host of `remote` at once instead of one after another.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none", default = "CommandConfig::loop_config_default")]
+    pub r#loop: Option<LoopConfig>,
+    /// Run the command again when it fails, before `on_error` applies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merge_streams: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strip_ansi: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_error: Option<OnError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell_args: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<String>,
+    /// Milliseconds after which a run of the command is stopped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Ask before running the command, skipping it unless confirmed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirm: Option<bool>,
+    /// Question asked before running the command, implying `confirm`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirm_prompt: Option<String>,
+    /// Variable set to the output of the command, for later actions to use.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture: Option<String>,
+}
+
+impl CommandConfig {
     fn stdout_default() -> Option<bool> {
         Some(false)
     }
@@ -133,7 +829,49 @@ impl Action {
     }
 
     fn loop_config_default() -> Option<LoopConfig> {
-        Some(LoopConfig { times: 1, delay: LoopConfig::delay_default() })
+        Some(LoopConfig {
+            times: Some(1),
+            delay: LoopConfig::delay_default(),
+            ..Default::default()
+        })
+    }
+}
+
+/// A file copied between the local machine and a remote host over SFTP.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct TransferConfig {
+    /// Path of the file on the local machine.
+    pub local: String,
+    /// Path of the file on the remote host.
+    pub remote_path: String,
+    /// Host the file is copied to or from, required by validation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote: Option<RemoteConfig>,
+    /// Whether a download replaces an existing local file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overwrite: Option<bool>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct WaitForConfig {
+    pub command: CommandType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote: Option<RemoteConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sudo: Option<SudoConfig>,
+    #[serde(skip_serializing_if = "Option::is_none", default = "WaitForConfig::interval_default")]
+    pub interval_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none", default = "WaitForConfig::timeout_default")]
+    pub timeout_ms: Option<u64>,
+}
+
+impl WaitForConfig {
+    fn interval_default() -> Option<u64> {
+        Some(1000)
+    }
+
+    fn timeout_default() -> Option<u64> {
+        Some(60000)
     }
 }
 
@@ -143,6 +881,9 @@ pub struct SudoConfig {
     pub user: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none", default = "SudoConfig::password_default")]
     pub password: Option<String>,
+    /// Run remote commands on a PTY, answering the password prompt of sudo.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_pty: Option<bool>,
 }
 
 impl SudoConfig {
@@ -153,70 +894,1442 @@ impl SudoConfig {
     fn password_default() -> Option<String> {
         Some(String::new())
     }
+
+    fn expand_env(&mut self, expansion: Expansion<'_>) -> Result<()> {
+        expand_env_opt(&mut self.user, expansion, false)?;
+        expand_env_opt(&mut self.password, expansion, false)
+    }
+}
+
+/// A key bound to an action, or several keys.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum KeySpecs {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl KeySpecs {
+    pub fn specs(&self) -> &[String] {
+        match self {
+            KeySpecs::Single(spec) => std::slice::from_ref(spec),
+            KeySpecs::Multiple(specs) => specs,
+        }
+    }
+}
+
+/// The host a command runs on, or the hosts it fans out to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Remotes {
+    Single(RemoteConfig),
+    Multiple(Vec<RemoteConfig>),
+}
+
+impl Remotes {
+    pub fn hosts(&self) -> &[RemoteConfig] {
+        match self {
+            Remotes::Single(remote) => std::slice::from_ref(remote),
+            Remotes::Multiple(remotes) => remotes,
+        }
+    }
+
+    fn expand_env(&mut self, expansion: Expansion<'_>) -> Result<()> {
+        match self {
+            Remotes::Single(remote) => remote.expand_env(expansion),
+            Remotes::Multiple(remotes) => {
+                remotes.iter_mut().try_for_each(|remote| remote.expand_env(expansion))
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct RemoteConfig {
+    /// Host name, or `Host` alias of `~/.ssh/config`.
     pub host: String,
-    #[serde(skip_serializing_if = "Option::is_none", default = "RemoteConfig::port_default")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub port: Option<u16>,
-    pub user: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none", default = "RemoteConfig::password_default")]
     pub password: Option<String>,
+    /// Path to a private key file to authenticate with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private_key: Option<String>,
+    /// Authenticate with the SSH agent, by default when neither a password
+    /// nor a private key is configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_agent: Option<bool>,
+    /// Reject hosts missing from `known_hosts` instead of adding them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict_host_keys: Option<bool>,
+    /// Bastion through which the host is reached.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jump: Option<Box<RemoteConfig>>,
+    /// Milliseconds to wait for the connection, and for every reply of the
+    /// server once connected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_ms: Option<u64>,
+    /// How to authenticate, instead of picking from the fields set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthMethod>,
+    /// Check that the host can be logged into when a stage using it starts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preflight: Option<bool>,
+}
+
+/// How a remote user authenticates.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    Password,
+    /// With the `private_key` file.
+    Key,
+    /// With the identities of the SSH agent, falling back on the password.
+    Agent,
+    /// By answering the questions of the server, such as one-time codes.
+    KeyboardInteractive,
 }
 
 impl RemoteConfig {
-    fn port_default() -> Option<u16> {
-        Some(22)
+    /// Whether the questions of the server, for the host or one of its jump
+    /// hosts, are answered by the presenter.
+    pub fn interactive(&self) -> bool {
+        self.auth == Some(AuthMethod::KeyboardInteractive)
+            || self.jump.as_ref().is_some_and(|jump| jump.interactive())
     }
 
     fn password_default() -> Option<String> {
         Some(String::new())
     }
+
+    fn expand_env(&mut self, expansion: Expansion<'_>) -> Result<()> {
+        self.host = expand_env(&self.host, expansion, false)?;
+        expand_env_opt(&mut self.user, expansion, false)?;
+        expand_env_opt(&mut self.password, expansion, false)?;
+        expand_env_opt(&mut self.private_key, expansion, false)?;
+        match &mut self.jump {
+            Some(jump) => jump.expand_env(expansion),
+            None => Ok(()),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct LoopConfig {
-    pub times: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub times: Option<u32>,
+    /// Repeat until a run exits with a zero exit code.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until_success: Option<bool>,
+    /// Milliseconds after which no further run is started.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none", default = "LoopConfig::delay_default")]
     pub delay: Option<u64>,
 }
 
+/// How often a failed run of a command is tried again.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RetryConfig {
+    /// Number of runs at most, the first one included.
+    pub attempts: u32,
+    /// Milliseconds waited before the first retry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delay_ms: Option<u64>,
+    /// Factor the delay is multiplied by after every retry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backoff_factor: Option<f64>,
+}
+
 impl LoopConfig {
     fn delay_default() -> Option<u64> {
         Some(0)
     }
+
+    /// Whether the loop ends on a condition rather than after a count.
+    fn is_conditional(&self) -> bool {
+        self.until_success.unwrap_or(false) || self.duration_ms.is_some()
+    }
+
+    /// The number of runs at most. A conditional loop runs until its
+    /// condition ends it.
+    pub fn max_runs(&self) -> u32 {
+        match self.times {
+            Some(times) => times,
+            None if self.is_conditional() => u32::MAX,
+            None => 1,
+        }
+    }
+}
+
+/// Lines the border of the interface is drawn with.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BorderStyle {
+    #[default]
+    Thick,
+    Rounded,
+    Plain,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct UiConfig {
+    /// Title shown on top of the border, instead of `AutoPilot`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub border: Option<BorderStyle>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_line_numbers: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_instructions: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wrap: Option<bool>,
+    /// Whether the outline of the stages and actions is shown beside the
+    /// output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_outline: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_timing: Option<bool>,
+    /// Seconds without key presses after which the attract screen is shown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attract_after: Option<u64>,
+    /// Milliseconds between two redraws of the interface.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tick_ms: Option<u64>,
 }
 
 #[derive(Default, Deserialize, Serialize)]
 pub struct Config {
     pub stages: Vec<Stage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ui: Option<UiConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefetch: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_style: Option<StyleConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_lines: Option<usize>,
+    /// Regular expressions whose matches are masked in the command output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redact: Option<Vec<String>>,
+    /// The rules of `redact`, compiled when loading the configuration.
+    #[serde(skip)]
+    pub redactions: Vec<Regex>,
+    /// The names of the variables set by the `capture` of the commands.
+    #[serde(skip)]
+    pub captured: HashSet<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell_args: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vars: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub missing_env: Option<MissingEnv>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keybindings: Option<HashMap<KeyAction, KeySpecs>>,
 }
 
+/// Milliseconds between two redraws of the interface unless configured.
+pub const DEFAULT_TICK_MS: u64 = 50;
+
+/// Shortest time between two redraws of the interface, in milliseconds.
+pub const MIN_TICK_MS: u64 = 10;
+
+/// Password value asking for the password when first needed, instead.
+pub const PASSWORD_PROMPT: &str = "$prompt";
+
+/// JSON schema of the configuration, built into the binary.
+pub const SCHEMA: &str = include_str!("../autopilot.schema.json");
+
 impl Config {
-    fn validate_config(config: &Config) -> Result<()> {
-        let schema_str = fs::read_to_string(PathBuf::from("autopilot.schema.json"))
-            .context("Failed to read schema file")?;
+    /// Validates the configuration `value` against the built-in schema, or the one at
+    /// `schema_path` if given.
+    ///
+    /// Every violation is listed with the position of the offending node,
+    /// as found in `positions`, relative to the file at `yaml_path`.
+    fn validate_config(
+        value: &Value,
+        schema_path: Option<&Path>,
+        positions: &Positions,
+        yaml_path: &Path,
+    ) -> Result<()> {
+        let schema_str = match schema_path {
+            Some(path) => fs::read_to_string(path)
+                .with_context(|| format!("Failed to read schema file '{}'", path.display()))?,
+            None => String::from(SCHEMA),
+        };
         let schema_json: Value =
             serde_json::from_str(&schema_str).context("Failed to parse JSON schema")?;
 
-        let json_value =
-            serde_json::to_value(config).context("Failed to convert YAML to JSON 2")?;
-        if let Err(err) = jsonschema::validate(&schema_json, &json_value) {
-            anyhow::bail!("Schema validation failed: {}", err);
+        let validator =
+            jsonschema::validator_for(&schema_json).context("Failed to compile JSON schema")?;
+        let errors: Vec<String> = validator
+            .iter_errors(value)
+            .map(|err| {
+                let pointer = err.instance_path.to_string();
+                let position = positions
+                    .get(&pointer)
+                    .map(|position| format!(" ({})", position.describe(yaml_path)))
+                    .unwrap_or_default();
+                let pointer = if pointer.is_empty() { "/" } else { &pointer };
+                format!("  - {}{}: {}", pointer, position, err)
+            })
+            .collect();
+        if !errors.is_empty() {
+            anyhow::bail!("Schema validation failed:\n{}", errors.join("\n"));
+        }
+
+        Ok(())
+    }
+
+    /// Parses a YAML document into its JSON equivalent.
+    ///
+    /// Going through a JSON value lets serde handle plain scalars as enum
+    /// variants, which YAML deserializers do not reliably support.
+    fn yaml_to_json(yaml: &str) -> Result<Value> {
+        let mut docs = YamlLoader::load_from_str(yaml).map_err(|err| {
+            anyhow::anyhow!(
+                "Failed to parse YAML at line {}, column {}: {}",
+                err.marker().line(),
+                err.marker().col() + 1,
+                err.info()
+            )
+        })?;
+        anyhow::ensure!(!docs.is_empty(), "Configuration file is empty");
+        Self::convert_yaml(docs.swap_remove(0))
+    }
+
+    /// Converts a YAML node, its aliases already resolved by the loader.
+    /// JSON has no NaN nor infinities, so these are errors.
+    fn convert_yaml(yaml: Yaml) -> Result<Value> {
+        Ok(match yaml {
+            Yaml::Real(real) => {
+                let float = Yaml::Real(real.clone())
+                    .as_f64()
+                    .with_context(|| format!("Invalid number: '{}'", real))?;
+                let number = Number::from_f64(float)
+                    .with_context(|| format!("Invalid number: '{}', expected a finite one", real))?;
+                Value::Number(number)
+            }
+            Yaml::Integer(int) => Value::from(int),
+            Yaml::String(string) => Value::String(string),
+            Yaml::Boolean(boolean) => Value::Bool(boolean),
+            Yaml::Array(array) => Value::Array(
+                array
+                    .into_iter()
+                    .map(Self::convert_yaml)
+                    .collect::<Result<_>>()?,
+            ),
+            Yaml::Hash(hash) => {
+                let mut map = Map::new();
+                for (key, value) in hash {
+                    let key = match key {
+                        Yaml::String(key) => key,
+                        Yaml::Integer(key) => key.to_string(),
+                        Yaml::Boolean(key) => key.to_string(),
+                        Yaml::Real(key) => key,
+                        key => anyhow::bail!("Unsupported YAML key: {:?}", key),
+                    };
+                    map.insert(key, Self::convert_yaml(value)?);
+                }
+                Value::Object(map)
+            }
+            Yaml::Null => Value::Null,
+            Yaml::Alias(_) | Yaml::BadValue => anyhow::bail!("Unsupported YAML value"),
+        })
+    }
+
+    /// Expands the environment variables referenced by the texts, commands,
+    /// hosts, users, passwords and paths of every stage.
+    ///
+    /// Returns the secrets of the `$file:` references of the commands, which
+    /// are only read in when they run.
+    fn expand_env(&mut self) -> Result<Vec<String>> {
+        let file_secrets = RefCell::new(Vec::new());
+        let expansion = Expansion {
+            missing: self.missing_env.unwrap_or_default(),
+            captured: &self.captured,
+            file_secrets: &file_secrets,
+        };
+        for (stage_idx, stage) in self.stages.iter_mut().enumerate() {
+            Self::expand_stage_env(stage, expansion).with_context(|| {
+                format!(
+                    "Failed to expand the environment variables of stage {} ('{}')",
+                    stage_idx + 1,
+                    stage.name
+                )
+            })?;
         }
+        Ok(file_secrets.into_inner())
+    }
 
+    fn expand_stage_env(stage: &mut Stage, expansion: Expansion<'_>) -> Result<()> {
+        for command in stage.setup.iter_mut().chain(stage.teardown.iter_mut()) {
+            command.expand_env(expansion)?;
+        }
+        for action_config in &mut stage.actions {
+            Self::expand_action_env(action_config, expansion)?;
+        }
+        Ok(())
+    }
+
+    fn expand_action_env(action_config: &mut ActionConfig, expansion: Expansion<'_>) -> Result<()> {
+        expand_env_opt(&mut action_config.label, expansion, false)?;
+        match &mut action_config.action {
+            Action::Message { text, .. } => *text = expand_env(text, expansion, false)?,
+            Action::Command(command) => {
+                command.command.expand_env(expansion)?;
+                if let Some(remotes) = &mut command.remote {
+                    remotes.expand_env(expansion)?;
+                }
+                if let Some(sudo) = &mut command.sudo {
+                    sudo.expand_env(expansion)?;
+                }
+                expand_env_opt(&mut command.cwd, expansion, false)?;
+                // The input is often a script, with variables of its own.
+                expand_env_opt(&mut command.stdin, expansion, true)?;
+            }
+            Action::WaitFor(wait_for) => {
+                wait_for.command.expand_env(expansion)?;
+                if let Some(remote) = &mut wait_for.remote {
+                    remote.expand_env(expansion)?;
+                }
+                if let Some(sudo) = &mut wait_for.sudo {
+                    sudo.expand_env(expansion)?;
+                }
+            }
+            Action::Upload(transfer) | Action::Download(transfer) => {
+                transfer.local = expand_env(&transfer.local, expansion, false)?;
+                transfer.remote_path = expand_env(&transfer.remote_path, expansion, false)?;
+                if let Some(remote) = &mut transfer.remote {
+                    remote.expand_env(expansion)?;
+                }
+            }
+            Action::Parallel { actions } => {
+                for child in actions {
+                    Self::expand_action_env(child, expansion)?;
+                }
+            }
+            Action::Pause { .. } | Action::Wait { .. } | Action::Clear => {}
+        }
         Ok(())
     }
 
-    pub fn load_config(yaml_path: &Path) -> Result<Self> {
+    /// Checks the styles that the JSON schema cannot fully validate.
+    fn validate_styles(&self) -> Result<()> {
+        if let Some(style) = &self.default_style {
+            style.validate().context("Invalid default style")?;
+        }
+        for (stage_idx, stage) in self.stages.iter().enumerate() {
+            if let Some(style) = &stage.style {
+                style.validate().with_context(|| {
+                    format!("Invalid style in stage {} ('{}')", stage_idx + 1, stage.name)
+                })?;
+            }
+            for (action_idx, action_config) in stage.actions.iter().enumerate() {
+                let styles: Vec<&StyleConfig> = match &action_config.action {
+                    Action::Message { style, .. } => style.iter().collect(),
+                    Action::Command(_) | Action::Parallel { .. } => action_config
+                        .action
+                        .commands()
+                        .into_iter()
+                        .filter_map(|command| command.style.as_ref())
+                        .collect(),
+                    Action::WaitFor(_)
+                    | Action::Upload(_)
+                    | Action::Download(_)
+                    | Action::Pause { .. }
+                    | Action::Wait { .. }
+                    | Action::Clear => Vec::new(),
+                };
+                for style in styles {
+                    style.validate().with_context(|| {
+                        format!(
+                            "Invalid style in stage {} ('{}'), action {}",
+                            stage_idx + 1,
+                            stage.name,
+                            action_idx + 1
+                        )
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that there is something to present, as a custom schema may not.
+    fn validate_stages(&self) -> Result<()> {
+        if self.stages.is_empty() {
+            anyhow::bail!("Nothing to present: the configuration has no stages");
+        }
+        for (stage_idx, stage) in self.stages.iter().enumerate() {
+            if stage.actions.is_empty() {
+                anyhow::bail!("Stage {} ('{}') has no actions", stage_idx + 1, stage.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that no two stages share a name, listing the stages of every
+    /// name given more than once.
+    fn validate_stage_names(&self) -> Result<()> {
+        let mut names: Vec<(&str, Vec<usize>)> = Vec::new();
+        for (stage_idx, stage) in self.stages.iter().enumerate() {
+            match names.iter_mut().find(|(name, _)| *name == stage.name) {
+                Some((_, stages)) => stages.push(stage_idx + 1),
+                None => names.push((&stage.name, vec![stage_idx + 1])),
+            }
+        }
+        let duplicates: Vec<String> = names
+            .iter()
+            .filter(|(_, stages)| stages.len() > 1)
+            .map(|(name, stages)| {
+                let (last, rest) = stages.split_last().unwrap();
+                let rest: Vec<String> = rest.iter().map(usize::to_string).collect();
+                format!("'{}' (stages {} and {})", name, rest.join(", "), last)
+            })
+            .collect();
+        if !duplicates.is_empty() {
+            anyhow::bail!("Duplicate stage names: {}", duplicates.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Checks that no loop sets both a count and a condition to end it.
+    fn validate_loops(&self) -> Result<()> {
+        for (stage_idx, stage) in self.stages.iter().enumerate() {
+            for (action_idx, action_config) in stage.actions.iter().enumerate() {
+                let loops = action_config.action.commands().into_iter();
+                for r#loop in loops.filter_map(|command| command.r#loop.as_ref()) {
+                    let conditional =
+                        r#loop.until_success.is_some() || r#loop.duration_ms.is_some();
+                    if r#loop.times.is_some() && conditional {
+                        anyhow::bail!(
+                            "Loop in stage {} ('{}'), action {} sets 'times' along with \
+                             'until_success' or 'duration_ms'",
+                            stage_idx + 1,
+                            stage.name,
+                            action_idx + 1
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that no command run as a script also sets `stdin`, as the
+    /// script is its input.
+    fn validate_scripts(&self) -> Result<()> {
+        for (stage_idx, stage) in self.stages.iter().enumerate() {
+            for (action_idx, action_config) in stage.actions.iter().enumerate() {
+                let commands = action_config.action.commands().into_iter();
+                if commands
+                    .filter(|command| command.command.is_script())
+                    .any(|command| command.stdin.is_some())
+                {
+                    anyhow::bail!(
+                        "Command in stage {} ('{}'), action {} is joined as a script, which is \
+                         its input, and cannot set 'stdin'",
+                        stage_idx + 1,
+                        stage.name,
+                        action_idx + 1
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that parallel groups only hold commands, which run without
+    /// conditions or confirmation as the group is a single step.
+    fn validate_parallel(&self) -> Result<()> {
+        for (stage_idx, stage) in self.stages.iter().enumerate() {
+            for (action_idx, action_config) in stage.actions.iter().enumerate() {
+                let Action::Parallel { actions } = &action_config.action else {
+                    continue;
+                };
+                for (child_idx, child) in actions.iter().enumerate() {
+                    let problem = match &child.action {
+                        Action::Command(command)
+                            if command.confirm.unwrap_or(false)
+                                || command.confirm_prompt.is_some() =>
+                        {
+                            "cannot ask for confirmation"
+                        }
+                        Action::Command(_) if child.when.is_some() => "cannot set 'when'",
+                        Action::Command(_) => continue,
+                        _ => "must be a command",
+                    };
+                    anyhow::bail!(
+                        "Parallel group in stage {} ('{}'), action {}: action {} {}",
+                        stage_idx + 1,
+                        stage.name,
+                        action_idx + 1,
+                        child_idx + 1,
+                        problem
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every file transfer has a remote host to copy to or from.
+    fn validate_transfers(&self) -> Result<()> {
+        for (stage_idx, stage) in self.stages.iter().enumerate() {
+            for (action_idx, action_config) in stage.actions.iter().enumerate() {
+                let name = match &action_config.action {
+                    Action::Upload(TransferConfig { remote: None, .. }) => "Upload",
+                    Action::Download(TransferConfig { remote: None, .. }) => "Download",
+                    _ => continue,
+                };
+                anyhow::bail!(
+                    "{} in stage {} ('{}'), action {} needs a 'remote' host",
+                    name,
+                    stage_idx + 1,
+                    stage.name,
+                    action_idx + 1
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists the distinct `user@host:port` remotes contacted by the actions,
+    /// in order of first appearance.
+    pub fn remote_hosts(&self) -> Vec<String> {
+        let mut hosts: Vec<String> = Vec::new();
+        let actions = self.stages.iter().flat_map(|stage| &stage.actions);
+        let remotes = actions.flat_map(|action_config| match &action_config.action {
+            Action::Command(_) | Action::Parallel { .. } => action_config
+                .action
+                .commands()
+                .into_iter()
+                .flat_map(|command| command.remote.as_ref().map_or(&[][..], Remotes::hosts))
+                .collect(),
+            Action::WaitFor(wait_for) => wait_for.remote.iter().collect(),
+            Action::Upload(transfer) | Action::Download(transfer) => {
+                transfer.remote.iter().collect()
+            }
+            Action::Message { .. }
+            | Action::Pause { .. }
+            | Action::Wait { .. }
+            | Action::Clear => Vec::new(),
+        });
+        for remote in remotes {
+            let mut host = match &remote.user {
+                Some(user) => format!("{}@{}", user, remote.host),
+                None => remote.host.clone(),
+            };
+            if let Some(port) = remote.port {
+                host.push_str(&format!(":{}", port));
+            }
+            if !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        }
+        hosts
+    }
+
+    /// Recursively merges `overlay` onto `base`: objects are merged key by
+    /// key, while any other value (scalars, arrays, null) replaces the base one.
+    fn merge_values(base: &mut Value, overlay: Value) {
+        match (base, overlay) {
+            (Value::Object(base), Value::Object(overlay)) => {
+                for (key, value) in overlay {
+                    match base.get_mut(&key) {
+                        Some(base_value) => Self::merge_values(base_value, value),
+                        None => {
+                            base.insert(key, value);
+                        }
+                    }
+                }
+            }
+            (base, overlay) => *base = overlay,
+        }
+    }
+
+    /// Reads the YAML file at `yaml_path`, along with the positions of its
+    /// nodes.
+    fn read_yaml(yaml_path: &Path) -> Result<(Value, Positions)> {
         let yaml_config = fs::read_to_string(PathBuf::from(yaml_path))
-            .context("Should have been able to read the file")?;
+            .with_context(|| format!("Failed to read '{}'", yaml_path.display()))?;
+        let value = Self::yaml_to_json(yaml_config.as_str())
+            .with_context(|| format!("Failed to parse '{}'", yaml_path.display()))?;
+        Ok((value, Positions::parse(&yaml_config, yaml_path)))
+    }
+
+    /// Reads the configuration at `yaml_path`, replacing every `include`
+    /// entry of its stage list with the stages of the file it names.
+    ///
+    /// `including` holds the files whose includes are being expanded, so
+    /// that a file including itself is reported.
+    fn read_config(
+        yaml_path: &Path,
+        including: &mut Vec<PathBuf>,
+    ) -> Result<(Value, Positions)> {
+        let canonical = fs::canonicalize(yaml_path)
+            .with_context(|| format!("Failed to read '{}'", yaml_path.display()))?;
+        if including.contains(&canonical) {
+            let chain: Vec<String> = including
+                .iter()
+                .chain([&canonical])
+                .map(|path| path.display().to_string())
+                .collect();
+            anyhow::bail!("Include cycle: {}", chain.join(" -> "));
+        }
+
+        let (mut value, mut positions) = Self::read_yaml(yaml_path)?;
+        let Some(Value::Array(stages)) = value.get_mut("stages") else {
+            return Ok((value, positions));
+        };
+        including.push(canonical);
+        // The stages are numbered anew as includes expand.
+        let stage_positions = positions.split_off("/stages");
+        let mut expanded = Vec::with_capacity(stages.len());
+        for (stage_idx, stage) in stages.drain(..).enumerate() {
+            let Some(include) = stage.get("include") else {
+                positions.move_from(
+                    &stage_positions,
+                    &format!("/stages/{}", stage_idx),
+                    &format!("/stages/{}", expanded.len()),
+                );
+                expanded.push(stage);
+                continue;
+            };
+            let include = include.as_str().with_context(|| {
+                format!("Invalid include in '{}': expected a path", yaml_path.display())
+            })?;
+            // Relative paths are relative to the including file.
+            let include_path = yaml_path.parent().unwrap_or(Path::new("")).join(include);
+            let (mut included, included_positions) = Self::read_config(&include_path, including)?;
+            let Some(Value::Array(included)) = included.get_mut("stages") else {
+                anyhow::bail!("Included file '{}' has no stages", include_path.display());
+            };
+            for (included_idx, stage) in included.drain(..).enumerate() {
+                positions.move_from(
+                    &included_positions,
+                    &format!("/stages/{}", included_idx),
+                    &format!("/stages/{}", expanded.len()),
+                );
+                expanded.push(stage);
+            }
+        }
+        including.pop();
+        *stages = expanded;
 
-        let config: Config = serde_yaml2::from_str(yaml_config.as_str())
-            .context("Failed to convert YAML to JSON")?;
-        Self::validate_config(&config)?;
+        Ok((value, positions))
+    }
+
+    /// Resolves the `vars` of a configuration, reading the values marked with
+    /// the `$env:` prefix from the environment, and those marked with the
+    /// `$file:` prefix from a file.
+    fn resolve_vars(vars: &Value) -> Result<HashMap<String, String>> {
+        let vars: HashMap<String, String> = serde_json::from_value(vars.clone())
+            .context("Invalid vars: expected a map of strings")?;
+        vars.into_iter()
+            .map(|(name, value)| {
+                if let Some(env_var) = value.strip_prefix("$env:") {
+                    env::var(env_var)
+                        .with_context(|| format!("Missing environment variable: '{}'", env_var))
+                        .map(|value| (name, value))
+                } else if let Some(path) = value.strip_prefix("$file:") {
+                    read_secret_file(path.trim()).map(|value| (name, value))
+                } else {
+                    Ok((name, value))
+                }
+            })
+            .collect()
+    }
+
+    /// The names of the variables captured by the commands of `stages`, as
+    /// written in the configuration.
+    fn captured_names(stages: &Value) -> HashSet<String> {
+        let mut names = HashSet::new();
+        let mut pending = vec![stages];
+        while let Some(value) = pending.pop() {
+            match value {
+                Value::Array(array) => pending.extend(array),
+                Value::Object(map) => {
+                    if let Some(Value::String(name)) = map.get("capture") {
+                        names.insert(name.clone());
+                    }
+                    pending.extend(map.values());
+                }
+                _ => {}
+            }
+        }
+        names
+    }
+
+    /// Replaces the `{{ name }}` placeholders of every string in `value`,
+    /// but for those of the `captured` variables.
+    fn expand_vars(
+        value: &mut Value,
+        vars: &HashMap<String, String>,
+        captured: &HashSet<String>,
+    ) -> Result<()> {
+        match value {
+            Value::String(text) => *text = Self::expand_str(text, vars, captured)?,
+            Value::Array(array) => {
+                for item in array {
+                    Self::expand_vars(item, vars, captured)?;
+                }
+            }
+            Value::Object(map) => {
+                for item in map.values_mut() {
+                    Self::expand_vars(item, vars, captured)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Replaces the `{{ name }}` placeholders of `text`.
+    ///
+    /// Only names made of alphanumerics, `_` and `-` are placeholders, so that
+    /// templates meant for the commands themselves (e.g. `{{.State}}`) are
+    /// left untouched.
+    fn expand_str(
+        text: &str,
+        vars: &HashMap<String, String>,
+        captured: &HashSet<String>,
+    ) -> Result<String> {
+        let mut expanded = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find("{{") {
+            let Some(len) = rest[start..].find("}}") else {
+                break;
+            };
+            let name = rest[start + 2..start + len].trim();
+            let end = start + len + 2;
+            // The iteration of a loop, and the output of a command, are only
+            // known when the commands run.
+            if name.is_empty()
+                || (name == ITER_VAR && !vars.contains_key(name))
+                || captured.contains(name)
+                || !name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            {
+                expanded.push_str(&rest[..end]);
+            } else {
+                let value = vars
+                    .get(name)
+                    .with_context(|| format!("Unknown variable '{}' in '{}'", name, text))?;
+                expanded.push_str(&rest[..start]);
+                expanded.push_str(value);
+            }
+            rest = &rest[end..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+
+    /// Loads the configuration at `yaml_path`, with every overlay merged onto
+    /// it in order before validation against the schema.
+    pub fn load_config(
+        yaml_path: &Path,
+        overlays: &[PathBuf],
+        schema_path: Option<&Path>,
+    ) -> Result<Self> {
+        let (mut value, mut positions) = Self::read_config(yaml_path, &mut Vec::new())?;
+        for overlay in overlays {
+            let (overlay, overlay_positions) = Self::read_config(overlay, &mut Vec::new())?;
+            positions.merge(&value, &overlay, &overlay_positions, "");
+            Self::merge_values(&mut value, overlay);
+        }
+        let captured = Self::captured_names(&value["stages"]);
+        if let Some(vars) = value.as_object_mut().and_then(|config| config.remove("vars")) {
+            Self::expand_vars(&mut value, &Self::resolve_vars(&vars)?, &captured)?;
+            value["vars"] = vars;
+        }
+        // The schema describes the configuration as written, so that it
+        // points at the offending field rather than at its parsed form.
+        Self::validate_config(&value, schema_path, &positions, yaml_path)?;
+        let mut config: Config =
+            serde_json::from_value(value).context("Failed to parse configuration")?;
+        config.captured = captured;
+        let file_secrets = config.expand_env()?;
+        config.validate_stages()?;
+        config.validate_stage_names()?;
+        config.validate_styles()?;
+        config.validate_transfers()?;
+        config.validate_loops()?;
+        config.validate_scripts()?;
+        config.validate_parallel()?;
+        Keymap::new(config.keybindings.as_ref())?;
+        // The secrets read by the commands are masked in their output too.
+        let patterns: Vec<String> = config
+            .redact
+            .iter()
+            .flatten()
+            .cloned()
+            .chain(
+                file_secrets
+                    .iter()
+                    .filter(|secret| !secret.is_empty())
+                    .map(|secret| regex::escape(secret)),
+            )
+            .collect();
+        config.redactions = redact::compile(&patterns)?;
 
         Ok(config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A path of the temporary directory for the file `name` of a test.
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("autopilot-{}-{}", std::process::id(), name))
+    }
+
+    /// Loads `yaml` as the configuration file `name`, validated against the
+    /// schema at `schema_path` if given.
+    fn load_with_schema(name: &str, yaml: &str, schema_path: Option<&Path>) -> Result<Config> {
+        let path = temp_path(&format!("{}.yaml", name));
+        fs::write(&path, yaml).unwrap();
+        let config = Config::load_config(&path, &[], schema_path);
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    /// Loads `yaml` as the configuration file `name`.
+    fn load(name: &str, yaml: &str) -> Result<Config> {
+        load_with_schema(name, yaml, None)
+    }
+
+    /// The error loading `yaml` fails with, and its causes.
+    fn load_error(name: &str, yaml: &str) -> String {
+        match load(name, yaml) {
+            Ok(_) => panic!("configuration {} loads", name),
+            Err(e) => format!("{:#}", e),
+        }
+    }
+
+    /// The configuration `value` stands for, unvalidated.
+    fn parse(value: Value) -> Config {
+        serde_json::from_value(value).expect("configuration parses")
+    }
+
+    #[test]
+    fn empty_configurations_are_errors() {
+        assert!(load_error("no-stages", "stages: []\n").contains("/stages"));
+        let error = load_error("no-actions", "stages:\n  - name: A\n    actions: []\n");
+        assert!(error.contains("/stages/0/actions"));
+
+        // Without the schema, validation catches them all the same.
+        let error = parse(json!({ "stages": [] })).validate_stages().unwrap_err();
+        assert_eq!(error.to_string(), "Nothing to present: the configuration has no stages");
+        let config = parse(json!({
+            "stages": [
+                { "name": "A", "actions": [{ "type": "clear" }] },
+                { "name": "B", "actions": [] },
+            ]
+        }));
+        let error = config.validate_stages().unwrap_err();
+        assert_eq!(error.to_string(), "Stage 2 ('B') has no actions");
+    }
+
+    /// `text` with its environment variables expanded, as on load.
+    fn expand(text: &str, shell: bool) -> Result<String> {
+        let expansion = Expansion {
+            missing: MissingEnv::Error,
+            captured: &HashSet::new(),
+            file_secrets: &RefCell::new(Vec::new()),
+        };
+        expand_env(text, expansion, shell)
+    }
+
+    #[test]
+    fn commands_expand_in_place() {
+        env::set_var("AUTOPILOT_TEST_TAG", "v1.2");
+        env::set_var("AUTOPILOT_TEST_MESSAGE", "two words");
+        assert_eq!(
+            expand("podman build --tag=prefix-$env:AUTOPILOT_TEST_TAG .", true).unwrap(),
+            "podman build --tag=prefix-v1.2 ."
+        );
+        // Quotes and spacing are kept for the shell.
+        assert_eq!(
+            expand("echo \"$env:AUTOPILOT_TEST_MESSAGE\"\t'a  b'", true).unwrap(),
+            "echo \"two words\"\t'a  b'"
+        );
+        assert_eq!(
+            expand("echo $env:AUTOPILOT_TEST_TAG\t$HOME ${HOME}", true).unwrap(),
+            "echo v1.2\t$HOME ${HOME}"
+        );
+    }
+
+    #[test]
+    fn braced_references_expand_outside_of_commands() {
+        env::set_var("AUTOPILOT_TEST_HOST", "db.example.com");
+        assert_eq!(
+            expand("admin@$env:AUTOPILOT_TEST_HOST", false).unwrap(),
+            "admin@db.example.com"
+        );
+        assert_eq!(
+            expand("https://${AUTOPILOT_TEST_HOST}/", false).unwrap(),
+            "https://db.example.com/"
+        );
+        assert_eq!(
+            expand("$${AUTOPILOT_TEST_HOST} costs $5", false).unwrap(),
+            "${AUTOPILOT_TEST_HOST} costs $5"
+        );
+    }
+
+    #[test]
+    fn missing_variables_are_errors_unless_empty() {
+        let error = expand("ssh ${AUTOPILOT_TEST_UNSET}", false).unwrap_err();
+        assert_eq!(error.to_string(), "Missing environment variable: 'AUTOPILOT_TEST_UNSET'");
+        let expansion = Expansion {
+            missing: MissingEnv::Empty,
+            captured: &HashSet::new(),
+            file_secrets: &RefCell::new(Vec::new()),
+        };
+        assert_eq!(
+            expand_env("[$env:AUTOPILOT_TEST_UNSET]", expansion, true).unwrap(),
+            "[]"
+        );
+    }
+
+    #[test]
+    fn file_secrets_stay_out_of_commands() {
+        let secret_path = temp_path("token");
+        fs::write(&secret_path, "hunter2\n").unwrap();
+        let yaml = format!(
+            "stages:\n  - name: A\n    actions:\n      - type: message\n        \
+             text: Token $file:{0}\n      - type: command\n        \
+             command: curl -u admin:$file:{0} https://example.com\n",
+            secret_path.display()
+        );
+        let config = load("file-secrets", &yaml);
+        fs::remove_file(&secret_path).unwrap();
+        let config = config.unwrap();
+        let actions = &config.stages[0].actions;
+        let Action::Message { text, .. } = &actions[0].action else {
+            panic!("first action is not a message");
+        };
+        assert_eq!(text, "Token hunter2");
+        let command = format!(
+            "$ curl -u admin:$file:{} https://example.com",
+            secret_path.display()
+        );
+        assert_eq!(actions[1].action.summary(), command);
+        // The output of the command is masked as well.
+        let masked = redact::apply(&config.redactions, "admin:hunter2");
+        assert_eq!(masked, format!("admin:{}", redact::MASK));
+    }
+
+    /// A configuration with a stage of every name of `names`.
+    fn with_stages(names: &[&str]) -> Config {
+        let stages: Vec<Value> = names
+            .iter()
+            .map(|name| json!({ "name": name, "actions": [{ "type": "clear" }] }))
+            .collect();
+        parse(json!({ "stages": stages }))
+    }
+
+    #[test]
+    fn distinct_stage_names_are_valid() {
+        assert!(with_stages(&["Setup", "Demo", "setup"]).validate_stage_names().is_ok());
+    }
+
+    #[test]
+    fn duplicate_stage_names_list_their_stages() {
+        let error = with_stages(&["Setup", "Demo", "Setup"])
+            .validate_stage_names()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Duplicate stage names: 'Setup' (stages 1 and 3)");
+
+        let error = with_stages(&["A", "B", "A", "B", "A"])
+            .validate_stage_names()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Duplicate stage names: 'A' (stages 1, 3 and 5), 'B' (stages 2 and 4)"
+        );
+    }
+
+    #[test]
+    fn command_lists_join_with_their_operator() {
+        let joined = |value: Value| {
+            serde_json::from_value::<CommandType>(value)
+                .expect("command parses")
+                .get_command()
+        };
+        assert_eq!(joined(json!(["make", "make test"])), "make && make test");
+        assert_eq!(joined(json!({ "commands": ["make", "make test"] })), "make && make test");
+        assert_eq!(
+            joined(json!({ "commands": ["ping -c1 a", "ping -c1 b"], "join": "||" })),
+            "ping -c1 a || ping -c1 b"
+        );
+        assert_eq!(
+            joined(json!({ "commands": ["false", "true"], "join": ";" })),
+            "false; true"
+        );
+        assert!(serde_json::from_value::<CommandType>(
+            json!({ "commands": ["false"], "join": "|" })
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn schemas_can_be_replaced() {
+        let yaml = "stages:\n  - name: A\n    actions:\n      - type: clear\n";
+        assert!(load("built-in-schema", yaml).is_ok());
+
+        // A schema of its own may be stricter than the built-in one.
+        let schema_path = temp_path("schema.json");
+        let schema = json!({ "type": "object", "required": ["title"] });
+        fs::write(&schema_path, schema.to_string()).unwrap();
+        let error = load_with_schema("own-schema", yaml, Some(&schema_path));
+        fs::remove_file(&schema_path).unwrap();
+        let Err(error) = error else {
+            panic!("configuration without a title loads");
+        };
+        assert!(format!("{:#}", error).contains("\"title\" is a required property"));
+
+        let Err(error) = load_with_schema("missing-schema", yaml, Some(&schema_path)) else {
+            panic!("configuration loads without its schema");
+        };
+        assert!(error.to_string().starts_with("Failed to read schema file"));
+    }
+
+    #[test]
+    fn shipped_configurations_match_the_schema_and_the_types() {
+        // The schema is written by hand, so every configuration shipped
+        // with autopilot must both validate and deserialize.
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut paths = Vec::new();
+        for dir in ["examples", "tests/fixtures"] {
+            for entry in fs::read_dir(root.join(dir)).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().is_some_and(|extension| extension == "yaml") {
+                    paths.push(path);
+                }
+            }
+        }
+        assert!(!paths.is_empty());
+        for path in paths {
+            let (value, positions) = Config::read_config(&path, &mut Vec::new()).unwrap();
+            if let Err(e) = Config::validate_config(&value, None, &positions, &path) {
+                panic!("'{}' does not match the schema: {:#}", path.display(), e);
+            }
+            if let Err(e) = serde_json::from_value::<Config>(value) {
+                panic!("'{}' does not deserialize: {}", path.display(), e);
+            }
+        }
+    }
+
+    #[test]
+    fn every_field_is_in_the_schema() {
+        // Struct literals rather than `..Default::default()`, so that a new
+        // field has to be added here, and so checked against the schema.
+        let style = || StyleConfig {
+            color: Some(String::from("red")),
+            bold: Some(true),
+            italic: Some(true),
+            underline: Some(true),
+            strikethrough: Some(true),
+            dim: Some(true),
+            background: Some(String::from("black")),
+        };
+        let sudo = || SudoConfig {
+            user: Some(String::from("root")),
+            password: Some(String::from("secret")),
+            request_pty: Some(true),
+        };
+        let remote = |jump: Option<RemoteConfig>| RemoteConfig {
+            host: String::from("server"),
+            port: Some(22),
+            user: Some(String::from("user")),
+            password: Some(String::from("secret")),
+            private_key: Some(String::from("~/.ssh/id_ed25519")),
+            use_agent: Some(true),
+            strict_host_keys: Some(true),
+            jump: jump.map(Box::new),
+            connect_timeout_ms: Some(1000),
+            auth: Some(AuthMethod::Key),
+            preflight: Some(true),
+        };
+        let transfer = || TransferConfig {
+            local: String::from("local.txt"),
+            remote_path: String::from("/tmp/remote.txt"),
+            remote: Some(remote(None)),
+            overwrite: Some(true),
+        };
+        let command = |command: CommandType| CommandConfig {
+            command,
+            sudo: Some(sudo()),
+            hide_stdout: Some(true),
+            hide_stderr: Some(true),
+            style: Some(style()),
+            remote: Some(Remotes::Multiple(vec![remote(Some(remote(None)))])),
+            parallel: Some(true),
+            r#loop: Some(LoopConfig {
+                times: Some(2),
+                until_success: Some(true),
+                duration_ms: Some(1000),
+                delay: Some(10),
+            }),
+            retry: Some(RetryConfig {
+                attempts: 3,
+                delay_ms: Some(10),
+                backoff_factor: Some(2.0),
+            }),
+            output_prefix: Some(String::from("> ")),
+            diff: Some(true),
+            merge_streams: Some(true),
+            strip_ansi: Some(true),
+            max_output_bytes: Some(1024),
+            on_error: Some(OnError::Prompt),
+            shell: Some(String::from("bash")),
+            shell_args: Some(vec![String::from("-c")]),
+            cwd: Some(String::from("/tmp")),
+            stdin: Some(String::from("input")),
+            timeout_ms: Some(1000),
+            confirm: Some(true),
+            confirm_prompt: Some(String::from("Run it?")),
+            capture: Some(String::from("OUTPUT")),
+        };
+        let action = |action: Action| ActionConfig {
+            action,
+            when: Some(When::OnSuccess),
+            label: Some(String::from("Label")),
+        };
+        let actions = vec![
+            action(Action::Message {
+                text: String::from("Hello"),
+                style: Some(style()),
+                speed: Some(10),
+                reveal: Some(Reveal::Word),
+                align: Some(Align::Center),
+                prefix: Some(String::from("# ")),
+            }),
+            action(Action::Command(Box::new(command(CommandType::Single(
+                String::from("true"),
+            ))))),
+            action(Action::Command(Box::new(command(CommandType::Joined {
+                commands: vec![String::from("true")],
+                join: JoinOperator::Script,
+            })))),
+            action(Action::WaitFor(WaitForConfig {
+                command: CommandType::ExecArgv {
+                    argv: vec![String::from("true")],
+                },
+                remote: Some(remote(None)),
+                sudo: Some(sudo()),
+                interval_ms: Some(10),
+                timeout_ms: Some(1000),
+            })),
+            action(Action::Upload(transfer())),
+            action(Action::Download(transfer())),
+            action(Action::Pause {
+                prompt: Some(String::from("Go on")),
+            }),
+            action(Action::Wait { millis: 10 }),
+            action(Action::Clear),
+            action(Action::Parallel {
+                actions: vec![action(Action::Command(Box::new(command(
+                    CommandType::Multiple(vec![String::from("true")]),
+                ))))],
+            }),
+        ];
+        let config = Config {
+            stages: vec![Stage {
+                name: String::from("Stage"),
+                actions,
+                align: Some(Align::Right),
+                prefix: Some(String::from("# ")),
+                style: Some(style()),
+                setup: Some(CommandType::Single(String::from("true"))),
+                teardown: Some(CommandType::Multiple(vec![String::from("true")])),
+            }],
+            ui: Some(UiConfig {
+                title: Some(String::from("Title")),
+                border: Some(BorderStyle::Rounded),
+                show_line_numbers: Some(true),
+                show_instructions: Some(true),
+                wrap: Some(true),
+                show_outline: Some(true),
+                show_timing: Some(true),
+                attract_after: Some(60),
+                tick_ms: Some(50),
+            }),
+            prefetch: Some(true),
+            prefix: Some(String::from("# ")),
+            default_style: Some(style()),
+            output_prefix: Some(String::from("> ")),
+            max_output_bytes: Some(1024),
+            max_lines: Some(10),
+            redact: Some(vec![String::from("secret")]),
+            redactions: Vec::new(),
+            captured: HashSet::new(),
+            shell: Some(String::from("bash")),
+            shell_args: Some(vec![String::from("-c")]),
+            vars: Some(HashMap::from([(String::from("NAME"), String::from("value"))])),
+            missing_env: Some(MissingEnv::Empty),
+            keybindings: Some(HashMap::from([
+                (KeyAction::Next, KeySpecs::Single(String::from("n"))),
+                (KeyAction::Prev, KeySpecs::Multiple(vec![String::from("p")])),
+            ])),
+        };
+        let value = serde_json::to_value(&config).unwrap();
+        let path = Path::new("populated.yaml");
+        if let Err(e) = Config::validate_config(&value, None, &Positions::default(), path) {
+            panic!("A fully populated configuration does not match the schema: {:#}", e);
+        }
+    }
+
+    #[test]
+    fn schema_violations_report_their_position() {
+        let error = load_error("position", "stages:\n  - name: A\n    actions: []\n");
+        assert!(error.contains("/stages/0/actions (line 3, column 14): "), "{}", error);
+
+        // Included stages and overlays point into their own files.
+        let included = temp_path("included.yaml");
+        let yaml = "stages:\n  - name: B\n    actions:\n      - type: clear\n        \
+                    speed: fast\n";
+        fs::write(&included, yaml).unwrap();
+        let overlay = temp_path("overlay.yaml");
+        fs::write(&overlay, "stages:\n  - name: C\n    actions: {}\n").unwrap();
+        let path = temp_path("including.yaml");
+        let yaml = format!(
+            "stages:\n  - name: A\n    actions:\n      - type: clear\n  - include: {}\n",
+            included.display()
+        );
+        fs::write(&path, yaml).unwrap();
+        let error = Config::load_config(&path, &[], None).map(|_| ());
+        let overlays = std::slice::from_ref(&overlay);
+        let overlaid_error = Config::load_config(&path, overlays, None).map(|_| ());
+        for file in [&included, &overlay, &path] {
+            fs::remove_file(file).unwrap();
+        }
+        let Err(error) = error else {
+            panic!("configuration with an invalid include loads");
+        };
+        let expected = format!(
+            "/stages/1/actions/0/speed ('{}', line 5, column 16)",
+            included.display()
+        );
+        assert!(format!("{:#}", error).contains(&expected), "{:#}", error);
+        let Err(error) = overlaid_error else {
+            panic!("configuration with an invalid overlay loads");
+        };
+        let expected = format!("/stages/0/actions ('{}', line 3, column 14)", overlay.display());
+        assert!(format!("{:#}", error).contains(&expected), "{:#}", error);
+    }
+
+    #[test]
+    fn yaml_converts_to_json() {
+        let yaml = "base: &base { color: red, bold: true }\n\
+                    styles: [*base, { size: 1.5, none: ~ }]\n";
+        assert_eq!(
+            Config::yaml_to_json(yaml).unwrap(),
+            json!({
+                "base": { "color": "red", "bold": true },
+                "styles": [
+                    { "color": "red", "bold": true },
+                    { "size": 1.5, "none": null },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn non_finite_numbers_are_errors() {
+        for number in [".nan", ".inf", "-.inf"] {
+            let error = Config::yaml_to_json(&format!("speed: {}\n", number)).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("Invalid number: '{}', expected a finite one", number)
+            );
+        }
+        assert!(Config::yaml_to_json("").is_err());
+    }
+
+    #[test]
+    fn expand_iteration_replaces_every_form() {
+        assert_eq!(
+            expand_iteration("node-{{ AUTOPILOT_ITER }} $env:AUTOPILOT_ITER", 2, false),
+            "node-2 2"
+        );
+        assert_eq!(expand_iteration("${AUTOPILOT_ITER}", 1, false), "1");
+        // The shell expands its own variables.
+        assert_eq!(
+            expand_iteration("echo ${AUTOPILOT_ITER}", 1, true),
+            "echo ${AUTOPILOT_ITER}"
+        );
+        // Other names are left as they are.
+        assert_eq!(
+            expand_iteration("$env:AUTOPILOT_ITERATION {{ .State }}", 0, false),
+            "$env:AUTOPILOT_ITERATION {{ .State }}"
+        );
+    }
+
+    #[test]
+    fn captured_values_are_quoted_for_the_shell() {
+        let captured = HashSet::from([String::from("POD")]);
+        let values = HashMap::from([(String::from("POD"), String::from("it's; rm -rf x"))]);
+        let expand = |action: Value| {
+            let action: ActionConfig = serde_json::from_value(action).unwrap();
+            let action = action.expand_captured(&captured, &values).unwrap();
+            serde_json::to_value(action).unwrap()
+        };
+
+        let action = expand(json!({
+            "type": "parallel",
+            "actions": [
+                { "type": "command", "command": "kubectl logs {{ POD }}", "stdin": "{{ POD }}" },
+                { "type": "wait_for", "command": ["test -n $env:POD"] },
+                { "type": "command", "command": { "argv": ["echo", "{{ POD }}"] } },
+            ],
+        }));
+        assert_eq!(action["actions"][0]["command"], "kubectl logs 'it'\\''s; rm -rf x'");
+        assert_eq!(action["actions"][0]["stdin"], "it's; rm -rf x");
+        assert_eq!(action["actions"][1]["command"][0], "test -n 'it'\\''s; rm -rf x'");
+        assert_eq!(action["actions"][2]["command"]["argv"][1], "it's; rm -rf x");
+
+        // Within quotes, the value is escaped for them instead.
+        let action = expand(json!({
+            "type": "command",
+            "command": "echo \"pod: {{ POD }}\" 'pod: {{ POD }}' \"\\\"{{ POD }}\"",
+        }));
+        assert_eq!(
+            action["command"],
+            "echo \"pod: it's; rm -rf x\" 'pod: it'\\''s; rm -rf x' \"\\\"it's; rm -rf x\""
+        );
+        let values = HashMap::from([(String::from("POD"), String::from("a\"$(b)`c`\\"))]);
+        let action: ActionConfig =
+            serde_json::from_value(json!({ "type": "command", "command": "echo \"{{POD}}\"" }))
+                .unwrap();
+        let action = action.expand_captured(&captured, &values).unwrap();
+        assert_eq!(action.action.summary(), "$ echo \"a\\\"\\$(b)\\`c\\`\\\\\"");
+
+        // Values that are a single word already are left as they are.
+        let values = HashMap::from([(String::from("POD"), String::from("pod/demo-1"))]);
+        let action: ActionConfig =
+            serde_json::from_value(json!({ "type": "command", "command": "echo {{POD}}" }))
+                .unwrap();
+        let action = action.expand_captured(&captured, &values).unwrap();
+        assert_eq!(action.action.summary(), "$ echo pod/demo-1");
+        let action = expand(json!({ "type": "message", "text": "Pod: {{ POD }}" }));
+        assert_eq!(action["text"], "Pod: it's; rm -rf x");
+    }
+}
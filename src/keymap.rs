@@ -0,0 +1,288 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{bail, Context, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Reverse, collections::HashMap};
+
+use crate::config::KeySpecs;
+
+/// What a key does while the output is shown.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyAction {
+    Next,
+    Prev,
+    Skip,
+    Quit,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    Top,
+    Bottom,
+    LineNumbers,
+    Wrap,
+    Outline,
+    Instructions,
+    Help,
+    Contents,
+    Restart,
+    SelectPrev,
+    SelectNext,
+    Rerun,
+    Search,
+    NextMatch,
+    PrevMatch,
+    Copy,
+    CopyAll,
+    /// Opens the latest URL of the messages.
+    OpenLink,
+    /// Clears the selection and the search.
+    Clear,
+}
+
+impl KeyAction {
+    /// The name of the action in the configuration.
+    fn name(self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|value| value.as_str().map(String::from))
+            .unwrap_or_default()
+    }
+}
+
+/// Keys bound to every action unless the configuration binds others.
+const DEFAULTS: &[(KeyAction, &[&str])] = &[
+    (KeyAction::Next, &["right"]),
+    (KeyAction::Prev, &["left"]),
+    (KeyAction::Skip, &["s", "S"]),
+    (KeyAction::Quit, &["q", "Q"]),
+    (KeyAction::ScrollUp, &["up"]),
+    (KeyAction::ScrollDown, &["down"]),
+    (KeyAction::PageUp, &["pageup"]),
+    (KeyAction::PageDown, &["pagedown"]),
+    (KeyAction::Top, &["home"]),
+    (KeyAction::Bottom, &["end"]),
+    (KeyAction::LineNumbers, &["l", "L"]),
+    (KeyAction::Wrap, &["w", "W"]),
+    (KeyAction::Outline, &["b", "B"]),
+    (KeyAction::Instructions, &["i", "I"]),
+    (KeyAction::Help, &["?"]),
+    (KeyAction::Contents, &["t", "T"]),
+    (KeyAction::Restart, &["r", "R"]),
+    (KeyAction::SelectPrev, &["["]),
+    (KeyAction::SelectNext, &["]"]),
+    (KeyAction::Rerun, &["enter"]),
+    (KeyAction::Search, &["/"]),
+    (KeyAction::NextMatch, &["n"]),
+    (KeyAction::PrevMatch, &["N"]),
+    (KeyAction::Copy, &["y"]),
+    (KeyAction::CopyAll, &["Y"]),
+    (KeyAction::OpenLink, &["o", "O"]),
+    (KeyAction::Clear, &["esc"]),
+];
+
+/// Names of the keys that are not characters, as written in the
+/// configuration and shown in the interface.
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("Right", KeyCode::Right),
+    ("Left", KeyCode::Left),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("Enter", KeyCode::Enter),
+    ("Esc", KeyCode::Esc),
+    ("Tab", KeyCode::Tab),
+    ("BackTab", KeyCode::BackTab),
+    ("Backspace", KeyCode::Backspace),
+    ("Delete", KeyCode::Delete),
+    ("Insert", KeyCode::Insert),
+    ("Space", KeyCode::Char(' ')),
+];
+
+/// A key, along with the modifiers that must be held with it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyBinding {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    /// Parses a key such as `q`, `pagedown`, `f5` or `ctrl+n`.
+    ///
+    /// Characters are matched as typed, so `N` is a shifted `n`. Key names
+    /// and modifiers ignore case.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut key = spec;
+        // A `+` ending the spec is the key itself.
+        while let Some((modifier, rest)) = key.split_once('+').filter(|(_, rest)| !rest.is_empty())
+        {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => bail!(
+                    "Unknown modifier '{}' in key '{}', expected 'ctrl', 'alt' or 'shift'",
+                    modifier,
+                    spec
+                ),
+            };
+            key = rest;
+        }
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => Self::named(key).with_context(|| {
+                format!(
+                    "Unknown key '{}', expected a character, a name such as 'right' or \
+                     'pagedown', or 'f1' to 'f12'",
+                    spec
+                )
+            })?,
+        };
+        // The case of a character already tells whether shift is held.
+        let code = match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::Char(c.to_ascii_uppercase())
+            }
+            code => code,
+        };
+        Ok(Self { code, modifiers })
+    }
+
+    fn named(name: &str) -> Option<KeyCode> {
+        let lower = name.to_lowercase();
+        if let Some(number) = lower.strip_prefix('f') {
+            if let Ok(number @ 1..=12) = number.parse::<u8>() {
+                return Some(KeyCode::F(number));
+            }
+        }
+        let lower = match lower.as_str() {
+            "escape" => "esc",
+            "return" => "enter",
+            "del" => "delete",
+            name => name,
+        };
+        KEY_NAMES
+            .iter()
+            .find(|(key_name, _)| key_name.to_lowercase() == lower)
+            .map(|&(_, code)| code)
+    }
+
+    /// Whether `event` is this key, held with at least its modifiers.
+    fn matches(&self, event: &KeyEvent) -> bool {
+        let mut modifiers = event.modifiers;
+        if let KeyCode::Char(_) = event.code {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        event.code == self.code && modifiers.contains(self.modifiers)
+    }
+
+    /// The key as shown in the interface.
+    fn label(&self) -> String {
+        let mut label = String::new();
+        for (modifier, name) in [
+            (KeyModifiers::CONTROL, "Ctrl+"),
+            (KeyModifiers::ALT, "Alt+"),
+            (KeyModifiers::SHIFT, "Shift+"),
+        ] {
+            if self.modifiers.contains(modifier) {
+                label.push_str(name);
+            }
+        }
+        match KEY_NAMES.iter().find(|(_, code)| *code == self.code) {
+            Some((name, _)) => label.push_str(name),
+            None => match self.code {
+                KeyCode::Char(c) => label.push(c),
+                KeyCode::F(number) => label.push_str(&format!("F{}", number)),
+                _ => label.push('?'),
+            },
+        }
+        label
+    }
+}
+
+/// The keys bound to every action.
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    bindings: Vec<(KeyAction, Vec<KeyBinding>)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new(None).expect("default keybindings are valid")
+    }
+}
+
+impl Keymap {
+    /// Binds the keys of `config` to their actions, replacing the default
+    /// keys of those actions.
+    pub fn new(config: Option<&HashMap<KeyAction, KeySpecs>>) -> Result<Self> {
+        let mut bindings = Vec::new();
+        for &(action, defaults) in DEFAULTS {
+            let keys = match config.and_then(|config| config.get(&action)) {
+                Some(specs) => specs
+                    .specs()
+                    .iter()
+                    .map(|spec| KeyBinding::parse(spec))
+                    .collect::<Result<Vec<_>>>()
+                    .with_context(|| format!("Invalid keybinding for '{}'", action.name()))?,
+                None => defaults
+                    .iter()
+                    .map(|spec| KeyBinding::parse(spec))
+                    .collect::<Result<Vec<_>>>()?,
+            };
+            bindings.push((action, keys));
+        }
+        // Configured keys win over the default ones they clash with.
+        if let Some(config) = config {
+            bindings.sort_by_key(|(action, _)| !config.contains_key(action));
+        }
+        Ok(Self { bindings })
+    }
+
+    /// The action bound to the key of `event`, preferring the key that
+    /// requires the most modifiers.
+    pub fn action(&self, event: &KeyEvent) -> Option<KeyAction> {
+        self.bindings
+            .iter()
+            .flat_map(|(action, keys)| keys.iter().map(move |key| (*action, key)))
+            .filter(|(_, key)| key.matches(event))
+            .min_by_key(|(_, key)| Reverse(key.modifiers.bits().count_ones()))
+            .map(|(action, _)| action)
+    }
+
+    /// The keys bound to `action` as shown in the interface, separated by a
+    /// slash. A letter bound in both cases is shown once, in upper case.
+    pub fn label(&self, action: KeyAction) -> String {
+        let Some((_, keys)) = self.bindings.iter().find(|(bound, _)| *bound == action) else {
+            return String::new();
+        };
+        let mut labels: Vec<String> = Vec::new();
+        for key in keys {
+            let mut key = *key;
+            if let KeyCode::Char(c) = key.code {
+                let other = KeyBinding {
+                    code: KeyCode::Char(c.to_ascii_uppercase()),
+                    ..key
+                };
+                if c.is_ascii_lowercase() && keys.contains(&other) {
+                    key = other;
+                }
+            }
+            let label = key.label();
+            if !labels.contains(&label) {
+                labels.push(label);
+            }
+        }
+        labels.join("/")
+    }
+}
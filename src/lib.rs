@@ -0,0 +1,54 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Runs the presentations of `autopilot`, stages of messages and commands
+//! described in a YAML file.
+//!
+//! The items re-exported here are the stable interface of the crate:
+//!
+//! - [`Config`] loads and validates a configuration, with the types of its
+//!   fields in [`config`], such as [`Action`].
+//! - [`App`] runs it, headless with [`App::run_headless`] or driven by the
+//!   events of a terminal and drawn with [`render`], and reports the outcome
+//!   as a [`Summary`].
+//! - [`CommandSession`] runs a single command, locally or over the SSH
+//!   sessions of a [`SessionCache`].
+//!
+//! The hidden modules serve the `autopilot` binary. They, and the fields of
+//! [`App`] holding the state of the interface, may change in any release.
+
+mod ansi;
+mod app;
+#[doc(hidden)]
+pub mod cast;
+mod clipboard;
+#[doc(hidden)]
+pub mod color;
+pub mod config;
+mod diff;
+#[doc(hidden)]
+pub mod event;
+mod keymap;
+mod links;
+#[doc(hidden)]
+pub mod logging;
+mod positions;
+mod redact;
+#[doc(hidden)]
+pub mod resume;
+mod session;
+mod summary;
+#[doc(hidden)]
+pub mod transcript;
+#[doc(hidden)]
+pub mod tui;
+mod ui;
+
+pub use crate::{
+    app::{ActionStatus, App, AppResult, Mode},
+    config::{Action, Config},
+    session::{CommandSession, SessionCache},
+    summary::Summary,
+    ui::render,
+};
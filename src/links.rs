@@ -0,0 +1,121 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{bail, Result};
+use std::{
+    env,
+    ops::Range,
+    process::{Command, Stdio},
+    thread,
+};
+
+/// Characters ending a sentence or a clause, rather than a URL, when found
+/// at its end.
+const TRAILING: &[char] = &['.', ',', ';', ':', '!', '?', '\'', '"'];
+
+/// Byte ranges of the `http://` and `https://` URLs in `text`.
+///
+/// A URL runs up to the next whitespace, less the punctuation ending it,
+/// and less closing brackets that it does not open itself, such as those of
+/// `(see https://example.com)`.
+pub fn find_urls(text: &str) -> Vec<Range<usize>> {
+    let mut urls = Vec::new();
+    let mut from = 0;
+    while let Some(idx) = text[from..].find("http") {
+        let start = from + idx;
+        let rest = &text[start..];
+        let scheme = ["https://", "http://"]
+            .into_iter()
+            .find(|scheme| rest.starts_with(scheme));
+        // Part of a word, as in `xhttp://`, or no scheme at all.
+        let in_word = text[..start]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_alphanumeric);
+        let Some(scheme) = scheme.filter(|_| !in_word) else {
+            from = start + "http".len();
+            continue;
+        };
+        let len = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '`'))
+            .unwrap_or(rest.len());
+        let mut url = &rest[..len];
+        loop {
+            let trimmed = url.trim_end_matches(TRAILING);
+            let trimmed = match trimmed.chars().next_back() {
+                Some(close @ (')' | ']' | '}')) => {
+                    let open = match close {
+                        ')' => '(',
+                        ']' => '[',
+                        _ => '{',
+                    };
+                    if trimmed.matches(open).count() < trimmed.matches(close).count() {
+                        &trimmed[..trimmed.len() - 1]
+                    } else {
+                        trimmed
+                    }
+                }
+                _ => trimmed,
+            };
+            if trimmed.len() == url.len() {
+                break;
+            }
+            url = trimmed;
+        }
+        if url.len() > scheme.len() {
+            urls.push(start..start + url.len());
+        }
+        from = start + len.max(scheme.len());
+    }
+    urls
+}
+
+/// Opens `url` in a browser, in the background.
+///
+/// The browsers of `$BROWSER`, a `:` separated list of commands where `%s`
+/// stands for the URL, are tried in turn. Without it, the URL is handed to
+/// the opener of the desktop.
+pub fn open(url: &str) -> Result<()> {
+    let browsers = env::var("BROWSER").unwrap_or_default();
+    let mut commands: Vec<Vec<String>> = browsers
+        .split(':')
+        .filter(|browser| !browser.trim().is_empty())
+        .map(|browser| {
+            let mut args: Vec<String> = browser.split_whitespace().map(String::from).collect();
+            if args.iter().any(|arg| arg.contains("%s")) {
+                args.iter_mut()
+                    .for_each(|arg| *arg = arg.replace("%s", url));
+            } else {
+                args.push(url.to_owned());
+            }
+            args
+        })
+        .collect();
+    if commands.is_empty() {
+        let opener: &[&str] = if cfg!(target_os = "macos") {
+            &["open"]
+        } else if cfg!(windows) {
+            &["cmd", "/C", "start", ""]
+        } else {
+            &["xdg-open"]
+        };
+        let mut args: Vec<String> = opener.iter().map(|arg| arg.to_string()).collect();
+        args.push(url.to_owned());
+        commands.push(args);
+    }
+    for args in &commands {
+        // Keep the browser from writing over the interface.
+        let spawned = Command::new(&args[0])
+            .args(&args[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        if let Ok(mut child) = spawned {
+            thread::spawn(move || child.wait());
+            return Ok(());
+        }
+    }
+    bail!("No browser could be started, set $BROWSER to choose one")
+}
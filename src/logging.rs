@@ -0,0 +1,115 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{anyhow, Context, Result};
+use std::{
+    env,
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tracing_subscriber::{filter::Targets, fmt::MakeWriter, prelude::*};
+
+/// What the logs keep of the events when `RUST_LOG` is not set.
+const DEFAULT_FILTER: &str = "autopilot=info";
+
+/// Text written in place of a secret.
+const REDACTED: &str = "********";
+
+/// Passwords and answers seen so far, longest first, kept out of the logs.
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Writes the logs to `path`, keeping the events selected by the directives
+/// of `RUST_LOG`, such as `debug` or `autopilot::session=trace`.
+pub fn init(path: &Path) -> Result<()> {
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create log file '{}'", path.display()))?;
+    let filter = match env::var("RUST_LOG") {
+        Ok(directives) if !directives.trim().is_empty() => directives,
+        _ => String::from(DEFAULT_FILTER),
+    };
+    let filter: Targets = filter
+        .parse()
+        // The error already repeats its cause.
+        .map_err(|e| anyhow!("Invalid RUST_LOG '{}': {}", filter, e))?;
+    let writer = LogWriter {
+        file: Arc::new(Mutex::new(file)),
+    };
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(writer)
+                .with_filter(filter),
+        )
+        .try_init()
+        .context("Failed to set up logging")
+}
+
+/// Redacts `secret` from every log line written from now on.
+pub fn add_secret(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    let mut secrets = SECRETS.lock().unwrap();
+    if !secrets.iter().any(|known| known == secret) {
+        secrets.push(secret.to_owned());
+        // A secret containing another is replaced whole.
+        secrets.sort_by_key(|known| std::cmp::Reverse(known.len()));
+    }
+}
+
+/// `text` with every known secret replaced.
+fn redact(text: &str) -> String {
+    SECRETS
+        .lock()
+        .unwrap()
+        .iter()
+        .fold(text.to_owned(), |text, secret| {
+            text.replace(secret, REDACTED)
+        })
+}
+
+/// Hands the log file to the formatter, one line per event.
+#[derive(Clone)]
+struct LogWriter {
+    file: Arc<Mutex<File>>,
+}
+
+impl<'a> MakeWriter<'a> for LogWriter {
+    type Writer = LogLine;
+
+    fn make_writer(&'a self) -> LogLine {
+        LogLine {
+            file: self.file.clone(),
+            text: Vec::new(),
+        }
+    }
+}
+
+/// An event being formatted, redacted and written whole once done, so that
+/// no secret is split across writes.
+struct LogLine {
+    file: Arc<Mutex<File>>,
+    text: Vec<u8>,
+}
+
+impl Write for LogLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.text.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogLine {
+    fn drop(&mut self) {
+        let line = redact(&String::from_utf8_lossy(&self.text));
+        // Logging must never fail the presentation.
+        let _ = self.file.lock().unwrap().write_all(line.as_bytes());
+    }
+}
@@ -2,57 +2,273 @@
 //
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-mod app;
-mod config;
-mod event;
-mod tui;
-mod ui;
-mod session;
-
-use clap::Parser;
-use ratatui::{backend::CrosstermBackend, Terminal};
-use std::{io, path::PathBuf};
-
-use crate::{
-    app::{App, AppResult},
+use autopilot::{
+    cast::Cast,
+    color::{self, ColorDepth},
+    config,
     event::{Event, EventHandler},
+    logging, resume,
+    transcript::{Transcript, TranscriptFormat},
     tui::Tui,
+    App, AppResult, Mode,
+};
+use clap::Parser;
+use ratatui::{backend::CrosstermBackend, Terminal};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
 #[derive(Parser)]
 struct Cli {
-    config_path: PathBuf,
+    #[arg(required_unless_present = "dump_schema")]
+    config_path: Option<PathBuf>,
+    /// Merge this YAML file onto the configuration (can be repeated).
+    #[arg(long, value_name = "PATH")]
+    overlay: Vec<PathBuf>,
+    /// Write a JSON summary of the run to this path on exit.
+    #[arg(long, value_name = "PATH")]
+    summary_json: Option<PathBuf>,
+    /// Start the presentation without the overview screen.
+    #[arg(long)]
+    no_splash: bool,
+    /// Validate the configuration against this JSON schema instead of the
+    /// built-in one.
+    #[arg(long, value_name = "PATH")]
+    schema: Option<PathBuf>,
+    /// Print the built-in JSON schema of the configuration and exit.
+    #[arg(long)]
+    dump_schema: bool,
+    /// Append the output shown during the presentation to this file.
+    #[arg(long, value_name = "PATH")]
+    transcript: Option<PathBuf>,
+    /// Format of the transcript.
+    #[arg(long, value_enum, default_value_t, value_name = "FORMAT")]
+    transcript_format: TranscriptFormat,
+    /// Record the presentation output to this file as an asciinema cast.
+    #[arg(long, value_name = "PATH")]
+    cast: Option<PathBuf>,
+    /// Validate the configuration, report any problem and exit.
+    #[arg(long)]
+    check: bool,
+    /// Run every action to completion without the user interface, printing
+    /// the output to stdout. Exits with a nonzero status if a command fails.
+    #[arg(long)]
+    headless: bool,
+    /// Scale the typing delay of every message by this factor; below 1 types
+    /// faster, above 1 slower.
+    #[arg(long, default_value_t = 1.0, value_name = "FACTOR", value_parser = parse_multiplier)]
+    speed_multiplier: f64,
+    /// Show the commands, transfers and stage hooks without running them.
+    /// Messages and waits still play.
+    #[arg(long)]
+    dry_run: bool,
+    /// Write a log of the connections and commands to this file. `RUST_LOG`
+    /// selects the events logged, such as `debug` (default
+    /// `autopilot=info`).
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+    /// Milliseconds between two redraws of the interface, from 10 (default
+    /// 50). Lower values animate more smoothly at the cost of CPU.
+    #[arg(long, value_name = "MS", value_parser = parse_tick_ms)]
+    tick_ms: Option<u64>,
+    /// Play the presentation by itself, running the next action this many
+    /// milliseconds after the last one is done. Pauses still wait for a key.
+    #[arg(long, value_name = "MS", conflicts_with = "headless")]
+    autoplay: Option<u64>,
+    /// Start over once the presentation is finished, when autoplaying.
+    #[arg(long = "loop", requires = "autoplay")]
+    autoplay_loop: bool,
+    /// Seconds without a key press after which autoplay, paused by any key,
+    /// takes over again.
+    #[arg(long, default_value_t = 30, value_name = "SECS", requires = "autoplay")]
+    autoplay_idle: u64,
+    /// Check that every remote host can be logged into as soon as a stage
+    /// using it starts, as `preflight: true` does for a single host.
+    #[arg(long)]
+    preflight: bool,
+    /// Disable all colors and text styles, as with `NO_COLOR`.
+    #[arg(long)]
+    no_color: bool,
+    /// Colors the terminal shows: 16, 256 or truecolor (default detected
+    /// from `COLORTERM` and `TERM`). Colors beyond them are shown as the
+    /// nearest ones.
+    #[arg(long, value_name = "DEPTH", value_parser = parse_color_depth)]
+    color_depth: Option<ColorDepth>,
+    /// Start at the action the presentation was left at when last quit with
+    /// this flag, and save the position on quit, next to the configuration.
+    #[arg(long, conflicts_with = "headless")]
+    resume: bool,
+    /// Forget the position saved by `--resume`, starting from the first
+    /// stage.
+    #[arg(long)]
+    reset_position: bool,
+}
+
+fn parse_multiplier(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(factor) if factor.is_finite() && factor >= 0.0 => Ok(factor),
+        _ => Err(String::from("expected a non-negative number")),
+    }
+}
+
+fn parse_tick_ms(value: &str) -> Result<u64, String> {
+    match value.parse::<u64>() {
+        Ok(tick_ms) if tick_ms >= config::MIN_TICK_MS => Ok(tick_ms),
+        _ => Err(format!("expected a number of milliseconds, at least {}", config::MIN_TICK_MS)),
+    }
+}
+
+fn parse_color_depth(value: &str) -> Result<ColorDepth, String> {
+    match value.to_lowercase().as_str() {
+        "16" => Ok(ColorDepth::Ansi),
+        "256" => Ok(ColorDepth::Indexed),
+        "truecolor" | "24bit" => Ok(ColorDepth::TrueColor),
+        _ => Err(String::from("expected 16, 256 or truecolor")),
+    }
 }
 
 #[tokio::main]
 async fn main() -> AppResult<()> {
     let args = Cli::parse();
-    let config =
-        config::Config::load_config(&args.config_path).expect("Parsing configuration failed");
+    if args.no_color {
+        color::disable();
+    }
+    if let Some(depth) = args.color_depth {
+        color::set_color_depth(depth);
+    }
+    if args.dump_schema {
+        print!("{}", config::SCHEMA);
+        return Ok(());
+    }
+    if let Some(path) = &args.log_file {
+        if let Err(e) = logging::init(path) {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    }
+    let config_path = args.config_path.clone().unwrap();
+    let config = match config::Config::load_config(&config_path, &args.overlay, args.schema.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}: {}", config_path.display(), e);
+            for cause in e.chain().skip(1) {
+                eprintln!("  caused by: {}", cause);
+            }
+            std::process::exit(1);
+        }
+    };
+    let saved = if args.reset_position {
+        resume::reset(&config_path).map(|()| None)
+    } else if args.resume {
+        resume::load(&config_path)
+    } else {
+        Ok(None)
+    };
+    let saved = match saved {
+        Ok(saved) => saved,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            if !args.reset_position {
+                eprintln!("Run with --reset-position to start from the first stage.");
+            }
+            std::process::exit(1);
+        }
+    };
+    if args.check {
+        println!("{}: configuration is valid", config_path.display());
+        return Ok(());
+    }
     // Create an application.
     let mut app = App::new(config);
+    app.speed_multiplier = args.speed_multiplier;
+    app.set_dry_run(args.dry_run);
+    app.preflight = args.preflight;
+    app.autoplay = args.autoplay.map(Duration::from_millis);
+    app.autoplay_loop = args.autoplay_loop;
+    app.autoplay_idle = Duration::from_secs(args.autoplay_idle);
+    if let Some(tick_ms) = args.tick_ms {
+        app.tick_rate = Duration::from_millis(tick_ms);
+    }
+    app.title = config_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    if let Some(path) = &args.transcript {
+        app.transcript = Some(Transcript::open(path, args.transcript_format)?);
+    }
+    if let Some(position) = saved {
+        // Overlays may have removed the action since.
+        if app.resume_at((position.stage, position.action)) {
+            app.scroll = position.scroll;
+        }
+    }
+    if args.headless {
+        if let Some(path) = &args.cast {
+            let (width, height) = crossterm::terminal::size().unwrap_or((80, 24));
+            app.cast = Some(Cast::create(path, width, height)?);
+        }
+        let success = app.run_headless();
+        if let Some(path) = &args.summary_json {
+            app.summary().write(path)?;
+        }
+        let success = success?;
+        std::process::exit(if success { 0 } else { 1 });
+    }
+    let result = run_interface(&mut app, &args, &config_path).await;
+    // The summary of a run that failed matters most of all.
+    if let Some(path) = &args.summary_json {
+        let written = app.summary().write(path);
+        result?;
+        written?;
+        return Ok(());
+    }
+    result
+}
+
+/// Runs the presentation in the terminal until it is quit, saving its
+/// position on the way out with `--resume`.
+async fn run_interface(app: &mut App, args: &Cli, config_path: &Path) -> AppResult<()> {
+    if !args.no_splash {
+        app.mode = Mode::Splash;
+    }
 
     // Initialize the terminal user interface.
     let backend = CrosstermBackend::new(io::stdout());
     let terminal = Terminal::new(backend)?;
-    let events = EventHandler::new(50);
+    app.resize(terminal.size()?.width);
+    if let Some(path) = &args.cast {
+        let size = terminal.size()?;
+        app.cast = Some(Cast::create(path, size.width, size.height)?);
+    }
+    let events = EventHandler::new(app.tick_rate.as_millis() as u64);
     let mut tui = Tui::new(terminal, events);
     tui.init()?;
 
     // Start the main loop.
     while app.running {
         // Render the user interface.
-        tui.draw(&mut app)?;
+        tui.draw(app)?;
         // Handle events.
         match tui.events.next().await? {
-            Event::Tick => app.tick(),
+            Event::Tick => app.tick()?,
             Event::Key(key_event) => app.handle_events(key_event)?,
-            Event::Mouse(_) => {}
-            Event::Resize(_, _) => {}
+            Event::Mouse(mouse_event) => app.handle_mouse(mouse_event),
+            Event::Resize(width, _) => app.resize(width),
         }
     }
 
     // Exit the user interface.
     tui.exit()?;
+
+    if args.resume {
+        match app.position() {
+            Some((stage, action)) => resume::save(config_path, stage, action, app.scroll)?,
+            // A finished presentation starts over.
+            None => resume::reset(config_path)?,
+        }
+    }
     Ok(())
 }
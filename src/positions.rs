@@ -0,0 +1,216 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+use yaml_rust2::{
+    parser::{MarkedEventReceiver, Parser},
+    scanner::Marker,
+    Event,
+};
+
+/// Where a node of a configuration was written.
+#[derive(Clone, Debug)]
+pub struct Position {
+    file: Rc<PathBuf>,
+    line: usize,
+    /// Column, from 1.
+    col: usize,
+}
+
+impl Position {
+    /// The position as shown in errors, naming its file unless it is `main`.
+    pub fn describe(&self, main: &Path) -> String {
+        if self.file.as_path() == main {
+            format!("line {}, column {}", self.line, self.col)
+        } else {
+            format!(
+                "'{}', line {}, column {}",
+                self.file.display(),
+                self.line,
+                self.col
+            )
+        }
+    }
+}
+
+/// The positions of the nodes of a configuration, keyed by the JSON pointer
+/// of the node in the value the configuration converts to.
+#[derive(Debug, Default)]
+pub struct Positions {
+    nodes: HashMap<String, Position>,
+}
+
+/// A collection being parsed, with the child expected next.
+enum Frame {
+    /// A mapping, and the key of its next value once read.
+    Mapping(Option<String>),
+    /// A sequence, and the index of its next item.
+    Sequence(usize),
+}
+
+/// Records the positions of the nodes of the first document of a stream.
+struct Recorder {
+    file: Rc<PathBuf>,
+    frames: Vec<Frame>,
+    done: bool,
+    positions: Positions,
+}
+
+impl Recorder {
+    /// The pointer of the node about to be read.
+    fn pointer(&self) -> String {
+        self.frames
+            .iter()
+            .map(|frame| match frame {
+                Frame::Mapping(key) => {
+                    let key = key.as_deref().unwrap_or_default();
+                    format!("/{}", key.replace('~', "~0").replace('/', "~1"))
+                }
+                Frame::Sequence(index) => format!("/{}", index),
+            })
+            .collect()
+    }
+
+    /// Records a node starting at `mark`, unless it is the key of a mapping.
+    /// Returns whether it was a key.
+    fn start_node(&mut self, mark: Marker, key: Option<&str>) -> bool {
+        if let Some(Frame::Mapping(pending @ None)) = self.frames.last_mut() {
+            *pending = Some(key.unwrap_or_default().to_owned());
+            return true;
+        }
+        let position = Position {
+            file: self.file.clone(),
+            line: mark.line(),
+            col: mark.col() + 1,
+        };
+        self.positions.nodes.insert(self.pointer(), position);
+        false
+    }
+
+    /// Records a node without children starting at `mark`, and moves past
+    /// it unless it is the key of a mapping.
+    fn leaf_node(&mut self, mark: Marker, key: Option<&str>) {
+        if !self.start_node(mark, key) {
+            self.end_node();
+        }
+    }
+
+    /// Moves past a node of the collection being parsed.
+    fn end_node(&mut self) {
+        match self.frames.last_mut() {
+            Some(Frame::Mapping(key)) => *key = None,
+            Some(Frame::Sequence(index)) => *index += 1,
+            None => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for Recorder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        if self.done {
+            return;
+        }
+        match event {
+            Event::Scalar(value, ..) => self.leaf_node(mark, Some(&value)),
+            Event::Alias(_) => self.leaf_node(mark, None),
+            // Collections as keys are not supported by the configuration.
+            Event::MappingStart(..) => {
+                self.start_node(mark, None);
+                self.frames.push(Frame::Mapping(None));
+            }
+            Event::SequenceStart(..) => {
+                self.start_node(mark, None);
+                self.frames.push(Frame::Sequence(0));
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.frames.pop();
+                self.end_node();
+            }
+            Event::DocumentEnd => self.done = true,
+            _ => {}
+        }
+    }
+}
+
+impl Positions {
+    /// The positions of the nodes of `yaml`, read from `file`. A document
+    /// that does not parse has none.
+    pub fn parse(yaml: &str, file: &Path) -> Self {
+        let mut recorder = Recorder {
+            file: Rc::new(file.to_owned()),
+            frames: Vec::new(),
+            done: false,
+            positions: Positions::default(),
+        };
+        let _ = Parser::new_from_str(yaml).load(&mut recorder, false);
+        recorder.positions
+    }
+
+    /// The position of the node at `pointer`, if known.
+    pub fn get(&self, pointer: &str) -> Option<&Position> {
+        self.nodes.get(pointer)
+    }
+
+    /// The positions of the node at `pointer` and of its descendants.
+    fn under<'a>(&'a self, pointer: &'a str) -> impl Iterator<Item = (&'a str, &'a Position)> {
+        self.nodes.iter().filter_map(move |(node, position)| {
+            let rest = node.strip_prefix(pointer)?;
+            (rest.is_empty() || rest.starts_with('/')).then_some((rest, position))
+        })
+    }
+
+    /// Removes the positions of the descendants of the node at `pointer`,
+    /// returning them.
+    pub fn split_off(&mut self, pointer: &str) -> Positions {
+        let descendants: Vec<String> = self
+            .under(pointer)
+            .filter(|(rest, _)| !rest.is_empty())
+            .map(|(rest, _)| format!("{}{}", pointer, rest))
+            .collect();
+        let nodes = descendants
+            .into_iter()
+            .filter_map(|node| self.nodes.remove_entry(&node))
+            .collect();
+        Positions { nodes }
+    }
+
+    /// Takes the positions of the node at `from` in `other`, and of its
+    /// descendants, as those of the node at `to`.
+    pub fn move_from(&mut self, other: &Positions, from: &str, to: &str) {
+        for (rest, position) in other.under(from) {
+            self.nodes
+                .insert(format!("{}{}", to, rest), position.clone());
+        }
+    }
+
+    /// Updates the positions of `base` for `overlay` to be merged onto it at
+    /// `pointer`: mappings are merged key by key, other nodes replaced.
+    pub fn merge(&mut self, base: &Value, overlay: &Value, positions: &Positions, pointer: &str) {
+        match (base, overlay) {
+            (Value::Object(base), Value::Object(overlay)) => {
+                if let Some(position) = positions.get(pointer) {
+                    self.nodes.insert(pointer.to_owned(), position.clone());
+                }
+                for (key, value) in overlay {
+                    let child =
+                        format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+                    match base.get(key) {
+                        Some(base_value) => self.merge(base_value, value, positions, &child),
+                        None => self.move_from(positions, &child, &child),
+                    }
+                }
+            }
+            _ => {
+                self.nodes.remove(pointer);
+                self.split_off(pointer);
+                self.move_from(positions, pointer, pointer);
+            }
+        }
+    }
+}
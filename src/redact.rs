@@ -0,0 +1,51 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+/// Text shown in place of a match of a redaction rule.
+pub const MASK: &str = "****";
+
+/// Compiles the redaction rules of the configuration.
+pub fn compile(patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).with_context(|| format!("Invalid redaction pattern '{}'", pattern))
+        })
+        .collect()
+}
+
+/// `text` with every match of `rules`, in turn, replaced by [`MASK`].
+pub fn apply(rules: &[Regex], text: &str) -> String {
+    rules.iter().fold(text.to_owned(), |text, rule| {
+        rule.replace_all(&text, MASK).into_owned()
+    })
+}
+
+/// Output streamed in chunks, held back up to the end of its last line so
+/// that the rules also mask the matches split across chunks.
+#[derive(Default)]
+pub struct Lines {
+    pending: String,
+}
+
+impl Lines {
+    /// Adds `chunk`, returning the lines it completes masked by `rules`.
+    pub fn push(&mut self, rules: &[Regex], chunk: &str) -> String {
+        self.pending.push_str(chunk);
+        // A carriage return ends the line a progress bar redraws.
+        let Some(end) = self.pending.rfind(['\n', '\r']) else {
+            return String::new();
+        };
+        let rest = self.pending.split_off(end + 1);
+        apply(rules, &std::mem::replace(&mut self.pending, rest))
+    }
+
+    /// Takes the rest of the output, masked by `rules`.
+    pub fn finish(&mut self, rules: &[Regex]) -> String {
+        apply(rules, &std::mem::take(&mut self.pending))
+    }
+}
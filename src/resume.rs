@@ -0,0 +1,106 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Where a presentation was left, saved on quit to be resumed.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SavedPosition {
+    /// Path of the configuration the position belongs to.
+    config: PathBuf,
+    /// Hash of the contents of the configuration, to tell whether it changed.
+    hash: String,
+    /// Stage of the next action to run.
+    pub stage: usize,
+    /// Next action to run, within its stage.
+    pub action: usize,
+    /// Lines the output was scrolled up by.
+    pub scroll: u16,
+}
+
+/// The file the position of the presentation of `config_path` is saved to:
+/// `.NAME.autopilot-state`, next to the configuration.
+pub fn state_path(config_path: &Path) -> PathBuf {
+    let name = config_path.file_name().unwrap_or_default().to_string_lossy();
+    config_path.with_file_name(format!(".{}.autopilot-state", name))
+}
+
+/// The FNV-1a hash of `bytes`, as hexadecimal. It stays the same across
+/// releases, unlike the hashers of the standard library.
+fn hash(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf29ce484222325_u64, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// The canonical path of the configuration and the hash of its contents.
+fn identify(config_path: &Path) -> Result<(PathBuf, String)> {
+    let contents = fs::read(config_path)
+        .with_context(|| format!("Failed to read '{}'", config_path.display()))?;
+    let path = fs::canonicalize(config_path)
+        .with_context(|| format!("Failed to resolve '{}'", config_path.display()))?;
+    Ok((path, hash(&contents)))
+}
+
+/// Saves the position of the presentation of `config_path`.
+pub fn save(config_path: &Path, stage: usize, action: usize, scroll: u16) -> Result<()> {
+    let (config, hash) = identify(config_path)?;
+    let position = SavedPosition {
+        config,
+        hash,
+        stage,
+        action,
+        scroll,
+    };
+    let path = state_path(config_path);
+    let json = serde_json::to_string_pretty(&position).context("Failed to serialize position")?;
+    fs::write(&path, json)
+        .with_context(|| format!("Failed to save position to '{}'", path.display()))
+}
+
+/// The position saved for the presentation of `config_path`, if any.
+///
+/// A position saved for another configuration, or for this one before it
+/// changed, is an error, as is a file that cannot be read.
+pub fn load(config_path: &Path) -> Result<Option<SavedPosition>> {
+    let path = state_path(config_path);
+    let json = match fs::read_to_string(&path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read '{}'", path.display()));
+        }
+    };
+    let position: SavedPosition = serde_json::from_str(&json)
+        .with_context(|| format!("Invalid saved position in '{}'", path.display()))?;
+    let (config, hash) = identify(config_path)?;
+    if position.config != config {
+        bail!(
+            "The position saved in '{}' is that of '{}'",
+            path.display(),
+            position.config.display()
+        );
+    }
+    if position.hash != hash {
+        bail!("'{}' changed since its position was saved", config_path.display());
+    }
+    Ok(Some(position))
+}
+
+/// Removes the position saved for the presentation of `config_path`, if any.
+pub fn reset(config_path: &Path) -> Result<()> {
+    let path = state_path(config_path);
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Failed to remove '{}'", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
@@ -2,16 +2,199 @@
 //
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::config::{CommandType, RemoteConfig, SudoConfig};
-use anyhow::{ensure, Context, Result};
-use ssh2::Session;
+use crate::config::{
+    expand_file_secrets, expand_iteration, read_file_secrets, shell_quote, AuthMethod,
+    CommandType, RemoteConfig, SudoConfig, TransferConfig, ITER_VAR,
+};
+use crate::logging;
+use anyhow::{bail, ensure, Context, Result};
+use ssh2::{
+    Channel, CheckResult, ErrorCode, HostKeyType, KeyboardInteractivePrompt, KnownHostFileKind,
+    Prompt, Session,
+};
 use std::borrow::Cow;
 use std::{
+    collections::{HashMap, HashSet},
     env,
-    io::Read,
-    net::TcpStream,
-    process::Command,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
 };
+use tracing::{debug, info, info_span, warn};
+
+/// Output kept per stream when no `max_output_bytes` is configured.
+const DEFAULT_MAX_OUTPUT_BYTES: usize = 1 << 20;
+
+/// Notice appended to an output stream cut at the size limit.
+const TRUNCATED_NOTICE: &str = "(output truncated)\n";
+
+/// Bytes read from an output stream at once.
+const CHUNK_SIZE: usize = 8192;
+
+/// How often a running command is checked for a stop request.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long connecting to a host, and every reply of the server, may take
+/// when neither the remote nor the SSH config sets it.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Error code of libssh2 for a blocking call that timed out.
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+
+/// Prompt `sudo` is told to show on a PTY, so that it is recognized and
+/// answered with the password.
+const SUDO_PTY_PROMPT: &str = "[sudo] password: ";
+
+/// Output stream of a command.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Output kept from a stream, up to the size limit.
+#[derive(Default)]
+struct StreamedOutput {
+    bytes: Vec<u8>,
+    /// Kept bytes not yet decoded, ending in an incomplete character.
+    pending: Vec<u8>,
+}
+
+impl StreamedOutput {
+    /// Keeps `chunk` up to `limit` bytes in total, and returns the text it
+    /// completes along with whether the limit still holds. Once exceeded, a
+    /// notice is appended.
+    fn push(&mut self, chunk: &[u8], limit: usize) -> (String, bool) {
+        let kept = chunk.len().min(limit.saturating_sub(self.bytes.len()));
+        self.bytes.extend_from_slice(&chunk[..kept]);
+        self.pending.extend_from_slice(&chunk[..kept]);
+        if kept < chunk.len() {
+            let mut text = self.flush();
+            if !self.bytes.ends_with(b"\n") {
+                self.bytes.push(b'\n');
+                text.push('\n');
+            }
+            self.bytes.extend_from_slice(TRUNCATED_NOTICE.as_bytes());
+            text.push_str(TRUNCATED_NOTICE);
+            return (text, false);
+        }
+
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            // Invalid bytes are replaced, only an incomplete character at
+            // the end waits for the next chunk.
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        let text = String::from_utf8_lossy(&self.pending[..valid]).into_owned();
+        self.pending.drain(..valid);
+        (text, true)
+    }
+
+    /// Returns the text of the bytes still pending.
+    fn flush(&mut self) -> String {
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+        text
+    }
+}
+
+/// Output of a command run on a PTY, cleaned of the sudo prompts and of the
+/// carriage returns the terminal adds to line ends.
+#[derive(Default)]
+struct PtyOutput {
+    /// Bytes held back as they may start a prompt or a line end.
+    held: Vec<u8>,
+    /// Whether a prompt was just removed, along with the line end sudo
+    /// writes once the password is entered.
+    after_prompt: bool,
+}
+
+impl PtyOutput {
+    /// Adds `chunk`, returning the bytes ready to be shown and how many
+    /// prompts it completed.
+    fn push(&mut self, chunk: &[u8]) -> (Vec<u8>, usize) {
+        self.held.extend_from_slice(chunk);
+        let prompt = SUDO_PTY_PROMPT.as_bytes();
+        let mut shown = Vec::with_capacity(self.held.len());
+        let mut prompts = 0;
+        let mut idx = 0;
+        while idx < self.held.len() {
+            let rest = &self.held[idx..];
+            if rest.starts_with(prompt) {
+                prompts += 1;
+                idx += prompt.len();
+                self.after_prompt = true;
+                continue;
+            }
+            // The next chunk may complete it.
+            if rest == b"\r" || prompt.starts_with(rest) {
+                break;
+            }
+            if rest.starts_with(b"\r\n") {
+                idx += 1;
+                continue;
+            }
+            if !(self.after_prompt && rest[0] == b'\n') {
+                shown.push(rest[0]);
+            }
+            self.after_prompt = false;
+            idx += 1;
+        }
+        self.held.drain(..idx);
+        (shown, prompts)
+    }
+
+    fn flush(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.held)
+    }
+}
+
+/// Receives the output of a running command.
+struct OutputSink<'a> {
+    stdout: StreamedOutput,
+    stderr: StreamedOutput,
+    limit: usize,
+    on_output: &'a mut dyn FnMut(OutputStream, &str),
+}
+
+impl OutputSink<'_> {
+    /// Keeps and forwards `chunk`. Returns `false` once the stream exceeds
+    /// the size limit, and the command should be stopped.
+    fn push(&mut self, stream: OutputStream, chunk: &[u8]) -> bool {
+        let output = match stream {
+            OutputStream::Stdout => &mut self.stdout,
+            OutputStream::Stderr => &mut self.stderr,
+        };
+        let (text, within_limit) = output.push(chunk, self.limit);
+        if !text.is_empty() {
+            (self.on_output)(stream, &text);
+        }
+        within_limit
+    }
+
+    /// Forwards the text of the bytes still pending in both streams.
+    fn flush(&mut self) {
+        for stream in [OutputStream::Stdout, OutputStream::Stderr] {
+            let text = match stream {
+                OutputStream::Stdout => self.stdout.flush(),
+                OutputStream::Stderr => self.stderr.flush(),
+            };
+            if !text.is_empty() {
+                (self.on_output)(stream, &text);
+            }
+        }
+    }
+}
 
 enum SessionConfiguration {
     Local(),
@@ -22,7 +205,7 @@ impl SessionConfiguration {
     fn get_effective_user(&self) -> String {
         match self {
             SessionConfiguration::Local() => whoami::username(),
-            SessionConfiguration::Remote(_, remote_config) => remote_config.user.clone(),
+            SessionConfiguration::Remote(_, remote_config) => remote_config.user.clone().unwrap(),
         }
     }
 
@@ -36,38 +219,431 @@ impl SessionConfiguration {
     }
 }
 
+/// Size of the chunks a file is copied in, between progress reports.
+const TRANSFER_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Whether a file is copied to or from the remote host.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+impl Direction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Direction::Upload => "upload",
+            Direction::Download => "download",
+        }
+    }
+}
+
+/// A file copied to or from a remote host over SFTP, on a session of the
+/// cache.
+pub struct FileTransfer {
+    direction: Direction,
+    local: String,
+    remote_path: String,
+    /// Whether a download replaces an existing local file.
+    overwrite: bool,
+    remote_config: RemoteConfig,
+    session: Session,
+}
+
+impl FileTransfer {
+    pub(crate) fn new(
+        direction: Direction,
+        transfer_config: TransferConfig,
+        sessions: &SessionCache,
+    ) -> Result<Self> {
+        // The remote host is checked by validation.
+        let remote = transfer_config.remote.unwrap();
+        let remote_config = CommandSession::resolve_remote_config(remote)?;
+        Ok(
+            Self {
+                direction,
+                local: transfer_config.local,
+                remote_path: transfer_config.remote_path,
+                overwrite: transfer_config.overwrite.unwrap_or(false),
+                session: sessions.get(&remote_config)?,
+                remote_config,
+            }
+        )
+    }
+
+    /// The operation, source and destination, as shown and recorded.
+    pub(crate) fn describe(&self) -> String {
+        match self.direction {
+            Direction::Upload => format!("upload {} {}", self.local, self.remote_path),
+            Direction::Download => format!("download {} {}", self.remote_path, self.local),
+        }
+    }
+
+    /// The line shown for the transfer, like the prompt of a command.
+    pub(crate) fn get_prompt(&self) -> String {
+        format!(
+            "[{}@{}:{}]$ {}\n",
+            self.remote_config.user.as_deref().unwrap(),
+            self.remote_config.host,
+            self.remote_config.port.unwrap(),
+            self.describe(),
+        )
+    }
+
+    /// Copies the file, calling `progress` with the bytes copied so far and
+    /// the size of the file, if known.
+    ///
+    /// Returns the bytes copied, which fall short of the size when stopped, in
+    /// which case the destination is left incomplete.
+    pub(crate) fn run(
+        &self,
+        progress: impl FnMut(u64, u64),
+        should_stop: impl Fn() -> bool,
+    ) -> Result<u64> {
+        info!(host = %self.remote_config.host, transfer = %self.describe(), "Transferring");
+        let sftp = self.session.sftp().context("Failed to start SFTP")?;
+        match self.direction {
+            Direction::Upload => {
+                let mut file = fs::File::open(&self.local)
+                    .with_context(|| format!("Failed to open '{}'", self.local))?;
+                let size = file.metadata()?.len();
+                let mut remote_file = sftp
+                    .create(Path::new(&self.remote_path))
+                    .with_context(|| format!("Failed to create '{}'", self.remote_path))?;
+                Self::copy(&mut file, &mut remote_file, size, progress, should_stop)
+            }
+            Direction::Download => {
+                let mut remote_file = sftp
+                    .open(Path::new(&self.remote_path))
+                    .with_context(|| format!("Failed to open '{}'", self.remote_path))?;
+                let size = remote_file.stat().ok().and_then(|stat| stat.size).unwrap_or(0);
+                let mut options = OpenOptions::new();
+                if self.overwrite {
+                    options.write(true).create(true).truncate(true);
+                } else {
+                    options.write(true).create_new(true);
+                }
+                let mut file = match options.open(&self.local) {
+                    Ok(file) => file,
+                    Err(e) if e.kind() == ErrorKind::AlreadyExists => bail!(
+                        "'{}' already exists, set 'overwrite: true' to replace it",
+                        self.local
+                    ),
+                    Err(e) => {
+                        return Err(e).with_context(|| format!("Failed to create '{}'", self.local))
+                    }
+                };
+                Self::copy(&mut remote_file, &mut file, size, progress, should_stop)
+            }
+        }
+    }
+
+    fn copy(
+        reader: &mut impl Read,
+        writer: &mut impl Write,
+        size: u64,
+        mut progress: impl FnMut(u64, u64),
+        should_stop: impl Fn() -> bool,
+    ) -> Result<u64> {
+        let mut buf = vec![0; TRANSFER_CHUNK_BYTES];
+        let mut copied = 0;
+        while !should_stop() {
+            let read = reader.read(&mut buf).context("Failed to read the file")?;
+            if read == 0 {
+                break;
+            }
+            writer.write_all(&buf[..read]).context("Failed to write the file")?;
+            copied += read as u64;
+            progress(copied, size);
+        }
+        Ok(copied)
+    }
+}
+
+/// Identifies an SSH session by host, port and user.
+type SessionKey = (String, u16, String);
+
+/// Authenticated SSH sessions shared by the commands run against the same
+/// `(host, port, user)`, so that they don't reconnect every time.
+///
+/// Cloning the cache yields a handle to the same sessions.
+#[derive(Clone, Default)]
+pub struct SessionCache {
+    sessions: Arc<Mutex<HashMap<SessionKey, Session>>>,
+    /// Why connections opened ahead failed, reported by the action needing
+    /// them instead of connecting again.
+    failures: Arc<Mutex<HashMap<SessionKey, anyhow::Error>>>,
+    /// Where the questions of keyboard-interactive authentications are sent
+    /// to be answered.
+    questions: Arc<Mutex<Option<Sender<AuthQuestion>>>>,
+    /// Whether sessions are left unconnected, as nothing runs over them.
+    dry_run: Arc<AtomicBool>,
+    /// The cached sessions a command is running on.
+    busy: Arc<Mutex<HashSet<SessionKey>>>,
+}
+
+/// The claim of a running command on a cached session, given up when
+/// dropped.
+struct SessionClaim {
+    busy: Arc<Mutex<HashSet<SessionKey>>>,
+    key: SessionKey,
+}
+
+impl Drop for SessionClaim {
+    fn drop(&mut self) {
+        self.busy.lock().unwrap().remove(&self.key);
+    }
+}
+
+impl SessionCache {
+    fn key(remote_config: &RemoteConfig) -> SessionKey {
+        (
+            remote_config.host.clone(),
+            remote_config.port.unwrap(),
+            remote_config.user.clone().unwrap(),
+        )
+    }
+
+    /// Returns the cached session for `remote_config`, connecting if needed.
+    fn get(&self, remote_config: &RemoteConfig) -> Result<Session> {
+        if self.dry_run.load(Ordering::Relaxed) {
+            return Ok(Session::new()?);
+        }
+        let key = Self::key(remote_config);
+        if let Some(session) = self.sessions.lock().unwrap().get(&key) {
+            debug!(host = %remote_config.host, "Reusing cached session");
+            return Ok(session.clone());
+        }
+        if let Some(e) = self.failures.lock().unwrap().remove(&key) {
+            return Err(e);
+        }
+        // The cache is not held while connecting, which can take as long as
+        // the presenter takes to answer the questions of the server.
+        let questions = self.questions.lock().unwrap().clone();
+        let session = CommandSession::connect(remote_config, questions.as_ref())?;
+        let mut sessions = self.sessions.lock().unwrap();
+        Ok(sessions.entry(key).or_insert(session).clone())
+    }
+
+    /// Claims the cached session for `remote_config` for a command to run
+    /// on, unless another running command holds it.
+    fn claim(&self, remote_config: &RemoteConfig) -> Option<SessionClaim> {
+        let key = Self::key(remote_config);
+        self.busy.lock().unwrap().insert(key.clone()).then(|| SessionClaim {
+            busy: self.busy.clone(),
+            key,
+        })
+    }
+
+    /// Opens a session of its own for `remote_config`, left out of the cache.
+    fn connect_uncached(&self, remote_config: &RemoteConfig) -> Result<Session> {
+        debug!(host = %remote_config.host, "Cached session busy, connecting again");
+        let questions = self.questions.lock().unwrap().clone();
+        CommandSession::connect(remote_config, questions.as_ref())
+    }
+
+    /// Sends the questions of keyboard-interactive authentications to
+    /// `questions`. Without it, those authentications fail.
+    pub fn set_questions(&self, questions: Option<Sender<AuthQuestion>>) {
+        *self.questions.lock().unwrap() = questions;
+    }
+
+    /// Hands out unconnected sessions instead of connecting, for commands
+    /// that are shown but not run.
+    pub fn set_dry_run(&self, dry_run: bool) {
+        self.dry_run.store(dry_run, Ordering::Relaxed);
+    }
+
+    /// Whether `remote_config` has yet to be connected to, nor failed to.
+    pub(crate) fn needs_connecting(&self, remote_config: &RemoteConfig) -> bool {
+        // A configuration that cannot be resolved fails the action anyway.
+        let Ok(remote_config) = CommandSession::resolve_remote_config(remote_config.clone()) else {
+            return false;
+        };
+        let key = Self::key(&remote_config);
+        !self.sessions.lock().unwrap().contains_key(&key)
+            && !self.failures.lock().unwrap().contains_key(&key)
+    }
+
+    /// Connects to `remote_config` ahead of the action needing it, keeping
+    /// the session, or the failure, for it.
+    pub(crate) fn open_ahead(&self, remote_config: RemoteConfig) {
+        let Ok(remote_config) = CommandSession::resolve_remote_config(remote_config) else {
+            return;
+        };
+        if let Err(e) = self.get(&remote_config) {
+            self.failures.lock().unwrap().insert(Self::key(&remote_config), e);
+        }
+    }
+
+    /// Replaces the cached session for `remote_config` by a new connection.
+    fn reconnect(&self, remote_config: &RemoteConfig) -> Result<Session> {
+        warn!(host = %remote_config.host, "Cached session died, reconnecting");
+        self.sessions.lock().unwrap().remove(&Self::key(remote_config));
+        self.get(remote_config)
+    }
+
+    /// Drops every cached session, closing the connections not in use.
+    pub fn clear(&self) {
+        self.sessions.lock().unwrap().clear();
+        self.failures.lock().unwrap().clear();
+    }
+}
+
+/// A question of a server during a keyboard-interactive authentication,
+/// such as a one-time code, waiting for the presenter to answer it.
+#[derive(Clone, Debug)]
+pub struct AuthQuestion {
+    /// The `user@host` authenticating.
+    pub label: String,
+    /// Instructions the server sent along with the question.
+    pub instructions: String,
+    pub text: String,
+    /// Whether the answer can be shown as it is typed.
+    pub echo: bool,
+    answer: Sender<Option<String>>,
+}
+
+impl AuthQuestion {
+    /// Answers the question, or gives up on the authentication with `None`.
+    pub fn answer(self, answer: Option<String>) {
+        // The authentication may have failed in the meantime.
+        let _ = self.answer.send(answer);
+    }
+}
+
+/// Answers the questions of a keyboard-interactive authentication: those
+/// asking for the password with the configured one, if any, and the others
+/// through the presenter.
+struct InteractiveAuth<'a> {
+    label: String,
+    password: &'a str,
+    questions: Option<&'a Sender<AuthQuestion>>,
+    /// Why a question went unanswered, failing the authentication.
+    unanswered: Option<&'static str>,
+}
+
+impl KeyboardInteractivePrompt for InteractiveAuth<'_> {
+    fn prompt<'b>(
+        &mut self,
+        _username: &str,
+        instructions: &str,
+        prompts: &[Prompt<'b>],
+    ) -> Vec<String> {
+        prompts
+            .iter()
+            .map(|prompt| {
+                if self.unanswered.is_some() {
+                    return String::new();
+                }
+                if !prompt.echo
+                    && !self.password.is_empty()
+                    && prompt.text.to_lowercase().contains("password")
+                {
+                    return self.password.to_owned();
+                }
+                let Some(questions) = self.questions else {
+                    self.unanswered = Some("needs an interactive presentation");
+                    return String::new();
+                };
+                let (sender, receiver) = mpsc::channel();
+                let question = AuthQuestion {
+                    label: self.label.clone(),
+                    instructions: instructions.trim().to_owned(),
+                    text: prompt.text.trim().to_owned(),
+                    echo: prompt.echo,
+                    answer: sender,
+                };
+                let answer = questions.send(question).ok().and_then(|_| receiver.recv().ok());
+                match answer.flatten() {
+                    Some(answer) => {
+                        if !prompt.echo {
+                            logging::add_secret(&answer);
+                        }
+                        answer
+                    }
+                    None => {
+                        self.unanswered = Some("was cancelled");
+                        String::new()
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
 pub struct CommandSession {
     command: String,
+    /// Program and arguments when the command must not go through a shell.
+    argv: Option<Vec<String>>,
+    /// Whether the command is a script written to the standard input of the
+    /// shell rather than passed as an argument.
+    script: bool,
     session_configuration: SessionConfiguration,
     sudo: Option<SudoConfig>,
     stdout: Vec<u8>,
     stderr: Vec<u8>,
+    exit_code: Option<i32>,
+    /// Bytes kept from each of stdout and stderr.
+    max_output_bytes: usize,
+    /// Shell and arguments running the command, when not the default.
+    shell: Option<(String, Vec<String>)>,
+    /// Directory the command runs in, instead of the current one.
+    cwd: Option<String>,
+    /// Input written to the command, which then reaches the end of file.
+    stdin: Option<String>,
+    /// Iteration of the loop the command runs in.
+    iteration: Option<u32>,
+    /// Secrets of the `$file:` references of the command, by path, read in
+    /// when it runs so that they are never shown.
+    file_secrets: HashMap<String, String>,
+    sessions: SessionCache,
 }
 
 impl CommandSession {
-    pub(crate) fn new(
+    /// Prepares `command` to run locally, or on `remote` over a session of
+    /// `sessions`, as the user of `sudo` if set.
+    pub fn new(
         command: &CommandType,
         remote: Option<RemoteConfig>,
         sudo: Option<SudoConfig>,
+        sessions: &SessionCache,
     ) -> Result<Self> {
+        ensure!(
+            remote.is_some() || sudo.is_none() || !cfg!(windows),
+            "sudo is not available for local commands on Windows"
+        );
+        if let Some(password) = sudo.as_ref().and_then(|sudo| sudo.password.as_deref()) {
+            logging::add_secret(password);
+        }
         Ok(
             Self {
                 command: Self::resolve_command(command)?,
+                argv: Self::resolve_argv(command)?,
+                script: command.is_script(),
                 session_configuration: if let Some(remote_config) = remote {
-                    Self::init_remote_session(Self::resolve_remote_config(remote_config)?)?
+                    let remote_config = Self::resolve_remote_config(remote_config)?;
+                    SessionConfiguration::Remote(sessions.get(&remote_config)?, remote_config)
                 } else {
                     SessionConfiguration::Local()
                 },
-                sudo: sudo
-                    .map(|sudo_config| Self::resolve_sudo_config(sudo_config))
-                    .transpose()?,
+                sudo,
                 stdout: Vec::new(),
                 stderr: Vec::new(),
+                exit_code: None,
+                max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+                shell: None,
+                cwd: None,
+                stdin: None,
+                iteration: None,
+                file_secrets: HashMap::new(),
+                sessions: sessions.clone(),
             }
         )
     }
 
-    pub(crate) fn get_prompt(&self) -> Result<String> {
+    /// The prompt line shown before the output, ending with the command.
+    pub fn get_prompt(&self) -> Result<String> {
         let (user, prompt_char)  = if let Some(sudo_config) = &self.sudo {
             (sudo_config.user.as_ref().unwrap(), '#')
         } else {
@@ -85,118 +661,1351 @@ impl CommandSession {
         )
     }
 
-    pub(crate) fn get_stdout(&self) -> Cow<'_, str> {
+    /// The host the command runs on, or `None` when it runs locally.
+    pub(crate) fn get_remote_host(&self) -> Option<&str> {
+        match &self.session_configuration {
+            SessionConfiguration::Local() => None,
+            SessionConfiguration::Remote(_, remote_config) => Some(&remote_config.host),
+        }
+    }
+
+    /// Standard output of the last run.
+    pub fn get_stdout(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.stdout)
     }
 
-    pub(crate) fn get_stderr(&self) -> Cow<'_, str> {
+    /// Standard error of the last run.
+    pub fn get_stderr(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.stderr)
     }
 
-    pub(crate) fn run_command(&mut self) -> Result<()> {
-        let cmd = self.get_sudo_command();
-        (self.stdout, self.stderr) = match &self.session_configuration {
+    /// Exit code of the last run, if the command terminated normally.
+    pub fn get_exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Caps the bytes kept from each output stream of the command.
+    pub fn set_max_output_bytes(&mut self, max_output_bytes: usize) {
+        self.max_output_bytes = max_output_bytes;
+    }
+
+    /// Runs the command through `shell`, passing it `args` before the command
+    /// string: by default `/C` to `cmd`, `-Command` to PowerShell and `-c` to
+    /// any other shell.
+    pub fn set_shell(&mut self, shell: String, args: Option<Vec<String>>) {
+        let args = args.unwrap_or_else(|| Self::default_shell_args(&shell));
+        self.shell = Some((shell, args));
+    }
+
+    /// The name of `shell`, without its directory and extension.
+    fn shell_name(shell: &str) -> String {
+        Path::new(shell)
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .unwrap_or(shell)
+            .to_lowercase()
+    }
+
+    fn default_shell_args(shell: &str) -> Vec<String> {
+        let args: &[&str] = match Self::shell_name(shell).as_str() {
+            "cmd" => &["/C"],
+            "powershell" | "pwsh" => &["-NoProfile", "-Command"],
+            _ => &["-c"],
+        };
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// Runs the command in `cwd`, checking that it exists for local commands.
+    pub fn set_cwd(&mut self, cwd: String) -> Result<()> {
+        if let SessionConfiguration::Local() = self.session_configuration {
+            ensure!(Path::new(&cwd).is_dir(), "No such directory: '{}'", cwd);
+        }
+        self.cwd = Some(cwd);
+        Ok(())
+    }
+
+    /// Writes `stdin` to the standard input of the command.
+    pub fn set_stdin(&mut self, stdin: String) {
+        self.stdin = Some(stdin);
+    }
+
+    /// Runs the command as `iteration` of a loop, set in [`ITER_VAR`] and
+    /// substituted for the references to it that the command has.
+    pub(crate) fn set_iteration(&mut self, iteration: u32) {
+        self.iteration = Some(iteration);
+    }
+
+    /// The command string of the current iteration, as shown.
+    fn shown_command(&self) -> String {
+        match self.iteration {
+            Some(iteration) => expand_iteration(&self.command, iteration, true),
+            None => self.command.clone(),
+        }
+    }
+
+    /// The command string of the current iteration, as run.
+    fn expanded_command(&self) -> String {
+        expand_file_secrets(&self.shown_command(), &self.file_secrets)
+    }
+
+    /// The variables set for the command, as `NAME=value`.
+    fn env_assignments(&self) -> Vec<String> {
+        self.iteration
+            .map(|iteration| format!("{}={}", ITER_VAR, iteration))
+            .into_iter()
+            .collect()
+    }
+
+    /// Arguments of `env` setting the variables for the command it runs, as
+    /// `sudo` does not pass on those of its own environment.
+    fn env_args(&self) -> Vec<String> {
+        let assignments = self.env_assignments();
+        if assignments.is_empty() {
+            return assignments;
+        }
+        std::iter::once(String::from("env")).chain(assignments).collect()
+    }
+
+    fn get_shell(&self) -> (String, Vec<String>) {
+        let (shell, args) = self
+            .shell
+            .clone()
+            .unwrap_or_else(|| (String::from("sh"), vec![String::from("-c")]));
+        self.script_shell(shell, args)
+    }
+
+    /// `shell` along with the arguments making it read a script from its
+    /// standard input instead of `args`, if the command is one.
+    fn script_shell(&self, shell: String, args: Vec<String>) -> (String, Vec<String>) {
+        if !self.script {
+            return (shell, args);
+        }
+        let args: &[&str] = match Self::shell_name(&shell).as_str() {
+            "powershell" | "pwsh" => &["-NoProfile", "-Command", "-"],
+            _ => &[],
+        };
+        let args = args.iter().map(|arg| arg.to_string()).collect();
+        (shell, args)
+    }
+
+    /// The shell running local commands, which defaults to `cmd` on Windows.
+    /// Remote hosts keep `sh`, whatever the local platform.
+    fn get_local_shell(&self) -> (String, Vec<String>) {
+        if self.shell.is_none() && cfg!(windows) {
+            let shell = String::from("cmd");
+            let args = Self::default_shell_args(&shell);
+            return self.script_shell(shell, args);
+        }
+        self.get_shell()
+    }
+
+    /// Runs the command to completion, keeping its output.
+    pub fn run_command(&mut self) -> Result<()> {
+        self.run_command_streaming(|_, _| {}, || false)
+    }
+
+    /// Runs the command, passing its output to `on_output` as it arrives.
+    ///
+    /// The command is stopped as soon as `should_stop` returns true, or when
+    /// an output stream exceeds the size limit. Its exit code is then unknown.
+    pub(crate) fn run_command_streaming(
+        &mut self,
+        mut on_output: impl FnMut(OutputStream, &str),
+        should_stop: impl Fn() -> bool,
+    ) -> Result<()> {
+        let _span = info_span!("command", host = self.get_remote_host()).entered();
+        match &self.argv {
+            Some(argv) => info!(?argv, sudo = self.sudo.is_some(), "Running command"),
+            None => {
+                let command = self.shown_command();
+                info!(%command, sudo = self.sudo.is_some(), "Running command");
+            }
+        }
+        let texts = std::iter::once(&self.command).chain(self.argv.iter().flatten());
+        self.file_secrets = match read_file_secrets(texts.map(String::as_str)) {
+            Ok(file_secrets) => file_secrets,
+            Err(e) => {
+                warn!(error = format!("{:#}", e), "Command failed");
+                self.exit_code = None;
+                return Err(e);
+            }
+        };
+        let mut sink = OutputSink {
+            stdout: StreamedOutput::default(),
+            stderr: StreamedOutput::default(),
+            limit: self.max_output_bytes,
+            on_output: &mut on_output,
+        };
+        let result = match &self.session_configuration {
+            SessionConfiguration::Local() if self.argv.is_some() => {
+                self.run_local_argv(&mut sink, &should_stop)
+            }
+            SessionConfiguration::Local() if self.sudo.is_some() => {
+                let (shell, args) = self.get_local_shell();
+                let sudo_args: Vec<String> = self
+                    .sudo_args()
+                    .into_iter()
+                    .chain(self.env_args())
+                    .chain([shell])
+                    .chain(args)
+                    .collect();
+                let command = self.expanded_command();
+                self.run_local_command("sudo", &sudo_args, command, &mut sink, &should_stop)
+            }
             SessionConfiguration::Local() => {
-                Self::run_local_command("sh", cmd)?
+                let (shell, args) = self.get_local_shell();
+                let command = self.expanded_command();
+                self.run_local_command(&shell, &args, command, &mut sink, &should_stop)
             }
-            SessionConfiguration::Remote(session, _) => {
-                Self::run_remote_command(session, cmd)?
+            // Channels have no working directory of their own.
+            SessionConfiguration::Remote(..) => {
+                let cmd = self.get_remote_command();
+                let cmd = match &self.cwd {
+                    Some(cwd) => format!("cd {} && {}", shell_quote(cwd), cmd),
+                    None => cmd,
+                };
+                // Nor are variables passed on by every server.
+                let cmd = self
+                    .env_assignments()
+                    .iter()
+                    .map(|assignment| format!("export {}; ", assignment))
+                    .chain([cmd])
+                    .collect();
+                self.run_remote_command(cmd, &mut sink, &should_stop)
             }
         };
+        sink.flush();
+        self.stdout = sink.stdout.bytes;
+        self.stderr = sink.stderr.bytes;
+        // A command that could not be run has no exit code, whatever the
+        // previous run left.
+        self.exit_code = result.as_ref().ok().copied().flatten();
+        match &result {
+            Ok(Some(exit_code)) => info!(exit_code, "Command finished"),
+            Ok(None) => info!("Command stopped"),
+            Err(e) => warn!(error = format!("{:#}", e), "Command failed"),
+        }
+        result?;
 
         Ok(())
     }
 
-    fn run_local_command(shell: &str, cmd: String) -> Result<(Vec<u8>, Vec<u8>)> {
-        let output = Command::new(shell)
-            .arg("-c")
-            .arg(cmd)
-            .output()
-            .context("Failed to execute a local command")?;
+    fn run_local_command(
+        &self,
+        shell: &str,
+        args: &[String],
+        cmd: String,
+        sink: &mut OutputSink,
+        should_stop: &dyn Fn() -> bool,
+    ) -> Result<Option<i32>> {
+        let mut command = Command::new(shell);
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        if let Some(iteration) = self.iteration {
+            command.env(ITER_VAR, iteration.to_string());
+        }
+        // Without input, the command must not read the terminal of the
+        // interface.
+        let input = self.input();
+        command.stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() });
+        command.args(args);
+        if !self.script {
+            Self::push_shell_command(&mut command, shell, &cmd);
+        }
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to execute a local command with '{}'", shell))?;
+        if let (Some(input), Some(stdin)) = (input, child.stdin.take()) {
+            Self::feed_stdin(stdin, input);
+        }
+
+        Self::stream_child(child, sink, should_stop)
+    }
+
+    /// Passes the command string `cmd` to `shell`.
+    ///
+    /// cmd does not split its command line with the quoting rules arguments
+    /// are escaped for, so it gets the command as is.
+    #[cfg(windows)]
+    fn push_shell_command(command: &mut Command, shell: &str, cmd: &str) {
+        use std::os::windows::process::CommandExt;
+        if Self::shell_name(shell) == "cmd" {
+            command.raw_arg(cmd);
+        } else {
+            command.arg(cmd);
+        }
+    }
+
+    #[cfg(not(windows))]
+    fn push_shell_command(command: &mut Command, _shell: &str, cmd: &str) {
+        command.arg(cmd);
+    }
+
+    /// Writes `input` to `stdin` and closes it.
+    ///
+    /// This happens in the background, as the child may not read all of its
+    /// input before its output is drained.
+    fn feed_stdin(mut stdin: ChildStdin, input: Vec<u8>) {
+        thread::spawn(move || {
+            // The child may exit without reading its input.
+            let _ = stdin.write_all(&input);
+        });
+    }
+
+    /// Sends the chunks read from `reader` until it is exhausted.
+    fn forward_chunks(
+        mut reader: impl Read + Send + 'static,
+        stream: OutputStream,
+        sender: Sender<(OutputStream, std::io::Result<Vec<u8>>)>,
+    ) {
+        thread::spawn(move || {
+            let mut chunk = [0; CHUNK_SIZE];
+            loop {
+                let read = match reader.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => Ok(chunk[..n].to_vec()),
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => Err(e),
+                };
+                let failed = read.is_err();
+                if sender.send((stream, read)).is_err() || failed {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Passes the output of `child` to `sink` until it exits, killing it if
+    /// it has to be stopped.
+    fn stream_child(
+        mut child: Child,
+        sink: &mut OutputSink,
+        should_stop: &dyn Fn() -> bool,
+    ) -> Result<Option<i32>> {
+        // Both pipes are drained concurrently so that neither can fill up
+        // and block the child.
+        let (sender, receiver) = mpsc::channel();
+        Self::forward_chunks(child.stdout.take().unwrap(), OutputStream::Stdout, sender.clone());
+        Self::forward_chunks(child.stderr.take().unwrap(), OutputStream::Stderr, sender);
+
+        let stopped = loop {
+            if should_stop() {
+                break true;
+            }
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok((stream, Ok(chunk))) => {
+                    if !sink.push(stream, &chunk) {
+                        break true;
+                    }
+                }
+                Ok((_, Err(e))) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(e).context("Failed to read the command output");
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break false,
+            }
+        };
+        if stopped {
+            // The child may have exited on its own in the meantime.
+            let _ = child.kill();
+            child.wait().context("Failed to stop a local command")?;
+            return Ok(None);
+        }
+        let status = child.wait().context("Failed to execute a local command")?;
+
+        Ok(status.code())
+    }
+
+    /// Runs the argv directly, going through `sudo` if configured.
+    fn run_local_argv(
+        &self,
+        sink: &mut OutputSink,
+        should_stop: &dyn Fn() -> bool,
+    ) -> Result<Option<i32>> {
+        let argv: Vec<String> = match self.iteration {
+            Some(iteration) => self
+                .argv
+                .iter()
+                .flatten()
+                .map(|arg| expand_iteration(arg, iteration, false))
+                .collect(),
+            None => self.argv.clone().unwrap(),
+        };
+        let argv: Vec<String> = argv
+            .iter()
+            .map(|arg| expand_file_secrets(arg, &self.file_secrets))
+            .collect();
+        let mut command = if self.sudo.is_some() {
+            let mut command = Command::new("sudo");
+            command.args(self.sudo_args()).args(self.env_args()).args(&argv);
+            command
+        } else {
+            let mut command = Command::new(&argv[0]);
+            command.args(&argv[1..]);
+            command
+        };
+
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        if let Some(iteration) = self.iteration {
+            command.env(ITER_VAR, iteration.to_string());
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to execute '{}'", argv[0]))?;
+        Self::feed_stdin(child.stdin.take().unwrap(), self.input().unwrap_or_default());
 
-        Ok((output.stdout, output.stderr))
+        Self::stream_child(child, sink, should_stop)
     }
 
+    /// Fills the fields `remote_config` leaves out from the `~/.ssh/config`
+    /// entry of its host.
     fn resolve_remote_config(remote_config: RemoteConfig) -> Result<RemoteConfig> {
+        let alias = remote_config.host;
+        let mut ssh_config = Self::ssh_config_options(&alias)?;
+        let password = remote_config.password;
+        let private_key = match remote_config.private_key {
+            Some(private_key) => Some(private_key),
+            // A password configured explicitly takes precedence over the
+            // identity of the SSH config.
+            None if password.as_deref().unwrap_or_default().is_empty() => ssh_config
+                .remove("identityfile")
+                .map(|path| Self::expand_home(&path))
+                .transpose()?,
+            None => None,
+        };
+        let port = match remote_config.port {
+            Some(port) => port,
+            None => match ssh_config.remove("port") {
+                Some(port) => port
+                    .parse()
+                    .with_context(|| format!("Invalid port '{}' for '{}' in SSH config", port, alias))?,
+                None => 22,
+            },
+        };
+        let user = match remote_config.user {
+            Some(user) => user,
+            None => ssh_config.remove("user").unwrap_or_else(whoami::username),
+        };
+        let connect_timeout_ms = match remote_config.connect_timeout_ms {
+            Some(timeout) => Some(timeout),
+            // The SSH config counts in seconds.
+            None => match ssh_config.remove("connecttimeout") {
+                Some(timeout) => Some(
+                    timeout
+                        .parse::<u64>()
+                        .map(|seconds| seconds * 1000)
+                        .with_context(|| {
+                            format!(
+                                "Invalid ConnectTimeout '{}' for '{}' in SSH config",
+                                timeout, alias
+                            )
+                        })?,
+                ),
+                None => None,
+            },
+        };
+        let host = match ssh_config.remove("hostname") {
+            Some(hostname) => hostname.replace("%h", &alias),
+            None => alias,
+        };
+
         Ok(
             RemoteConfig {
-                host: Self::resolve_env_str(remote_config.host)?,
-                port: remote_config.port,
-                user: Self::resolve_env_str(remote_config.user)?,
-                password: Self::resolve_env_opt(remote_config.password)?,
+                host,
+                port: Some(port),
+                user: Some(user),
+                password,
+                private_key,
+                use_agent: remote_config.use_agent,
+                strict_host_keys: remote_config.strict_host_keys,
+                jump: remote_config
+                    .jump
+                    .map(|jump| Self::resolve_remote_config(*jump).map(Box::new))
+                    .transpose()?,
+                connect_timeout_ms,
+                auth: remote_config.auth,
+                preflight: remote_config.preflight,
             }
         )
     }
 
-    fn resolve_sudo_config(sudo_config: SudoConfig) -> Result<SudoConfig> {
-        Ok(
-            SudoConfig {
-                user: Self::resolve_env_opt(sudo_config.user)?,
-                password: Self::resolve_env_opt(sudo_config.password)?,
+    fn ssh_dir() -> Result<PathBuf> {
+        let home = env::var_os("HOME").context("Cannot locate ~/.ssh: HOME is not set")?;
+        Ok(PathBuf::from(home).join(".ssh"))
+    }
+
+    fn expand_home(path: &str) -> Result<String> {
+        match path.strip_prefix("~/") {
+            Some(rest) => {
+                let home = env::var("HOME").context("Cannot expand '~': HOME is not set")?;
+                Ok(format!("{}/{}", home, rest))
             }
-        )
+            None => Ok(path.to_owned()),
+        }
     }
 
-    fn init_remote_session(remote_config: RemoteConfig) -> Result<SessionConfiguration> {
+    /// Reads the options of `~/.ssh/config` applying to `alias`, keyed by
+    /// lowercase keyword. A missing file applies no option.
+    fn ssh_config_options(alias: &str) -> Result<HashMap<String, String>> {
+        let Ok(ssh_dir) = Self::ssh_dir() else {
+            return Ok(HashMap::new());
+        };
+        let path = ssh_dir.join("config");
+        if !path.exists() {
+            return Ok(HashMap::new());
+        }
+        let config = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        Ok(Self::parse_ssh_config(&config, alias))
+    }
+
+    /// Collects the options of an SSH config applying to `alias`.
+    ///
+    /// As with OpenSSH, the first value found for an option wins. `Match`
+    /// blocks and `Include` directives are not supported and are skipped.
+    fn parse_ssh_config(config: &str, alias: &str) -> HashMap<String, String> {
+        let alias = alias.to_lowercase();
+        let mut options = HashMap::new();
+        let mut applies = true;
+        for line in config.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((keyword, value)) = line.split_once(|c: char| c.is_whitespace() || c == '=')
+            else {
+                continue;
+            };
+            let value = value
+                .trim_start_matches(|c: char| c.is_whitespace() || c == '=')
+                .trim()
+                .trim_matches('"');
+            match keyword.to_lowercase().as_str() {
+                "host" => applies = Self::host_matches(&value.to_lowercase(), &alias),
+                "match" => applies = false,
+                "include" => {}
+                keyword if applies => {
+                    options.entry(keyword.to_owned()).or_insert_with(|| value.to_owned());
+                }
+                _ => {}
+            }
+        }
+        options
+    }
+
+    /// Whether `alias` matches the `Host` patterns, none of the negated
+    /// (`!`) patterns being matched.
+    fn host_matches(patterns: &str, alias: &str) -> bool {
+        let mut matched = false;
+        for pattern in patterns.split_whitespace() {
+            if let Some(pattern) = pattern.strip_prefix('!') {
+                if Self::glob_match(pattern, alias) {
+                    return false;
+                }
+            } else if Self::glob_match(pattern, alias) {
+                matched = true;
+            }
+        }
+        matched
+    }
+
+    /// Matches `text` against a pattern where `*` stands for any characters
+    /// and `?` for exactly one.
+    fn glob_match(pattern: &str, text: &str) -> bool {
+        let mut chars = pattern.chars();
+        match chars.next() {
+            None => text.is_empty(),
+            Some('*') => text
+                .char_indices()
+                .map(|(idx, _)| idx)
+                .chain([text.len()])
+                .any(|idx| Self::glob_match(chars.as_str(), &text[idx..])),
+            Some('?') => {
+                let mut text = text.chars();
+                text.next().is_some() && Self::glob_match(chars.as_str(), text.as_str())
+            }
+            Some(c) => text
+                .strip_prefix(c)
+                .is_some_and(|text| Self::glob_match(chars.as_str(), text)),
+        }
+    }
+
+    /// Connects and authenticates to the host of `remote_config`, sending the
+    /// questions of a keyboard-interactive authentication to `questions`.
+    fn connect(
+        remote_config: &RemoteConfig,
+        questions: Option<&Sender<AuthQuestion>>,
+    ) -> Result<Session> {
+        let _span = info_span!("connect", host = %remote_config.host).entered();
+        let Some(jump) = &remote_config.jump else {
+            let tcp = Self::connect_tcp(remote_config)?;
+            return Self::open_session(tcp, remote_config, questions);
+        };
+        info!(jump = %jump.host, "Connecting through jump host");
+        let jump_session = Self::connect(jump, questions)
+            .with_context(|| format!("Failed to connect to jump host '{}'", jump.host))?;
+        let result = Self::tunnel(jump_session, remote_config)
+            .and_then(|tcp| Self::open_session(tcp, remote_config, questions));
+        result.with_context(|| {
+            format!(
+                "Failed to connect to '{}' through jump host '{}'",
+                remote_config.host, jump.host
+            )
+        })
+    }
+
+    /// The time allowed to connect to the host of `remote_config`, and for
+    /// every reply of its server.
+    fn connect_timeout(remote_config: &RemoteConfig) -> Duration {
+        remote_config
+            .connect_timeout_ms
+            .map_or(DEFAULT_CONNECT_TIMEOUT, Duration::from_millis)
+    }
+
+    /// Connects to the host of `remote_config`, trying each of its addresses
+    /// in turn for at most the connect timeout.
+    fn connect_tcp(remote_config: &RemoteConfig) -> Result<TcpStream> {
         let addr = format!("{}:{}", remote_config.host, remote_config.port.unwrap());
-        let tcp = TcpStream::connect(addr)?;
+        let timeout = Self::connect_timeout(remote_config);
+        let mut error = None;
+        let addrs = addr
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve '{}'", remote_config.host))?;
+        for socket_addr in addrs {
+            debug!(%socket_addr, timeout_ms = timeout.as_millis() as u64, "Connecting");
+            match TcpStream::connect_timeout(&socket_addr, timeout) {
+                Ok(tcp) => return Ok(tcp),
+                Err(e) => {
+                    debug!(%socket_addr, error = %e, "Connection failed");
+                    error = Some(e);
+                }
+            }
+        }
+        match error {
+            Some(e) if e.kind() == ErrorKind::TimedOut => {
+                warn!(%addr, timeout_ms = timeout.as_millis() as u64, "Connection timed out");
+                bail!(
+                    "Connection to '{}' timed out after {:.1}s",
+                    addr,
+                    timeout.as_secs_f64()
+                )
+            }
+            Some(e) => Err(e).with_context(|| format!("Failed to connect to '{}'", addr)),
+            None => bail!("No address found for '{}'", remote_config.host),
+        }
+    }
+
+    /// Opens a tunnel to the host of `remote_config` through `jump_session`.
+    ///
+    /// libssh2 needs a socket to run a session over, so the tunnel is exposed
+    /// as a loopback connection whose traffic a thread relays to a direct
+    /// TCP/IP channel of the jump host. The thread, and with it the jump
+    /// session, ends once the returned stream is closed.
+    fn tunnel(jump_session: Session, remote_config: &RemoteConfig) -> Result<TcpStream> {
+        let mut channel = jump_session.channel_direct_tcpip(
+            &remote_config.host,
+            remote_config.port.unwrap(),
+            None,
+        )?;
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let tcp = TcpStream::connect(listener.local_addr()?)?;
+        let (mut relay, _) = listener.accept()?;
+
+        jump_session.set_blocking(false);
+        relay.set_nonblocking(true)?;
+        thread::spawn(move || {
+            let mut buf = [0u8; 16 * 1024];
+            loop {
+                let mut idle = true;
+                match relay.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        idle = false;
+                        if Self::write_retrying(&mut channel, &buf[..n]).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(_) => break,
+                }
+                match channel.read(&mut buf) {
+                    Ok(0) if channel.eof() => break,
+                    Ok(0) => {}
+                    Ok(n) => {
+                        idle = false;
+                        if Self::write_retrying(&mut relay, &buf[..n]).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(_) => break,
+                }
+                if idle {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+            // Keep the jump session alive as long as the tunnel is.
+            drop(jump_session);
+        });
+
+        Ok(tcp)
+    }
+
+    /// Writes all of `data` to a non-blocking writer.
+    fn write_retrying(writer: &mut impl Write, mut data: &[u8]) -> std::io::Result<()> {
+        while !data.is_empty() {
+            match writer.write(data) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => data = &data[n..],
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        writer.flush()
+    }
+
+    /// Runs the SSH handshake over `tcp`, then verifies the host key and
+    /// authenticates.
+    fn open_session(
+        tcp: TcpStream,
+        remote_config: &RemoteConfig,
+        questions: Option<&Sender<AuthQuestion>>,
+    ) -> Result<Session> {
         let mut session = Session::new()?;
+        // Also bounds the replies awaited by the later blocking calls, so
+        // that a server gone silent fails the action instead of hanging.
+        let timeout = Self::connect_timeout(remote_config);
+        session.set_timeout(timeout.as_millis().try_into().unwrap_or(u32::MAX));
         session.set_tcp_stream(tcp);
-        session.handshake()?;
+        if let Err(e) = session.handshake() {
+            if e.code() == ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT) {
+                warn!(timeout_ms = timeout.as_millis() as u64, "SSH handshake timed out");
+                bail!(
+                    "SSH handshake with '{}' timed out after {:.1}s",
+                    remote_config.host,
+                    timeout.as_secs_f64()
+                );
+            }
+            return Err(e)
+                .with_context(|| format!("SSH handshake with '{}' failed", remote_config.host));
+        }
+        Self::verify_host_key(&session, remote_config, &Self::known_hosts_path()?)?;
+
+        let user = remote_config.user.as_deref().unwrap();
+        let password = remote_config.password.as_deref().unwrap_or_default();
+        logging::add_secret(password);
+        let auth = remote_config.auth.unwrap_or(if remote_config.private_key.is_some() {
+            AuthMethod::Key
+        } else if remote_config.use_agent.unwrap_or(password.is_empty()) {
+            AuthMethod::Agent
+        } else {
+            AuthMethod::Password
+        });
+        info!(user, ?auth, "Authenticating");
+        match auth {
+            AuthMethod::Key => {
+                let private_key = remote_config
+                    .private_key
+                    .as_deref()
+                    .context("Authentication with a key needs a 'private_key'")?;
+                session
+                    .userauth_pubkey_file(user, None, Path::new(private_key), None)
+                    .with_context(|| format!("Authentication with key '{}' failed", private_key))?;
+            }
+            AuthMethod::Agent => {
+                let agent_result = Self::authenticate_with_agent(&session, user);
+                // Fall back on the password, if any, when the agent was forced.
+                if agent_result.is_err() && !password.is_empty() {
+                    session.userauth_password(user, password)?;
+                } else {
+                    agent_result?;
+                }
+            }
+            AuthMethod::Password => session.userauth_password(user, password)?,
+            AuthMethod::KeyboardInteractive => {
+                let mut prompter = InteractiveAuth {
+                    label: format!("{}@{}", user, remote_config.host),
+                    password,
+                    questions,
+                    unanswered: None,
+                };
+                let result = session.userauth_keyboard_interactive(user, &mut prompter);
+                if let Some(reason) = prompter.unanswered {
+                    bail!("Keyboard-interactive authentication {}", reason);
+                }
+                result.context("Keyboard-interactive authentication failed")?;
+            }
+        }
+        ensure!(session.authenticated(), "Session authentication failed");
+        info!(user, "Authenticated");
 
-        session.userauth_password(&remote_config.user, remote_config.password.as_ref().unwrap())?;
-        ensure!(session.authenticated(), "Session password authentication failed");
+        Ok(session)
+    }
 
-        Ok(SessionConfiguration::Remote(session, remote_config))
+    fn known_hosts_path() -> Result<PathBuf> {
+        Ok(Self::ssh_dir()?.join("known_hosts"))
     }
 
-    fn run_remote_command(session: &Session, cmd: String) -> Result<(Vec<u8>, Vec<u8>)> {
-        let mut channel = session.channel_session()?;
-        channel.exec(cmd.as_str())?;
+    /// Checks the host key of `session` against the `known_hosts` file.
+    fn verify_host_key(
+        session: &Session,
+        remote_config: &RemoteConfig,
+        known_hosts_path: &Path,
+    ) -> Result<()> {
+        let (key, key_type) = session.host_key().context("The server sent no host key")?;
+        Self::check_host_key(session, key, key_type, remote_config, known_hosts_path)
+    }
 
-        let mut stdout: Vec<u8> = Vec::new();
-        channel.read_to_end(&mut stdout)?;
+    /// Checks `key`, the host key of `remote_config`, against the
+    /// `known_hosts` file, with the known hosts support of `session`.
+    ///
+    /// Unknown hosts are rejected when `strict_host_keys` is set (the default),
+    /// or accepted and appended to the file otherwise. A key differing from
+    /// the known one is always rejected.
+    fn check_host_key(
+        session: &Session,
+        key: &[u8],
+        key_type: HostKeyType,
+        remote_config: &RemoteConfig,
+        known_hosts_path: &Path,
+    ) -> Result<()> {
+        let host = &remote_config.host;
+        let port = remote_config.port.unwrap();
+        let mut known_hosts = session.known_hosts()?;
+        if known_hosts_path.exists() {
+            known_hosts
+                .read_file(known_hosts_path, KnownHostFileKind::OpenSSH)
+                .with_context(|| format!("Failed to read '{}'", known_hosts_path.display()))?;
+        }
 
-        let mut stderr: Vec<u8> = Vec::new();
-        channel.stderr().read_to_end(&mut stderr)?;
+        match known_hosts.check_port(host, port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::Mismatch => bail!(
+                "Host key of '{}' does not match the one in '{}': someone may be intercepting the connection",
+                host,
+                known_hosts_path.display()
+            ),
+            CheckResult::NotFound if remote_config.strict_host_keys.unwrap_or(true) => bail!(
+                "Host key of '{}' is not in '{}': connect once with ssh, or set 'strict_host_keys: false' to accept it",
+                host,
+                known_hosts_path.display()
+            ),
+            CheckResult::NotFound => {
+                let name = if port == 22 {
+                    host.clone()
+                } else {
+                    format!("[{}]:{}", host, port)
+                };
+                known_hosts.add(&name, key, "", key_type.into())?;
+                // Append the new entry rather than rewriting the whole file,
+                // which would drop the lines libssh2 cannot represent.
+                let added = known_hosts.iter()?.pop().context("Failed to add the host key")?;
+                let mut line = known_hosts.write_string(&added, KnownHostFileKind::OpenSSH)?;
+                if !line.ends_with('\n') {
+                    line.push('\n');
+                }
+                if let Some(dir) = known_hosts_path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(known_hosts_path)
+                    .and_then(|mut file| file.write_all(line.as_bytes()))
+                    .with_context(|| format!("Failed to update '{}'", known_hosts_path.display()))
+            }
+            CheckResult::Failure => bail!("Failed to check the host key of '{}'", host),
+        }
+    }
 
-        Ok((stdout, stderr))
+    /// Tries every identity of the running SSH agent until one is accepted.
+    fn authenticate_with_agent(session: &Session, user: &str) -> Result<()> {
+        let mut agent = session.agent()?;
+        agent
+            .connect()
+            .context("SSH agent is not running (is SSH_AUTH_SOCK set?)")?;
+        agent
+            .list_identities()
+            .context("Failed to list the SSH agent identities")?;
+        let identities = agent.identities()?;
+        ensure!(!identities.is_empty(), "SSH agent has no identities loaded");
+        for identity in &identities {
+            if agent.userauth(user, identity).is_ok() {
+                return Ok(());
+            }
+        }
+        bail!(
+            "None of the {} SSH agent identities was accepted for user '{}'",
+            identities.len(),
+            user
+        )
     }
 
-    fn resolve_env_str(value: String) -> Result<String> {
-        if value.starts_with("$env:") {
-            let env_var = &value[5..];
-            env::var(env_var).with_context(|| format!("Missing environment variable: '{}'", env_var))
+    fn run_remote_command(
+        &mut self,
+        cmd: String,
+        sink: &mut OutputSink,
+        should_stop: &dyn Fn() -> bool,
+    ) -> Result<Option<i32>> {
+        // On a PTY, sudo reads the password from the terminal once it asks
+        // for it, rather than from the input.
+        let pty = self.sudo_pty();
+        let (input, password) = if pty {
+            let password = format!("{}\n", self.sudo.as_ref().unwrap().password.as_ref().unwrap());
+            (self.stdin_text().map(String::into_bytes), Some(password.into_bytes()))
         } else {
-            Ok(value)
+            (self.input(), None)
+        };
+        let SessionConfiguration::Remote(session, remote_config) = &mut self.session_configuration
+        else {
+            unreachable!("not a remote session");
+        };
+        // Streaming switches the whole session to non-blocking, which would
+        // leave another command reading it without a timeout: a command
+        // running at the same time on the same host gets its own session.
+        let claim = self.sessions.claim(remote_config);
+        let mut own_session;
+        let session = match claim {
+            Some(_) => session,
+            None => {
+                own_session = self.sessions.connect_uncached(remote_config)?;
+                &mut own_session
+            }
+        };
+        let mut channel = match session.channel_session() {
+            Ok(channel) => channel,
+            Err(_) if claim.is_some() => {
+                // The cached connection died since it was opened: connect
+                // again once, for this and the following commands.
+                *session = self.sessions.reconnect(remote_config)?;
+                session.channel_session()?
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if pty {
+            channel.request_pty("xterm", None, None)?;
+        }
+        channel.exec(cmd.as_str())?;
+
+        // Both streams are polled, so that the session can be left
+        // non-blocking in between to check for a stop request.
+        session.set_blocking(false);
+        let finished = Self::stream_channel(
+            &mut channel,
+            input.as_deref(),
+            password.as_deref(),
+            sink,
+            should_stop,
+        );
+        session.set_blocking(true);
+        if !finished? {
+            // Closing the channel stops the remote command; its exit status
+            // is lost.
+            channel.close()?;
+            channel.wait_close()?;
+            return Ok(None);
+        }
+
+        channel.wait_close()?;
+        Ok(Some(channel.exit_status()?))
+    }
+
+    /// Writes `input` to `channel` and passes its output to `sink`, until it
+    /// reaches the end of file. Returns `false` if the command has to be
+    /// stopped first.
+    ///
+    /// With a `password`, the channel is a PTY: the sudo prompts are answered
+    /// with it and removed from the output, and the input is only written
+    /// once the first one is, so that it cannot be taken for the password.
+    fn stream_channel(
+        channel: &mut Channel,
+        mut input: Option<&[u8]>,
+        password: Option<&[u8]>,
+        sink: &mut OutputSink,
+        should_stop: &dyn Fn() -> bool,
+    ) -> Result<bool> {
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut pty = password.map(|_| PtyOutput::default());
+        let mut held_input = if pty.is_some() { input.take() } else { None };
+        loop {
+            let mut idle = true;
+            // The input is written as the window allows, in between reads, so
+            // that a command filling its output cannot block on it.
+            if let Some(remaining) = input {
+                let written = if remaining.is_empty() {
+                    channel.send_eof().map(|()| 0).map_err(std::io::Error::from)
+                } else {
+                    channel.write(remaining)
+                };
+                match written {
+                    Ok(n) if !remaining.is_empty() => {
+                        idle = false;
+                        input = Some(&remaining[n..]);
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    // The command may exit without reading its input.
+                    _ => input = None,
+                }
+            }
+            for stream in [OutputStream::Stdout, OutputStream::Stderr] {
+                let read = match stream {
+                    OutputStream::Stdout => channel.read(&mut chunk),
+                    OutputStream::Stderr => channel.stderr().read(&mut chunk),
+                };
+                match read {
+                    Ok(0) => {}
+                    Ok(n) => {
+                        idle = false;
+                        let output = match &mut pty {
+                            Some(pty) => {
+                                let (shown, prompts) = pty.push(&chunk[..n]);
+                                for _ in 0..prompts {
+                                    Self::write_retrying(channel, password.unwrap())
+                                        .context("Failed to write the sudo password")?;
+                                    input = input.or(held_input.take());
+                                }
+                                Cow::Owned(shown)
+                            }
+                            None => Cow::Borrowed(&chunk[..n]),
+                        };
+                        if !sink.push(stream, &output) {
+                            return Ok(false);
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e).context("Failed to read the command output"),
+                }
+            }
+            if should_stop() {
+                return Ok(false);
+            }
+            if idle {
+                if channel.eof() {
+                    if let Some(pty) = &mut pty {
+                        sink.push(OutputStream::Stdout, &pty.flush());
+                    }
+                    return Ok(true);
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
         }
     }
 
-    fn resolve_env_opt(value_opt: Option<String>) -> Result<Option<String>> {
-        value_opt
-            .map(|value| Self::resolve_env_str(value))
-            .transpose()
+    fn resolve_argv(command: &CommandType) -> Result<Option<Vec<String>>> {
+        let CommandType::ExecArgv { argv } = command else {
+            return Ok(None);
+        };
+        ensure!(!argv.is_empty(), "Command argv is empty");
+        Ok(Some(argv.clone()))
     }
 
     fn resolve_command(command: &CommandType) -> Result<String> {
-        let mut cmd_parts_resolved = Vec::new();
-        for s in command.get_command().split_whitespace() {
-            cmd_parts_resolved.push(Self::resolve_env_str(s.to_string())?);
+        if let Some(argv) = Self::resolve_argv(command)? {
+            return Ok(argv.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" "));
         }
 
-        Ok(cmd_parts_resolved.join(" "))
+        Ok(command.get_command())
     }
 
-    fn get_sudo_command(&self) -> String {
-        if let Some(sudo_config) = &self.sudo {
-            format!(
-                "echo {} | sudo -kS -u {} -p '' {}",
-                sudo_config.password.as_ref().unwrap(),
-                sudo_config.user.as_ref().unwrap(),
-                self.command,
-            )
+    /// The command as run by the shell, quoted for another shell.
+    fn shell_invocation(&self) -> String {
+        let (shell, args) = self.get_shell();
+        std::iter::once(shell)
+            .chain(args)
+            .chain((!self.script).then(|| self.expanded_command()))
+            .map(|word| shell_quote(&word))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Whether the command runs with remote sudo on a PTY.
+    fn sudo_pty(&self) -> bool {
+        matches!(self.session_configuration, SessionConfiguration::Remote(..))
+            && self.sudo.as_ref().is_some_and(|sudo| sudo.request_pty.unwrap_or(false))
+    }
+
+    /// Arguments making `sudo` read the password from its standard input,
+    /// without prompting for it, or on a PTY from the terminal after a known
+    /// prompt.
+    fn sudo_args(&self) -> Vec<String> {
+        let user = self.sudo.as_ref().unwrap().user.clone().unwrap();
+        if self.sudo_pty() {
+            ["-k", "-u", &user, "-p", SUDO_PTY_PROMPT].map(String::from).to_vec()
         } else {
-            self.command.clone()
+            ["-kS", "-u", &user, "-p", ""].map(String::from).to_vec()
+        }
+    }
+
+    /// What the command reads: its script if it is one, else `stdin`.
+    fn stdin_text(&self) -> Option<String> {
+        if self.script {
+            return Some(format!("{}\n", self.expanded_command()));
         }
+        self.stdin.clone()
+    }
+
+    /// Input written to the command: the sudo password followed by `stdin`.
+    fn input(&self) -> Option<Vec<u8>> {
+        let password = self.sudo.as_ref().map(|sudo_config| {
+            format!("{}\n", sudo_config.password.as_ref().unwrap())
+        });
+        match (password, self.stdin_text()) {
+            (None, None) => None,
+            (password, stdin) => Some(
+                password
+                    .unwrap_or_default()
+                    .into_bytes()
+                    .into_iter()
+                    .chain(stdin.iter().flat_map(|stdin| stdin.bytes()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The command line handed to the remote shell.
+    ///
+    /// The sudo password is kept out of it, and written to the standard input
+    /// of `sudo` instead.
+    fn get_remote_command(&self) -> String {
+        if self.sudo.is_some() {
+            let sudo_args: Vec<String> = self
+                .sudo_args()
+                .into_iter()
+                .chain(self.env_args())
+                .map(|arg| shell_quote(&arg))
+                .collect();
+            format!("sudo {} {}", sudo_args.join(" "), self.shell_invocation())
+        } else if (self.shell.is_some() || self.script) && self.argv.is_none() {
+            self.shell_invocation()
+        } else {
+            self.expanded_command()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// A host on the loopback interface, given up on after `timeout_ms`.
+    fn loopback(port: u16, timeout_ms: u64) -> RemoteConfig {
+        RemoteConfig {
+            host: String::from("127.0.0.1"),
+            port: Some(port),
+            user: Some(String::from("demo")),
+            connect_timeout_ms: Some(timeout_ms),
+            ..Default::default()
+        }
+    }
+
+    /// The key blob of an ed25519 host key made of `byte` alone.
+    fn ed25519_key(byte: u8) -> Vec<u8> {
+        let mut key = Vec::new();
+        for field in [&b"ssh-ed25519"[..], &[byte; 32]] {
+            key.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            key.extend_from_slice(field);
+        }
+        key
+    }
+
+    /// Checks `key` as the host key of `host`, port 2222.
+    fn check_key(host: &str, key: &[u8], strict: bool, known_hosts: &Path) -> Result<()> {
+        let remote_config = RemoteConfig {
+            host: host.to_owned(),
+            strict_host_keys: Some(strict),
+            ..loopback(2222, 1000)
+        };
+        let session = Session::new().unwrap();
+        CommandSession::check_host_key(
+            &session,
+            key,
+            HostKeyType::Ed25519,
+            &remote_config,
+            known_hosts,
+        )
+    }
+
+    /// A copy of the known_hosts fixture, for the test `name` to change.
+    fn known_hosts(name: &str) -> PathBuf {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/known_hosts");
+        let path = env::temp_dir().join(format!("autopilot-{}-{}", std::process::id(), name));
+        fs::copy(fixture, &path).unwrap();
+        path
+    }
+
+    #[test]
+    fn known_host_keys_are_accepted() {
+        let path = known_hosts("known-key");
+        assert!(check_key("demo.example.com", &ed25519_key(1), true, &path).is_ok());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn changed_host_keys_are_rejected() {
+        let path = known_hosts("changed-key");
+        // Even when unknown hosts would be accepted.
+        let error = check_key("demo.example.com", &ed25519_key(2), false, &path).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Host key of 'demo.example.com' does not match the one in"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unknown_hosts_are_rejected_unless_lenient() {
+        let path = known_hosts("unknown-host");
+        let error = check_key("new.example.com", &ed25519_key(3), true, &path).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Host key of 'new.example.com' is not in"));
+
+        // Accepted, the key is known from then on.
+        assert!(check_key("new.example.com", &ed25519_key(3), false, &path).is_ok());
+        let contents = fs::read_to_string(&path).unwrap();
+        let added = contents.lines().last().unwrap();
+        assert!(added.starts_with("[new.example.com]:2222 ssh-ed25519 "));
+        assert!(check_key("new.example.com", &ed25519_key(3), true, &path).is_ok());
+        assert!(check_key("new.example.com", &ed25519_key(4), true, &path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn output_is_kept_up_to_the_limit() {
+        let mut output = StreamedOutput::default();
+        assert_eq!(output.push(b"abc\n", 4), (String::from("abc\n"), true));
+        // The next byte is one too many.
+        assert_eq!(output.push(b"d", 4), (String::from(TRUNCATED_NOTICE), false));
+        assert_eq!(output.bytes, b"abc\n(output truncated)\n");
+    }
+
+    #[test]
+    fn truncated_lines_end_before_the_notice() {
+        let mut output = StreamedOutput::default();
+        assert_eq!(
+            output.push(b"hello world", 5),
+            (format!("hello\n{}", TRUNCATED_NOTICE), false)
+        );
+    }
+
+    #[test]
+    fn characters_split_across_chunks_wait_for_their_end() {
+        let mut output = StreamedOutput::default();
+        let bytes = "é".as_bytes();
+        assert_eq!(output.push(&bytes[..1], 16), (String::new(), true));
+        assert_eq!(output.push(&bytes[1..], 16), (String::from("é"), true));
+    }
+
+    #[test]
+    fn sudo_passwords_stay_out_of_remote_command_lines() {
+        let sudo = SudoConfig {
+            user: Some(String::from("root")),
+            password: Some(String::from("s3cr3t-pa55")),
+            request_pty: None,
+        };
+        let command = CommandType::Single(String::from("cat /etc/shadow"));
+        let mut session =
+            CommandSession::new(&command, None, Some(sudo), &SessionCache::default()).unwrap();
+        session.set_stdin(String::from("input\n"));
+        let command_line = session.get_remote_command();
+        assert!(command_line.starts_with("sudo -kS -u root -p '' "));
+        assert!(command_line.contains("cat /etc/shadow"));
+        assert!(!command_line.contains("s3cr3t-pa55"));
+        // The password is read by sudo, ahead of the input of the command.
+        assert_eq!(session.input().unwrap(), b"s3cr3t-pa55\ninput\n");
+    }
+
+    #[test]
+    fn file_secrets_are_only_read_in_when_run() {
+        let path = env::temp_dir().join(format!("autopilot-{}-secret", std::process::id()));
+        fs::write(&path, "hunter2\n").unwrap();
+        let reference = format!("$file:{}", path.display());
+        let sessions = SessionCache::default();
+        let command = CommandType::Single(format!("printf %s {}", reference));
+        let mut session = CommandSession::new(&command, None, None, &sessions).unwrap();
+        let argv = CommandType::ExecArgv {
+            argv: vec![
+                String::from("printf"),
+                String::from("%s"),
+                format!("--token={}", reference),
+            ],
+        };
+        let mut argv_session = CommandSession::new(&argv, None, None, &sessions).unwrap();
+        let results = (session.run_command(), argv_session.run_command());
+        fs::remove_file(&path).unwrap();
+        results.0.unwrap();
+        results.1.unwrap();
+        let prompt = session.get_prompt().unwrap();
+        assert!(prompt.ends_with(&format!("$ printf %s {}\n", reference)));
+        assert_eq!(session.get_stdout(), "hunter2");
+        assert_eq!(argv_session.get_stdout(), "--token=hunter2");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn local_commands_without_input_read_nothing() {
+        let command = CommandType::Single(String::from("readlink /proc/self/fd/0"));
+        let mut session =
+            CommandSession::new(&command, None, None, &SessionCache::default()).unwrap();
+        session.run_command().unwrap();
+        assert_eq!(session.get_stdout(), "/dev/null\n");
+    }
+
+    #[test]
+    fn running_commands_do_not_share_a_session() {
+        let sessions = SessionCache::default();
+        let claim = sessions.claim(&loopback(22, 0));
+        assert!(claim.is_some());
+        // Another command on the same host needs a session of its own, but
+        // not one for another user.
+        assert!(sessions.claim(&loopback(22, 0)).is_none());
+        let other_user = RemoteConfig {
+            user: Some(String::from("other")),
+            ..loopback(22, 0)
+        };
+        assert!(sessions.claim(&other_user).is_some());
+        drop(claim);
+        assert!(sessions.claim(&loopback(22, 0)).is_some());
+    }
+
+    #[test]
+    fn closed_ports_fail_within_the_timeout() {
+        let port = TcpListener::bind(("127.0.0.1", 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let start = Instant::now();
+        let error = CommandSession::connect_tcp(&loopback(port, 500)).unwrap_err();
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(error.to_string().starts_with("Failed to connect to '127.0.0.1:"));
+    }
+
+    #[test]
+    fn silent_servers_time_out_the_handshake() {
+        // Connections are queued, but the server never speaks.
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let remote_config = loopback(listener.local_addr().unwrap().port(), 300);
+        let start = Instant::now();
+        let tcp = CommandSession::connect_tcp(&remote_config).unwrap();
+        let Err(error) = CommandSession::open_session(tcp, &remote_config, None) else {
+            panic!("handshake succeeded");
+        };
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(
+            error.to_string(),
+            "SSH handshake with '127.0.0.1' timed out after 0.3s"
+        );
     }
 }
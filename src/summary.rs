@@ -0,0 +1,37 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{fs, path::Path};
+
+/// Outcome of a single command action execution.
+#[derive(Clone, Debug, Serialize)]
+pub struct CommandRecord {
+    pub stage: String,
+    pub command: String,
+    /// Exit code of the last repetition, if the command terminated normally.
+    pub exit_code: Option<i32>,
+    pub duration_ms: u128,
+}
+
+/// Machine-readable summary of a presentation run.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub total_stages: usize,
+    pub total_actions: usize,
+    pub actions_run: usize,
+    pub commands: Vec<CommandRecord>,
+    pub force_stops: usize,
+    pub finished: bool,
+    pub success: bool,
+}
+
+impl Summary {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize summary")?;
+        fs::write(path, json)
+            .with_context(|| format!("Failed to write summary to '{}'", path.display()))
+    }
+}
@@ -0,0 +1,92 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// How the entries of a transcript are written.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum TranscriptFormat {
+    /// Plain text, each entry under a header with its time and stage.
+    #[default]
+    Text,
+    /// One JSON object per entry and line.
+    Jsonl,
+}
+
+/// An output entry, as written to a JSON-lines transcript.
+#[derive(Serialize)]
+struct Record<'a> {
+    /// Milliseconds since the Unix epoch.
+    timestamp_ms: u128,
+    /// Milliseconds since the transcript was opened.
+    elapsed_ms: u128,
+    stage: &'a str,
+    text: &'a str,
+}
+
+/// Log of the output shown during a presentation.
+///
+/// Every entry is written as soon as it is recorded, so that an interrupted
+/// presentation still leaves the output up to that point.
+pub struct Transcript {
+    file: File,
+    format: TranscriptFormat,
+    start: Instant,
+}
+
+impl Transcript {
+    /// Opens `path` to append entries to it, creating it if needed.
+    pub fn open(path: &Path, format: TranscriptFormat) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open transcript '{}'", path.display()))?;
+        Ok(Self {
+            file,
+            format,
+            start: Instant::now(),
+        })
+    }
+
+    /// Appends the text of an output entry shown in `stage`.
+    pub fn record(&mut self, stage: &str, text: &str) -> Result<()> {
+        let elapsed = self.start.elapsed();
+        let line = match self.format {
+            TranscriptFormat::Text => {
+                let newline = if text.ends_with('\n') { "" } else { "\n" };
+                format!(
+                    "[{:.3}s] {}\n{}{}\n",
+                    elapsed.as_secs_f64(),
+                    stage,
+                    text,
+                    newline
+                )
+            }
+            TranscriptFormat::Jsonl => {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                let record = Record {
+                    timestamp_ms: timestamp.as_millis(),
+                    elapsed_ms: elapsed.as_millis(),
+                    stage,
+                    text,
+                };
+                serde_json::to_string(&record)? + "\n"
+            }
+        };
+        self.file
+            .write_all(line.as_bytes())
+            .context("Failed to write to the transcript")
+    }
+}
@@ -3,49 +3,990 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use ratatui::{
+    layout::{Constraint, Flex, Layout, Rect},
     prelude::Margin,
-    style::{Color, Style, Stylize},
-    symbols::border,
+    style::{Color, Modifier, Style, Stylize},
+    symbols::{self, border},
     text::{Line, Span},
-    widgets::{Block, Padding, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState},
+    widgets::{
+        Block, Clear, LineGauge, List, Padding, Paragraph, Scrollbar, ScrollbarOrientation,
+        ScrollbarState,
+    },
     Frame,
 };
 
-use crate::app::App;
+use std::ops::Range;
+use unicode_width::UnicodeWidthChar;
 
-fn render_text(app: &App) -> Vec<Line<'_>> {
-    app.buffer
-        .lock()
-        .unwrap()
-        .iter()
-        .map(|t| {
-            let mut res = t.clone().into_lines();
+use crate::{
+    app::{App, BufferedOutput, Mode, PreflightCheck, PreflightOutcome, Search},
+    color::{colors_enabled, styled},
+    config::BorderStyle,
+    keymap::KeyAction,
+    links,
+};
+
+/// Narrowest terminal the outline pane is shown on, beside the output.
+const OUTLINE_MIN_WIDTH: u16 = 80;
+
+/// Marker ending a row whose line continues on the next row.
+const WRAP_MARKER: &str = "↩";
+
+/// Actions listed in the help overlay, with the keys bound to them.
+const KEYBINDINGS: &[(&[KeyAction], &str)] = &[
+    (&[KeyAction::Next], "Next action / stop running action"),
+    (&[KeyAction::Prev], "Previous action"),
+    (&[KeyAction::Skip], "Skip the next action"),
+    (
+        &[KeyAction::ScrollUp, KeyAction::ScrollDown],
+        "Scroll one line",
+    ),
+    (
+        &[KeyAction::PageUp, KeyAction::PageDown],
+        "Scroll ten lines",
+    ),
+    (
+        &[KeyAction::Top, KeyAction::Bottom],
+        "Scroll to the top/bottom",
+    ),
+    (&[KeyAction::LineNumbers], "Toggle line numbers"),
+    (&[KeyAction::Wrap], "Toggle line wrapping"),
+    (&[KeyAction::Outline], "Toggle the outline pane"),
+    (&[KeyAction::Instructions], "Toggle instructions bar"),
+    (
+        &[KeyAction::SelectPrev, KeyAction::SelectNext],
+        "Select previous/next command",
+    ),
+    (&[KeyAction::Rerun], "Re-run selected command"),
+    (&[KeyAction::Search], "Search the output"),
+    (
+        &[KeyAction::NextMatch, KeyAction::PrevMatch],
+        "Next/previous match",
+    ),
+    (
+        &[KeyAction::Copy, KeyAction::CopyAll],
+        "Copy last/all output",
+    ),
+    (&[KeyAction::OpenLink], "Open the latest link"),
+    (&[KeyAction::Clear], "Clear selection, search and preflight"),
+    (&[KeyAction::Contents], "Jump to a stage"),
+    (&[], "Jump to stage 1 to 9"),
+    (&[KeyAction::Restart], "Restart the presentation"),
+    (&[KeyAction::Help], "Toggle this help"),
+    (&[KeyAction::Quit], "Quit"),
+];
+
+/// Base style of the output pane and overlays.
+fn base_style() -> Style {
+    styled(Style::default().fg(Color::Gray).bg(Color::Black))
+}
+
+/// Style of the key names in the instructions and help overlay.
+fn key_style() -> Style {
+    styled(
+        Style::default()
+            .fg(Color::Blue)
+            .add_modifier(Modifier::BOLD),
+    )
+}
+
+/// Style of the matches of the search, the current one standing out.
+fn match_style(current: bool) -> Style {
+    let style = if current {
+        Style::new().bold()
+    } else {
+        Style::new()
+    };
+    if !colors_enabled() {
+        return style.reversed();
+    }
+    let bg = if current {
+        Color::LightGreen
+    } else {
+        Color::Yellow
+    };
+    style.fg(Color::Black).bg(bg)
+}
+
+/// Style of the URLs of messages.
+fn link_style() -> Style {
+    let style = Style::new().underlined();
+    if colors_enabled() {
+        style.fg(Color::LightBlue)
+    } else {
+        style
+    }
+}
+
+/// Builds the lines of the output pane, borrowing the text of the entries
+/// rather than copying it on every frame.
+///
+/// The matches of `search` are highlighted, and the index of the line of
+/// every match returned along with the lines.
+fn render_text<'a>(
+    buffer: &'a mut [BufferedOutput],
+    selected: Option<usize>,
+    search: Option<&mut Search>,
+) -> (Vec<Line<'a>>, Vec<usize>) {
+    let mut lines: Vec<Line<'a>> = buffer
+        .iter_mut()
+        .enumerate()
+        .flat_map(|(idx, entry)| {
+            let links = entry.has_links();
+            let mut res: Vec<Line<'a>> = entry
+                .lines()
+                .iter()
+                .map(borrow_line)
+                .map(|line| if links { highlight_urls(line) } else { line })
+                .collect();
+            if selected == Some(idx) {
+                if let Some(prompt) = res.first_mut() {
+                    *prompt = std::mem::take(prompt).patch_style(Modifier::REVERSED);
+                }
+            }
             res.push(Line::default());
             res
         })
-        .flatten()
+        .collect();
+    let Some(search) = search else {
+        return (lines, Vec::new());
+    };
+
+    let found: Vec<Vec<Range<usize>>> = lines
+        .iter()
+        .map(|line| find_matches(line, search))
+        .collect();
+    search.matches = found.iter().map(Vec::len).sum();
+    search.current = search.current.min(search.matches.saturating_sub(1));
+    let mut match_lines = Vec::with_capacity(search.matches);
+    for (idx, (line, ranges)) in lines.iter_mut().zip(found).enumerate() {
+        if ranges.is_empty() {
+            continue;
+        }
+        let first = match_lines.len();
+        match_lines.extend(std::iter::repeat_n(idx, ranges.len()));
+        let current = search.current.checked_sub(first);
+        *line = patch_ranges(std::mem::take(line), &ranges, |m| {
+            match_style(Some(m) == current)
+        });
+    }
+    (lines, match_lines)
+}
+
+/// The character ranges of `line` matching the search query, without
+/// overlapping each other.
+fn find_matches(line: &Line<'_>, search: &Search) -> Vec<Range<usize>> {
+    let fold = |c: char| {
+        if search.case_sensitive {
+            c
+        } else {
+            c.to_lowercase().next().unwrap_or(c)
+        }
+    };
+    let query: Vec<char> = search.query.chars().map(fold).collect();
+    let chars: Vec<char> = line
+        .spans
+        .iter()
+        .flat_map(|span| span.content.chars())
+        .map(fold)
+        .collect();
+    let mut ranges = Vec::new();
+    if query.is_empty() {
+        return ranges;
+    }
+    let mut start = 0;
+    while start + query.len() <= chars.len() {
+        if chars[start..start + query.len()] == query[..] {
+            ranges.push(start..start + query.len());
+            start += query.len();
+        } else {
+            start += 1;
+        }
+    }
+    ranges
+}
+
+/// Splits the spans of `line` to patch the characters in each of `ranges`
+/// with the style `patch` gives for the index of the range.
+fn patch_ranges<'a>(
+    line: Line<'a>,
+    ranges: &[Range<usize>],
+    patch: impl Fn(usize) -> Style,
+) -> Line<'a> {
+    let piece_span = |text: String, style: Style, range: Option<usize>| match range {
+        Some(range) => Span::styled(text, style.patch(patch(range))),
+        None => Span::styled(text, style),
+    };
+    let mut spans: Vec<Span<'a>> = Vec::new();
+    let mut offset = 0;
+    for span in line.spans {
+        let mut piece = String::new();
+        let mut piece_range = None;
+        for c in span.content.chars() {
+            let range = ranges.iter().position(|range| range.contains(&offset));
+            if range != piece_range && !piece.is_empty() {
+                spans.push(piece_span(
+                    std::mem::take(&mut piece),
+                    span.style,
+                    piece_range,
+                ));
+            }
+            piece_range = range;
+            piece.push(c);
+            offset += 1;
+        }
+        if !piece.is_empty() {
+            spans.push(piece_span(piece, span.style, piece_range));
+        }
+    }
+    Line {
+        spans,
+        style: line.style,
+        alignment: line.alignment,
+    }
+}
+
+/// Underlines the URLs of `line`.
+fn highlight_urls(line: Line<'_>) -> Line<'_> {
+    let text: String = line
+        .spans
+        .iter()
+        .map(|span| span.content.as_ref())
+        .collect();
+    let urls = links::find_urls(&text);
+    if urls.is_empty() {
+        return line;
+    }
+    // Spans are split by character, while URLs are found by byte.
+    let chars = |idx: usize| text[..idx].chars().count();
+    let ranges: Vec<Range<usize>> = urls
+        .into_iter()
+        .map(|url| chars(url.start)..chars(url.end))
+        .collect();
+    patch_ranges(line, &ranges, |_| link_style())
+}
+
+/// A copy of `line` whose spans borrow their text from it.
+fn borrow_line<'a>(line: &'a Line<'_>) -> Line<'a> {
+    Line {
+        spans: line
+            .spans
+            .iter()
+            .map(|span| Span::styled(span.content.as_ref(), span.style))
+            .collect(),
+        style: line.style,
+        alignment: line.alignment,
+    }
+}
+
+/// Prefixes every line with its right-aligned, 1-based line number.
+///
+/// The gutter width is sized to the total line count so that it stays
+/// constant for the whole buffer.
+fn add_line_numbers(lines: Vec<Line<'_>>) -> Vec<Line<'_>> {
+    let width = lines.len().to_string().len();
+    lines
+        .into_iter()
+        .enumerate()
+        .map(|(idx, mut line)| {
+            line.spans.insert(
+                0,
+                Span::styled(
+                    format!("{:>width$} ", idx + 1),
+                    styled(Style::default().fg(Color::DarkGray)),
+                ),
+            );
+            line
+        })
+        .collect()
+}
+
+/// Soft-wraps `line` into rows of at most `width` columns, breaking after
+/// whitespace when possible.
+///
+/// Every row but the last ends with [`WRAP_MARKER`], and continuation rows
+/// are indented by `indent` columns to keep clear of the line number gutter.
+fn wrap_line(line: Line<'_>, width: usize, indent: usize) -> Vec<Line<'static>> {
+    let cells: Vec<(char, Style, usize)> = line
+        .spans
+        .iter()
+        .flat_map(|span| {
+            span.content
+                .chars()
+                .map(move |c| (c, span.style, c.width().unwrap_or(0)))
+        })
+        .collect();
+    let mut remaining: usize = cells.iter().map(|cell| cell.2).sum();
+    let mut rows = Vec::new();
+    let mut start = 0;
+    // An empty line still takes a row.
+    while rows.is_empty() || start < cells.len() {
+        let available = if rows.is_empty() {
+            width
+        } else {
+            width.saturating_sub(indent)
+        };
+        if remaining <= available || available <= 1 {
+            rows.push(start..cells.len());
+            break;
+        }
+        // Leave room for the marker, and never break inside the gutter.
+        let (mut end, mut used, mut after_space) = (start, 0, None);
+        while end < cells.len() && used + cells[end].2 < available {
+            used += cells[end].2;
+            end += 1;
+            if end > indent && cells[end - 1].0.is_whitespace() {
+                after_space = Some(end);
+            }
+        }
+        let end = after_space.unwrap_or(end.max(start + 1));
+        remaining -= cells[start..end].iter().map(|cell| cell.2).sum::<usize>();
+        rows.push(start..end);
+        start = end;
+    }
+
+    let last = rows.len().saturating_sub(1);
+    rows.into_iter()
+        .enumerate()
+        .map(|(idx, range)| {
+            let mut spans: Vec<Span<'static>> = Vec::new();
+            for &(c, style, _) in &cells[range] {
+                match spans.last_mut() {
+                    Some(span) if span.style == style => span.content.to_mut().push(c),
+                    _ => spans.push(Span::styled(c.to_string(), style)),
+                }
+            }
+            if idx > 0 && indent > 0 {
+                spans.insert(0, Span::raw(" ".repeat(indent)));
+            }
+            if idx < last {
+                spans.push(Span::styled(
+                    WRAP_MARKER,
+                    styled(Style::default().fg(Color::DarkGray)),
+                ));
+            }
+            let mut row = Line::from(spans).style(line.style);
+            row.alignment = line.alignment;
+            row
+        })
         .collect()
 }
 
-fn render_block(app_status: Span<'static>) -> Block<'static> {
-    let title = Line::from(" AutoPilot ".bold());
-    let instructions = Line::from(vec![
-        app_status.into(),
-        "━━━━━━━━━━━━━━".into(),
+fn render_title(app: &App) -> Line<'static> {
+    match &app.border_title {
+        Some(title) => Line::from(Span::styled(
+            format!(" {} ", title),
+            styled(Style::new().bold()),
+        )),
+        None => Line::default(),
+    }
+}
+
+/// The lines of the main border, in the style configured.
+fn line_set(app: &App) -> symbols::line::Set {
+    match app.border {
+        BorderStyle::Thick => symbols::line::THICK,
+        BorderStyle::Rounded => symbols::line::ROUNDED,
+        BorderStyle::Plain => symbols::line::NORMAL,
+    }
+}
+
+/// The border of the main panes, in the style configured.
+fn border_set(app: &App) -> border::Set {
+    match app.border {
+        BorderStyle::Thick => border::THICK,
+        BorderStyle::Rounded => border::ROUNDED,
+        BorderStyle::Plain => border::PLAIN,
+    }
+}
+
+fn render_block(app: &App) -> Block<'static> {
+    let title = render_title(app);
+    let block = Block::bordered()
+        .title(title.centered())
+        .border_set(border_set(app))
+        .padding(Padding::horizontal(1));
+    if !app.show_instructions {
+        return block;
+    }
+
+    // Runs of the bottom border between the parts of the instructions.
+    let separator = line_set(app).horizontal.repeat(2);
+    let progress = app.progress();
+    let mut instructions = vec![app.status(), separator.clone().into()];
+    if let Some(search) = app.search.as_ref().filter(|_| app.mode != Mode::Search) {
+        let found = if search.matches == 0 {
+            String::from("no matches")
+        } else {
+            format!("{}/{}", search.current + 1, search.matches)
+        };
+        instructions.push(format!(" /{} · {} ", search.query, found).into());
+        instructions.push(separator.clone().into());
+    }
+    instructions.push(
+        format!(
+            " Stage {}/{} · Action {}/{} ",
+            progress.stage, progress.stages, progress.action, progress.actions
+        )
+        .into(),
+    );
+    if let Some(total) = app.total.filter(|_| app.show_timing) {
+        let secs = total.as_secs();
+        instructions
+            .push(format!("· {}:{:02}:{:02} ", secs / 3600, secs / 60 % 60, secs % 60).into());
+    }
+    instructions.extend([
+        separator.into(),
         " Next ".into(),
-        "<Left>".blue().bold(),
+        Span::styled(keys_label(app, &[KeyAction::Next]), key_style()),
         " Prev ".into(),
-        "<Right>".blue().bold(),
+        Span::styled(keys_label(app, &[KeyAction::Prev]), key_style()),
         " Scroll ".into(),
-        "<Up/Down>".blue().bold(),
+        Span::styled(
+            keys_label(app, &[KeyAction::ScrollUp, KeyAction::ScrollDown]),
+            key_style(),
+        ),
+        " Help ".into(),
+        Span::styled(keys_label(app, &[KeyAction::Help]), key_style()),
         " Quit ".into(),
-        "<Q> ".blue().bold(),
+        Span::styled(keys_label(app, &[KeyAction::Quit]) + " ", key_style()),
     ]);
-    Block::bordered()
-        .title(title.centered())
-        .title_bottom(instructions.centered())
-        .border_set(border::THICK)
-        .padding(Padding::horizontal(1))
+    block.title_bottom(Line::from(instructions).centered())
+}
+
+/// Draws the overall completion over the top border, keeping the title on top.
+fn render_gauge(app: &App, frame: &mut Frame, area: Rect) {
+    let progress = app.progress();
+    let ratio = if progress.total == 0 {
+        1.0
+    } else {
+        progress.done as f64 / progress.total as f64
+    };
+    let area = Rect {
+        x: area.x + 1,
+        y: area.y,
+        width: area.width.saturating_sub(2),
+        height: 1,
+    };
+    frame.render_widget(
+        LineGauge::default()
+            .label("")
+            .line_set(line_set(app))
+            .filled_style(styled(Style::default().fg(Color::LightGreen)))
+            .ratio(ratio),
+        area,
+    );
+    frame.render_widget(render_title(app).centered(), area);
+}
+
+/// Splits `area` into the outline pane, if shown, and the output pane. The
+/// output takes the whole area on narrow terminals.
+fn split_outline(app: &App, area: Rect) -> (Option<Rect>, Rect) {
+    if !app.show_outline || area.width < OUTLINE_MIN_WIDTH {
+        return (None, area);
+    }
+    let width = (area.width / 4).clamp(24, 40);
+    let [outline, output] =
+        Layout::horizontal([Constraint::Length(width), Constraint::Min(0)]).areas(area);
+    (Some(outline), output)
+}
+
+/// Splits the bottom of `area` off for the checks of the remote hosts, if
+/// any are shown, taking up to a third of it.
+fn split_preflight(checks: &[PreflightCheck], area: Rect) -> (Option<Rect>, Rect) {
+    if checks.is_empty() {
+        return (None, area);
+    }
+    let height = (checks.len() as u16 + 2).min((area.height / 3).max(3));
+    let [output, preflight] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(height)]).areas(area);
+    (Some(preflight), output)
+}
+
+/// Lists the remote hosts of the stage being checked, and why those that
+/// failed did.
+fn render_preflight(app: &App, checks: &[PreflightCheck], frame: &mut Frame, area: Rect) {
+    let lines: Vec<Line> = checks
+        .iter()
+        .map(|check| {
+            let (text, color) = match &check.outcome {
+                PreflightOutcome::Checking => (format!("… {}", check.label), Color::DarkGray),
+                PreflightOutcome::Passed => (format!("✔ {}", check.label), Color::LightGreen),
+                PreflightOutcome::Failed(reason) => {
+                    (format!("✘ {}: {}", check.label, reason), Color::LightRed)
+                }
+            };
+            Line::from(Span::styled(text, styled(Style::new().fg(color))))
+        })
+        .collect();
+    let mut block = Block::bordered()
+        .title(Line::from(Span::styled(" Preflight ", styled(Style::new().bold()))).centered())
+        .border_set(border_set(app))
+        .padding(Padding::horizontal(1));
+    if checks
+        .iter()
+        .all(|check| check.outcome != PreflightOutcome::Checking)
+    {
+        block = block.title_bottom(
+            Line::from(vec![
+                " Dismiss ".into(),
+                Span::styled(keys_label(app, &[KeyAction::Clear]) + " ", key_style()),
+            ])
+            .centered(),
+        );
+    }
+    frame.render_widget(Paragraph::new(lines).block(block).style(base_style()), area);
+}
+
+/// Lists the stages and their actions, the action last run highlighted and
+/// those before it dimmed, scrolled to keep it in view.
+fn render_outline(app: &App, frame: &mut Frame, area: Rect) {
+    let progress = app.progress();
+    let shown = (progress.stage - 1, progress.action);
+    let current = progress
+        .action
+        .checked_sub(1)
+        .map(|action| (shown.0, action));
+    let mut lines = Vec::new();
+    let mut current_line = 0;
+    for (stage_idx, (name, actions)) in app.outline().into_iter().enumerate() {
+        let style = if stage_idx == shown.0 {
+            Style::new().bold()
+        } else {
+            Style::new()
+        };
+        lines.push(Line::from(Span::styled(
+            format!("{}. {}", stage_idx + 1, name),
+            styled(style),
+        )));
+        for (action_idx, summary) in actions.into_iter().enumerate() {
+            let position = (stage_idx, action_idx);
+            let style = if Some(position) == current {
+                current_line = lines.len();
+                Style::new().add_modifier(Modifier::REVERSED)
+            } else if position < shown {
+                Style::new().fg(Color::DarkGray)
+            } else {
+                Style::new()
+            };
+            lines.push(Line::from(Span::styled(
+                format!("  {}", summary),
+                styled(style),
+            )));
+        }
+    }
+    let height = area.height.saturating_sub(2) as usize;
+    let scroll = current_line
+        .saturating_sub(height / 2)
+        .min(lines.len().saturating_sub(height));
+    frame.render_widget(
+        Paragraph::new(lines)
+            .block(
+                Block::bordered()
+                    .title(
+                        Line::from(Span::styled(" Outline ", styled(Style::new().bold())))
+                            .centered(),
+                    )
+                    .border_set(border_set(app))
+                    .padding(Padding::horizontal(1)),
+            )
+            .style(base_style())
+            .scroll((scroll as u16, 0)),
+        area,
+    );
+}
+
+/// Returns a rectangle of the given size centered inside `area`.
+fn popup_area(area: Rect, width: u16, height: u16) -> Rect {
+    let [area] = Layout::vertical([Constraint::Length(height)])
+        .flex(Flex::Center)
+        .areas(area);
+    let [area] = Layout::horizontal([Constraint::Length(width)])
+        .flex(Flex::Center)
+        .areas(area);
+    area
+}
+
+/// The keys bound to `actions`, as shown in the interface.
+fn keys_label(app: &App, actions: &[KeyAction]) -> String {
+    let labels: Vec<String> = actions
+        .iter()
+        .map(|&action| app.keymap.label(action))
+        .collect();
+    format!("<{}>", labels.join("/"))
+}
+
+fn render_help(app: &App, frame: &mut Frame) {
+    let keys: Vec<String> = KEYBINDINGS
+        .iter()
+        .map(|(actions, _)| match actions {
+            // Digits not bound to an action jump to a stage.
+            [] => String::from("<1-9>"),
+            actions => keys_label(app, actions),
+        })
+        .collect();
+    let key_width = keys
+        .iter()
+        .map(|key| key.chars().count())
+        .max()
+        .unwrap_or(0);
+    let lines: Vec<Line> = KEYBINDINGS
+        .iter()
+        .zip(&keys)
+        .map(|((_, description), key)| {
+            Line::from(vec![
+                Span::styled(format!("{:<key_width$}  ", key), key_style()),
+                Span::raw(*description),
+            ])
+        })
+        .collect();
+    let width = lines.iter().map(Line::width).max().unwrap_or(0) as u16 + 4;
+    let height = lines.len() as u16 + 2;
+    let area = popup_area(frame.area(), width, height);
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .block(
+                Block::bordered()
+                    .title(
+                        Line::from(Span::styled(" Keybindings ", styled(Style::new().bold())))
+                            .centered(),
+                    )
+                    .border_set(border::ROUNDED)
+                    .padding(Padding::horizontal(1)),
+            )
+            .style(base_style()),
+        area,
+    );
+}
+
+fn render_contents(app: &mut App, frame: &mut Frame) {
+    let names: Vec<String> = app
+        .stage_names()
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| format!("{}. {}", idx + 1, name))
+        .collect();
+    let width = names
+        .iter()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(30) as u16
+        + 6;
+    let height = (names.len() as u16 + 2).min(frame.area().height);
+    let area = popup_area(frame.area(), width, height);
+    frame.render_widget(Clear, area);
+    frame.render_stateful_widget(
+        List::new(names)
+            .block(
+                Block::bordered()
+                    .title(
+                        Line::from(Span::styled(" Contents ", styled(Style::new().bold())))
+                            .centered(),
+                    )
+                    .title_bottom(
+                        Line::from(vec![
+                            " Jump ".into(),
+                            Span::styled("<Enter>", key_style()),
+                            " Cancel ".into(),
+                            Span::styled("<Esc> ", key_style()),
+                        ])
+                        .centered(),
+                    )
+                    .border_set(border::ROUNDED)
+                    .padding(Padding::horizontal(1)),
+            )
+            .highlight_symbol("> ")
+            .highlight_style(styled(Style::new().reversed()))
+            .style(base_style()),
+        area,
+        &mut app.contents,
+    );
+}
+
+fn render_splash(app: &App, frame: &mut Frame) {
+    let (stages, actions, hosts) = app.overview();
+    let mut lines = vec![
+        Line::from(Span::styled(app.title.clone(), styled(Style::new().bold()))).centered(),
+        Line::default(),
+        Line::from(format!("{} stages, {} actions", stages, actions)),
+        Line::default(),
+    ];
+    if hosts.is_empty() {
+        lines.push(Line::from("All commands run locally."));
+    } else {
+        lines.push(Line::from("Remote hosts:"));
+        lines.extend(
+            hosts
+                .into_iter()
+                .map(|host| Line::from(format!("  {}", host))),
+        );
+    }
+    lines.push(Line::default());
+    lines.push(
+        Line::from(vec![
+            "Press ".into(),
+            Span::styled(keys_label(app, &[KeyAction::Next]), key_style()),
+            " to start".into(),
+        ])
+        .centered(),
+    );
+    let width = lines.iter().map(Line::width).max().unwrap_or(0) as u16 + 4;
+    let height = lines.len() as u16 + 2;
+    let area = popup_area(frame.area(), width, height);
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .block(
+                Block::bordered()
+                    .border_set(border::ROUNDED)
+                    .padding(Padding::horizontal(1)),
+            )
+            .style(base_style()),
+        area,
+    );
+}
+
+fn render_prompt(app: &App, frame: &mut Frame) {
+    let code = app
+        .failure()
+        .and_then(|failure| failure.exit_code)
+        .map_or_else(|| String::from("none"), |c| c.to_string());
+    let lines = vec![
+        Line::from(format!("The command failed with exit code {}.", code)),
+        Line::default(),
+        Line::from(vec![
+            "Proceed to the next action? ".into(),
+            Span::styled("<Y>", key_style()),
+            "es / ".into(),
+            Span::styled("<N>", key_style()),
+            "o".into(),
+        ])
+        .centered(),
+    ];
+    let width = lines.iter().map(Line::width).max().unwrap_or(0) as u16 + 4;
+    let height = lines.len() as u16 + 2;
+    let area = popup_area(frame.area(), width, height);
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .block(
+                Block::bordered()
+                    .title(
+                        Line::from(Span::styled(" Error ", styled(Style::new().bold()))).centered(),
+                    )
+                    .border_set(border::ROUNDED)
+                    .border_style(styled(Style::new().fg(Color::Red)))
+                    .padding(Padding::horizontal(1)),
+            )
+            .style(base_style()),
+        area,
+    );
+}
+
+fn render_confirm(app: &App, frame: &mut Frame) {
+    let Some(confirmation) = &app.confirmation else {
+        return;
+    };
+    let question = confirmation
+        .prompt
+        .as_deref()
+        .unwrap_or("This command needs to be confirmed:");
+    let mut lines: Vec<Line> = question
+        .lines()
+        .map(|line| Line::from(line.to_owned()))
+        .collect();
+    lines.push(Line::default());
+    lines.extend(confirmation.command.lines().map(|line| {
+        Line::from(Span::styled(
+            format!("$ {}", line),
+            styled(Style::new().bold()),
+        ))
+    }));
+    lines.extend([
+        Line::default(),
+        Line::from(vec![
+            "Run it? ".into(),
+            Span::styled("<Y>", key_style()),
+            "es / ".into(),
+            Span::styled("<N>", key_style()),
+            "o, skip it".into(),
+        ])
+        .centered(),
+    ]);
+    let width = lines.iter().map(Line::width).max().unwrap_or(0) as u16 + 4;
+    let height = lines.len() as u16 + 2;
+    let area = popup_area(frame.area(), width.min(frame.area().width), height);
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .block(
+                Block::bordered()
+                    .title(
+                        Line::from(Span::styled(" Confirm ", styled(Style::new().bold())))
+                            .centered(),
+                    )
+                    .border_set(border::ROUNDED)
+                    .border_style(styled(Style::new().fg(Color::Yellow)))
+                    .padding(Padding::horizontal(1)),
+            )
+            .style(base_style()),
+        area,
+    );
+}
+
+fn render_password(app: &App, frame: &mut Frame) {
+    let Some(prompt) = &app.password_prompt else {
+        return;
+    };
+    let masked = format!("{}_", "•".repeat(prompt.input.chars().count()));
+    let (title, mut lines) = match &prompt.question {
+        // A question of a keyboard-interactive server.
+        Some(question) => {
+            let mut lines = vec![Line::from(format!("Authentication for {}", question.label))];
+            if !question.instructions.trim().is_empty() {
+                lines.extend(
+                    question
+                        .instructions
+                        .lines()
+                        .map(|line| Line::from(line.to_owned())),
+                );
+            }
+            lines.push(Line::from(question.text.trim_end().to_owned()));
+            if question.echo {
+                lines.push(Line::from(format!("{}_", prompt.input)));
+            } else {
+                lines.push(Line::from(masked));
+            }
+            (" Authentication ", lines)
+        }
+        None => (
+            " Password ",
+            vec![
+                Line::from(format!("Password for {}:", prompt.label)),
+                Line::from(masked),
+            ],
+        ),
+    };
+    lines.extend([
+        Line::default(),
+        Line::from(vec![
+            Span::styled("<Enter>", key_style()),
+            " Confirm ".into(),
+            Span::styled("<Esc>", key_style()),
+            " Cancel".into(),
+        ])
+        .centered(),
+    ]);
+    // Leave room for the password to grow.
+    let width = lines.iter().map(Line::width).max().unwrap_or(0).max(40) as u16 + 4;
+    let height = lines.len() as u16 + 2;
+    let area = popup_area(frame.area(), width, height);
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .block(
+                Block::bordered()
+                    .title(Line::from(Span::styled(title, styled(Style::new().bold()))).centered())
+                    .border_set(border::ROUNDED)
+                    .padding(Padding::horizontal(1)),
+            )
+            .style(base_style()),
+        area,
+    );
+}
+
+fn render_search(app: &App, frame: &mut Frame) {
+    let Some(search) = &app.search else {
+        return;
+    };
+    let case = if search.case_sensitive { "on" } else { "off" };
+    let lines = vec![
+        Line::from(format!("/{}_", search.query)),
+        Line::default(),
+        Line::from(vec![
+            Span::styled("<Enter>", key_style()),
+            " Find ".into(),
+            Span::styled("<Tab>", key_style()),
+            format!(" Match case: {} ", case).into(),
+            Span::styled("<Esc>", key_style()),
+            " Cancel".into(),
+        ])
+        .centered(),
+    ];
+    // Leave room for the query to grow.
+    let width = lines.iter().map(Line::width).max().unwrap_or(0).max(40) as u16 + 4;
+    let height = lines.len() as u16 + 2;
+    let area = popup_area(frame.area(), width, height);
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .block(
+                Block::bordered()
+                    .title(
+                        Line::from(Span::styled(" Search ", styled(Style::new().bold())))
+                            .centered(),
+                    )
+                    .border_set(border::ROUNDED)
+                    .padding(Padding::horizontal(1)),
+            )
+            .style(base_style()),
+        area,
+    );
+}
+
+/// Seconds each upcoming stage is advertised on the attract screen.
+const ATTRACT_TIP_SECS: u64 = 3;
+
+fn render_attract(app: &App, frame: &mut Frame) {
+    let elapsed = app.attract_elapsed().as_secs();
+    let stages = app.stage_names();
+    let tip = stages[(elapsed / ATTRACT_TIP_SECS) as usize % stages.len()];
+    let prompt = Line::from(vec![
+        "Press ".into(),
+        Span::styled("any key", key_style()),
+        " to start".into(),
+    ]);
+    // Size the box for the longest tip and the prompt, so that it stays
+    // still while they change.
+    let tip_width = stages
+        .iter()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let width = app
+        .title
+        .chars()
+        .count()
+        .max("Coming up: ".len() + tip_width)
+        .max(prompt.width()) as u16
+        + 4;
+    let lines = vec![
+        Line::from(Span::styled(app.title.clone(), styled(Style::new().bold()))),
+        Line::default(),
+        Line::from(format!("Coming up: {}", tip)),
+        Line::default(),
+        if elapsed.is_multiple_of(2) {
+            prompt
+        } else {
+            Line::default()
+        },
+    ];
+    let height = lines.len() as u16 + 2;
+    frame.render_widget(Clear, frame.area());
+    frame.render_widget(Block::new().style(base_style()), frame.area());
+    frame.render_widget(
+        Paragraph::new(lines)
+            .centered()
+            .block(
+                Block::bordered()
+                    .border_set(border::DOUBLE)
+                    .padding(Padding::horizontal(1)),
+            )
+            .style(base_style()),
+        popup_area(frame.area(), width, height),
+    );
 }
 
 /// Renders the user interface widgets.
@@ -54,29 +995,81 @@ pub fn render(app: &mut App, frame: &mut Frame) {
     // See the following resources:
     // - https://docs.rs/ratatui/latest/ratatui/widgets/index.html
     // - https://github.com/ratatui/ratatui/tree/master/examples
-    let area = frame.area();
-    let text = render_text(app);
-    let total_lines = text.len() as u16;
-    let position = total_lines.saturating_sub(app.scroll);
-    let vertical_scroll = if position > area.height {
-        position.saturating_sub(area.height) + 1
-    } else {
-        0
+    let (outline, area) = split_outline(app, frame.area());
+    if let Some(outline) = outline {
+        render_outline(app, frame, outline);
+    }
+    let checks = app.preflight_checks();
+    let (preflight, area) = split_preflight(&checks, area);
+    if let Some(preflight) = preflight {
+        render_preflight(app, &checks, frame, preflight);
+    }
+    let buffer = app.buffer.clone();
+    let mut buffer = buffer.lock().unwrap();
+    let (mut text, match_lines) = render_text(&mut buffer, app.selected, app.search.as_mut());
+    // Line of the match the view has to be scrolled to, if any.
+    let jump_line = match app.search.as_mut() {
+        Some(search) if search.jump => {
+            search.jump = false;
+            match_lines.get(search.current).copied()
+        }
+        _ => None,
     };
+    let mut jump_row = jump_line;
+    if app.show_line_numbers {
+        // The gutter only widens lines, so the vertical scroll math below
+        // is unaffected by it.
+        text = add_line_numbers(text);
+    }
+    if app.wrap {
+        // Borders and padding take two columns on each side.
+        let width = area.width.saturating_sub(4) as usize;
+        let indent = if app.show_line_numbers {
+            text.len().to_string().len() + 1
+        } else {
+            0
+        };
+        let mut rows = Vec::with_capacity(text.len());
+        for (idx, line) in text.into_iter().enumerate() {
+            if jump_line == Some(idx) {
+                jump_row = Some(rows.len());
+            }
+            rows.extend(wrap_line(line, width, indent));
+        }
+        text = rows;
+    }
+    let total_lines = text.len() as u16;
+    // Borders take a row at the top and at the bottom.
+    let height = area.height.saturating_sub(2);
+    let max_scroll = total_lines.saturating_sub(height);
+    if let Some(row) = jump_row {
+        // Bring the match to the middle of the pane, as far as it scrolls.
+        let top = (row as u16).saturating_sub(height / 2).min(max_scroll);
+        app.scroll = max_scroll - top;
+    }
+    let scroll = app.scroll.min(max_scroll);
+    let vertical_scroll = max_scroll - scroll;
     frame.render_widget(
         Paragraph::new(text)
-            .block(render_block(app.status()))
-            .style(Style::default().fg(Color::Gray).bg(Color::Black))
+            .block(render_block(app))
+            .style(base_style())
             .scroll((vertical_scroll, 0)),
         area,
     );
+    drop(buffer);
+    // Scrolling past the top is forgotten, so scrolling back is immediate.
+    app.scroll = scroll;
+
+    if app.show_instructions {
+        render_gauge(app, frame, area);
+    }
 
-    if total_lines > area.height {
+    if max_scroll > 0 {
         let scrollbar = Scrollbar::new(ScrollbarOrientation::VerticalRight)
             .begin_symbol(Some("↑"))
             .end_symbol(Some("↓"));
         let mut scrollbar_state =
-            ScrollbarState::new(total_lines as usize).position(position as usize);
+            ScrollbarState::new(max_scroll as usize + 1).position(vertical_scroll as usize);
         frame.render_stateful_widget(
             scrollbar,
             area.inner(Margin {
@@ -86,4 +1079,16 @@ pub fn render(app: &mut App, frame: &mut Frame) {
             &mut scrollbar_state,
         );
     }
+
+    match app.mode {
+        Mode::Splash => render_splash(app, frame),
+        Mode::Attract => render_attract(app, frame),
+        Mode::Prompt => render_prompt(app, frame),
+        Mode::Password => render_password(app, frame),
+        Mode::Confirm => render_confirm(app, frame),
+        Mode::Help => render_help(app, frame),
+        Mode::Contents => render_contents(app, frame),
+        Mode::Search => render_search(app, frame),
+        Mode::Normal => {}
+    }
 }
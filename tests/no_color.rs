@@ -0,0 +1,23 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Styles with colors disabled, as `--no-color` does. Colors can only be
+//! disabled once per process, hence a test binary of their own.
+
+use autopilot::{color, config::StyleConfig};
+use ratatui::style::Style;
+
+#[test]
+fn styled_messages_are_plain() {
+    color::disable();
+    let style = StyleConfig {
+        color: Some(String::from("red")),
+        background: Some(String::from("#202020")),
+        bold: Some(true),
+        italic: Some(true),
+        underline: Some(true),
+        ..Default::default()
+    };
+    assert_eq!(Style::from(style), Style::default());
+}
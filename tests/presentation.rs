@@ -0,0 +1,431 @@
+// SPDX-FileCopyrightText: 2025 Albert Esteve <aesteve@redhat.com>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Drives a presentation through its keys, as a presenter would, checking
+//! the output and the state of the actions along the way.
+
+use autopilot::{
+    transcript::{Transcript, TranscriptFormat},
+    ActionStatus, App, Config, Mode,
+};
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{backend::TestBackend, text::Line, Terminal};
+use std::{
+    env, fs,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Longest an action may take before the test gives up on it.
+const ACTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn load(fixture: &str) -> App {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(fixture);
+    let config = Config::load_config(&path, &[], None).expect("fixture is valid");
+    let mut app = App::new(config);
+    // Messages are written at once.
+    app.speed_multiplier = 0.0;
+    app
+}
+
+fn press(app: &mut App, code: KeyCode) {
+    app.handle_events(KeyEvent::from(code))
+        .expect("key is handled");
+}
+
+/// Ticks the application until the action started last is done.
+fn wait_idle(app: &mut App) {
+    let start = Instant::now();
+    loop {
+        app.tick().expect("tick succeeds");
+        if matches!(
+            app.action_status(),
+            ActionStatus::Stopped | ActionStatus::Waiting
+        ) {
+            return;
+        }
+        assert!(start.elapsed() < ACTION_TIMEOUT, "action did not finish");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Moves to the next action and waits for it to finish.
+fn next(app: &mut App) {
+    press(app, KeyCode::Right);
+    wait_idle(app);
+}
+
+/// The rows of the screen, as drawn on a terminal of 120x24.
+fn draw(app: &mut App) -> Vec<String> {
+    draw_sized(app, 120, 24)
+}
+
+/// The rows of the screen, as drawn on a terminal of `width`x`height`.
+fn draw_sized(app: &mut App, width: u16, height: u16) -> Vec<String> {
+    let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+    terminal
+        .draw(|frame| autopilot::render(app, frame))
+        .unwrap();
+    let buffer = terminal.backend().buffer();
+    (0..buffer.area.height)
+        .map(|y| {
+            (0..buffer.area.width)
+                .map(|x| buffer[(x, y)].symbol())
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn runs_the_actions_in_turn() {
+    let mut app = load("presentation.yaml");
+    assert_eq!(app.action_status(), ActionStatus::Stopped);
+
+    next(&mut app);
+    let output = app.output();
+    assert_eq!(output[0], "### Basics ###");
+    assert!(output[1].contains("Hello from the harness"));
+
+    press(&mut app, KeyCode::Right);
+    assert_eq!(app.action_status(), ActionStatus::Running);
+    wait_idle(&mut app);
+    assert_eq!(app.action_status(), ActionStatus::Stopped);
+    let output = app.output();
+    assert_eq!(output.len(), 3);
+    assert!(output[2].contains("$ echo local output\n"));
+    assert!(output[2].contains("\nlocal output\n"));
+
+    next(&mut app);
+    let output = app.output();
+    for iteration in 0..3 {
+        assert!(output[3].contains(&format!("iteration {}\n", iteration)));
+    }
+
+    // The stage title is kept.
+    next(&mut app);
+    assert_eq!(app.output(), ["### Basics ###"]);
+
+    next(&mut app);
+    let output = app.output();
+    assert_eq!(output.len(), 2);
+    assert!(output[1].contains("\nafter clear\n"));
+    assert!(app.summary().finished);
+}
+
+#[test]
+fn loops_see_their_iteration() {
+    let mut app = load("presentation.yaml");
+    for _ in 0..3 {
+        next(&mut app);
+    }
+    let output = app.output();
+    let iterations: Vec<&str> = output[3]
+        .lines()
+        .filter(|line| line.starts_with("iteration"))
+        .collect();
+    assert_eq!(iterations, ["iteration 0", "iteration 1", "iteration 2"]);
+}
+
+#[test]
+fn only_scripts_run_multiline_loops() {
+    let mut app = load("join.yaml");
+    // Joined with `&&`, the lines of the loop are not valid shell.
+    next(&mut app);
+    let output = app.output();
+    assert!(output[1].to_lowercase().contains("syntax error"));
+    assert!(!output[1].contains("loop 1"));
+
+    next(&mut app);
+    let output = app.output();
+    assert!(output[2].contains("loop 1\nloop 2\nloop 3\n"));
+}
+
+#[test]
+fn quitting_stops_the_commands() {
+    let mut app = load("long_loop.yaml");
+    press(&mut app, KeyCode::Right);
+    let start = Instant::now();
+    while !app.output().concat().contains("tick 1\n") {
+        app.tick().expect("tick succeeds");
+        assert!(start.elapsed() < ACTION_TIMEOUT, "loop did not start");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    press(&mut app, KeyCode::Char('q'));
+    assert!(!app.running);
+    let output = app.output();
+    assert!(output[1].contains("Command interrupted!\n"));
+    // Nothing is written once quit.
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(app.output(), output);
+}
+
+#[test]
+fn going_back_undoes_the_actions() {
+    let mut app = load("presentation.yaml");
+    for _ in 0..4 {
+        next(&mut app);
+    }
+    assert_eq!(app.output().len(), 1);
+
+    // Undoing the clear brings the output back.
+    press(&mut app, KeyCode::Left);
+    assert_eq!(app.output().len(), 4);
+
+    press(&mut app, KeyCode::Left);
+    press(&mut app, KeyCode::Left);
+    assert_eq!(app.output().len(), 2);
+    assert_eq!(app.progress().action, 1);
+}
+
+#[test]
+fn draws_the_output() {
+    let mut app = load("presentation.yaml");
+    next(&mut app);
+    next(&mut app);
+    let rows = draw(&mut app);
+    assert!(rows[0].contains("AutoPilot"));
+    assert!(rows.iter().any(|row| row.contains("### Basics ###")));
+    assert!(rows
+        .iter()
+        .any(|row| row.contains("> Hello from the harness")));
+    assert!(rows.iter().any(|row| row.contains("local output")));
+    assert!(rows[23].contains("Stage 1/1 · Action 2/5"));
+}
+
+#[test]
+fn going_back_undoes_every_entry_of_an_action() {
+    let mut app = load("navigation.yaml");
+    next(&mut app);
+    next(&mut app);
+    // The label is an entry of its own, above the command.
+    let output = app.output();
+    assert_eq!(output.len(), 4);
+    assert_eq!(output[2], "== Listing ==");
+
+    press(&mut app, KeyCode::Left);
+    assert_eq!(app.output(), output[..2]);
+    assert_eq!(app.progress().action, 1);
+
+    next(&mut app);
+    assert_eq!(app.output(), output);
+}
+
+#[test]
+fn scrolling_stops_at_the_top_of_the_output() {
+    let mut app = load("navigation.yaml");
+    next(&mut app);
+    next(&mut app);
+    let shows = |rows: &[String], text: &str| rows.iter().any(|row| row.contains(text));
+    assert!(shows(&draw(&mut app), "line 40"));
+
+    press(&mut app, KeyCode::Home);
+    let rows = draw(&mut app);
+    assert!(shows(&rows, "### Navigation ###"));
+    assert!(!shows(&rows, "line 40"));
+    let top = app.scroll;
+    assert!(top < u16::MAX);
+
+    // Scrolling past the top is forgotten.
+    press(&mut app, KeyCode::PageUp);
+    assert_eq!(draw(&mut app), rows);
+    assert_eq!(app.scroll, top);
+    press(&mut app, KeyCode::Down);
+    draw(&mut app);
+    assert_eq!(app.scroll, top - 1);
+
+    press(&mut app, KeyCode::End);
+    assert!(shows(&draw(&mut app), "line 40"));
+    assert_eq!(app.scroll, 0);
+}
+
+#[test]
+fn resizing_keeps_the_output_in_view() {
+    let mut app = load("navigation.yaml");
+    app.resize(120);
+    next(&mut app);
+    next(&mut app);
+    app.resize(60);
+    let rows = draw_sized(&mut app, 60, 16);
+    assert!(rows.iter().any(|row| row.contains("line 40")));
+
+    // Unwrapped, lines take the same rows at any width.
+    press(&mut app, KeyCode::Up);
+    app.resize(80);
+    assert_eq!(app.scroll, 1);
+    // Wrapped, the rows scrolled by no longer match.
+    press(&mut app, KeyCode::Char('w'));
+    press(&mut app, KeyCode::Up);
+    app.resize(80);
+    assert_eq!(app.scroll, 1);
+    app.resize(100);
+    assert_eq!(app.scroll, 0);
+}
+
+#[test]
+fn wrapping_fits_panes_narrower_than_the_line_numbers() {
+    let mut app = load("navigation.yaml");
+    next(&mut app);
+    next(&mut app);
+    press(&mut app, KeyCode::Char('l'));
+    press(&mut app, KeyCode::Char('w'));
+    for width in 1..12 {
+        draw_sized(&mut app, width, 16);
+    }
+}
+
+#[test]
+fn commands_that_cannot_run_report_why() {
+    let mut app = load("errors.yaml");
+    next(&mut app);
+    assert!(app.running);
+    let output = app.output();
+    assert!(output[1].contains(
+        "Failed to run command: Failed to execute '/nonexistent/program': \
+         No such file or directory"
+    ));
+
+    next(&mut app);
+    assert!(app.running);
+    let output = app.output();
+    assert!(output[2].starts_with("Invalid working directory."));
+    assert!(output[2].contains("No such directory: '/nonexistent/dir'"));
+    assert_eq!(app.action_status(), ActionStatus::Stopped);
+}
+
+#[test]
+fn transcripts_leave_out_the_styling() {
+    let path = env::temp_dir().join(format!("autopilot-{}-transcript.log", std::process::id()));
+    let mut app = load("transcript.yaml");
+    app.transcript = Some(Transcript::open(&path, TranscriptFormat::Text).unwrap());
+    next(&mut app);
+    // The output of an action is recorded on the tick after it is done.
+    app.tick().unwrap();
+    let transcript = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(transcript.contains("\nred and plain\n"), "{:?}", transcript);
+}
+
+#[test]
+fn only_commands_and_wait_for_set_the_last_status() {
+    let mut app = load("when.yaml");
+    for _ in 0..7 {
+        next(&mut app);
+    }
+    let output = app.output().join("\n");
+    assert!(output.contains("Failed before the wait"), "{}", output);
+    assert!(output.contains("Ready"), "{}", output);
+    assert!(output.contains("Timed out after"), "{}", output);
+    assert!(!output.contains("Still ready"), "{}", output);
+}
+
+#[test]
+fn attract_waits_for_the_action_and_restarts() {
+    let mut app = load("attract.yaml");
+    app.speed_multiplier = 1.0;
+    press(&mut app, KeyCode::Right);
+    // Typing the message is not idle time.
+    let idle = Instant::now();
+    while idle.elapsed() < Duration::from_millis(1200) {
+        app.tick().unwrap();
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(app.mode, Mode::Normal);
+    assert_eq!(app.action_status(), ActionStatus::Running);
+
+    // Once shown, any key starts over, stopping what is left to type.
+    app.mode = Mode::Attract;
+    press(&mut app, KeyCode::Char('x'));
+    wait_idle(&mut app);
+    app.tick().unwrap();
+    assert_eq!(app.mode, Mode::Normal);
+    assert_eq!(app.output(), ["### Attract ###"]);
+}
+
+#[test]
+fn redaction_masks_streamed_and_captured_output() {
+    let mut app = load("redact.yaml");
+    next(&mut app);
+    let output = app.output();
+    assert!(output[1].contains("\n****\n"), "{}", output[1]);
+    // Captured output is kept as it was shown.
+    next(&mut app);
+    next(&mut app);
+    assert!(
+        app.output()[3].contains("Captured ****"),
+        "{:?}",
+        app.output()
+    );
+
+    // The rules belong to the presentation, not to the process.
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/redact.yaml");
+    let mut config = Config::load_config(&path, &[], None).unwrap();
+    config.redactions.clear();
+    let mut app = App::new(config);
+    next(&mut app);
+    assert!(app.output()[1].contains("\ntoken-12345\n"));
+}
+
+#[test]
+fn going_back_restores_the_last_status() {
+    let mut app = load("back_when.yaml");
+    for _ in 0..3 {
+        next(&mut app);
+    }
+    press(&mut app, KeyCode::Left);
+    press(&mut app, KeyCode::Left);
+    assert_eq!(app.output().len(), 2);
+
+    // The message runs again after the failed command, not the last one.
+    next(&mut app);
+    let output = app.output();
+    assert!(output[2].contains("Failed as expected"), "{:?}", output);
+}
+
+/// Whether `program` is found in the `PATH`.
+fn in_path(program: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+#[test]
+fn bashisms_only_expand_under_bash() {
+    if !in_path("bash") {
+        eprintln!("bash is not installed, skipping");
+        return;
+    }
+    let mut app = load("shells.yaml");
+    next(&mut app);
+    assert!(app.output()[1].contains("\n1 2 3\n"), "{:?}", app.output());
+
+    // Bash running as sh still expands braces.
+    let sh = fs::canonicalize("/bin/sh").unwrap_or_default();
+    if sh.file_name().is_some_and(|name| name == "bash") {
+        return;
+    }
+    next(&mut app);
+    assert!(app.output()[2].contains("\n{1..3}\n"), "{:?}", app.output());
+}
+
+#[test]
+fn drawing_again_keeps_the_rendered_lines() {
+    let mut app = load("presentation.yaml");
+    next(&mut app);
+    next(&mut app);
+    let rendered = |app: &App| -> Vec<*const Line> {
+        app.buffer
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .map(|entry| entry.lines().as_ptr())
+            .collect()
+    };
+    draw(&mut app);
+    let first = rendered(&app);
+    draw(&mut app);
+    assert_eq!(rendered(&app), first);
+}